@@ -1,10 +1,275 @@
-use anyhow::Result;
-use frontend::Lexer;
+use anyhow::{Context, Result, bail};
+use frontend::{Lexer, Token};
+use ir::{IrModule, verify_ssa};
+use passes::{
+    ConstantFoldPass, ConstantPropagationPass, DeadCodeRemovalPass, DeadFunctionElimPass,
+    GVNPass, LICMPass, PassManager, PhiEliminationPass, RangePropagationPass, SCCPPass,
+    SSAConstructionPass, StrengthReductionPass, TransformPass, UnreachableBlockElimPass,
+};
+use riscv_backend::{MachineFunc, emit_data_section, emit_riscv, select_instructions};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+const USAGE: &str = concat!(
+    "usage: cli <input.py> [-o output.s] [--dump-ir] [--dump-ssa] [--dump-machine-ir]",
+    " [--time-passes]"
+);
+
+/// Command line options, the intermediate dumps all go to stderr
+#[derive(Debug, Default)]
+struct Options {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    dump_ir: bool,
+    dump_ssa: bool,
+    dump_machine_ir: bool,
+    time_passes: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut opts = Options::default();
+        let mut input = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => {
+                    let path = args.next().context("`-o` expects an output path")?;
+                    opts.output = Some(PathBuf::from(path));
+                }
+                "--dump-ir" => opts.dump_ir = true,
+                "--dump-ssa" => opts.dump_ssa = true,
+                "--dump-machine-ir" => opts.dump_machine_ir = true,
+                "--time-passes" => opts.time_passes = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                flag if flag.starts_with('-') => bail!("unknown option `{}`\n{}", flag, USAGE),
+                path => {
+                    if input.replace(PathBuf::from(path)).is_some() {
+                        bail!("only one input file is supported\n{}", USAGE);
+                    }
+                }
+            }
+        }
+
+        opts.input = input.context(USAGE)?;
+        Ok(opts)
+    }
+}
 
 fn main() -> Result<()> {
-    let mut lex = Lexer::new("def aa() -> = 1 + s_s");
-    let tokens = lex.scan_all();
+    let opts = Options::parse(std::env::args().skip(1))?;
+
+    let source = std::fs::read_to_string(&opts.input)
+        .with_context(|| format!("couldn't read {}", opts.input.display()))?;
+
+    let mut lex = Lexer::new(&source);
+    let tokens = lex.scan_all()?;
+    let module = build_ir(&tokens)?;
+
+    let mut dumps = io::stderr();
+    match &opts.output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("couldn't create {}", path.display()))?;
+            let mut out = BufWriter::new(file);
+            compile(module, &opts, &mut out, &mut dumps)?;
+            out.flush()?;
+        }
+        None => {
+            let stdout = io::stdout();
+            compile(module, &opts, &mut stdout.lock(), &mut dumps)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Takes `module` from plain IR to assembly on `out`, the dumps `opts` asks
+/// for are written to `dumps`
+fn compile<W: Write, D: Write>(
+    mut module: IrModule,
+    opts: &Options,
+    out: &mut W,
+    dumps: &mut D,
+) -> Result<()> {
+    if opts.dump_ir {
+        writeln!(dumps, "{:#?}", module)?;
+    }
+
+    // the pipeline puts the module in SSA form, the dump is of the optimized SSA
+    pipeline(opts).run(&mut module)?;
+    for func in &module.functions {
+        verify_ssa(func)?;
+    }
+    if opts.dump_ssa {
+        writeln!(dumps, "{:#?}", module)?;
+    }
+
+    // instruction selection has no way to lower a phi
+    for func in module.functions.iter_mut() {
+        PhiEliminationPass {}.run_on_function(func);
+    }
+
+    let machine_funcs: Vec<MachineFunc> =
+        module.functions.iter().map(select_instructions).collect();
+    if opts.dump_machine_ir {
+        writeln!(dumps, "{:#?}", machine_funcs)?;
+    }
+
+    // register allocation happens as part of the emission
+    emit_riscv(&machine_funcs, out)?;
+    emit_data_section(&module, out)?;
 
-    println!("{:#?}", tokens);
     Ok(())
 }
+
+/// The default optimization pipeline, takes the module into SSA form first
+fn pipeline(opts: &Options) -> PassManager {
+    let mut pm = PassManager::new();
+    pm.verbose = opts.time_passes;
+    pm.add_module_pass(DeadFunctionElimPass::default());
+    // blocks the entry can't reach are left out of SSA renaming
+    pm.add_pass(UnreachableBlockElimPass {});
+    pm.add_pass(SSAConstructionPass {});
+    pm.add_pass(ConstantPropagationPass {});
+    pm.add_pass(ConstantFoldPass {});
+    pm.add_pass(SCCPPass {});
+    pm.add_pass(GVNPass {});
+    pm.add_pass(LICMPass {});
+    pm.add_pass(RangePropagationPass {});
+    pm.add_pass(StrengthReductionPass {});
+    pm.add_pass(DeadCodeRemovalPass {});
+    // branches SCCP folded can leave blocks behind
+    pm.add_pass(UnreachableBlockElimPass {});
+    pm
+}
+
+// TODO: hook up once the parser lands, frontend/src/parser.rs is still empty
+fn build_ir(tokens: &[Token]) -> Result<IrModule> {
+    bail!(
+        "lexed {} tokens, but there is no parser to build the IR from them yet",
+        tokens.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::{IrFunction, IrInstruction};
+
+    fn parse(args: &[&str]) -> Options {
+        Options::parse(args.iter().map(|a| a.to_string())).unwrap()
+    }
+
+    fn main_module() -> IrModule {
+        let mut func = IrFunction::new("main");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Print {
+                values: vec!["7".to_string()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        let mut module = IrModule::default();
+        module.add_function(func);
+        module
+    }
+
+    #[test]
+    fn options_pick_up_every_flag() {
+        let opts = parse(&["in.py", "--dump-ir", "--time-passes", "-o", "out.s"]);
+        assert_eq!(opts.input, PathBuf::from("in.py"));
+        assert_eq!(opts.output, Some(PathBuf::from("out.s")));
+        assert!(opts.dump_ir && opts.time_passes);
+        assert!(!opts.dump_ssa && !opts.dump_machine_ir);
+
+        assert!(Options::parse(["--bogus".to_string()].into_iter()).is_err());
+        assert!(Options::parse(std::iter::empty()).is_err());
+    }
+
+    #[test]
+    fn pipeline_builds_ssa_before_optimizing() {
+        let mut pm = pipeline(&parse(&["in.py"]));
+        pm.schedule().unwrap();
+        let names = pm.pass_names();
+        assert_eq!(
+            names[..2],
+            ["UnreachableBlockElimPass", "SSAConstructionPass"]
+        );
+        // SCCP, GVN and LICM reuse it instead of adding their own
+        assert!(!names[2..].contains(&"SSAConstructionPass"));
+        for pass in ["SCCPPass", "GVNPass", "LICMPass"] {
+            assert!(names.contains(&pass));
+        }
+    }
+
+    #[test]
+    fn flags_reach_the_pipeline() {
+        assert!(pipeline(&parse(&["in.py", "--time-passes"])).verbose);
+        assert!(!pipeline(&parse(&["in.py"])).verbose);
+
+        let dumped = |args: &[&str]| {
+            let (mut out, mut dumps) = (Vec::new(), Vec::new());
+            compile(main_module(), &parse(args), &mut out, &mut dumps).unwrap();
+            assert!(String::from_utf8(out).unwrap().contains("\nmain:\n"));
+            String::from_utf8(dumps).unwrap()
+        };
+
+        assert!(dumped(&["in.py"]).is_empty());
+        assert!(dumped(&["in.py", "--dump-ir"]).contains("IrModule"));
+        assert!(dumped(&["in.py", "--dump-ssa"]).contains("IrModule"));
+        assert!(dumped(&["in.py", "--dump-machine-ir"]).contains("MachineFunc"));
+    }
+
+    #[test]
+    fn phis_are_gone_before_instruction_selection() {
+        // entry: br c left right
+        // left:  jmp join
+        // right: jmp join
+        // join:  x = phi(1, 2); print x; ret
+        let mut func = IrFunction::new("main");
+        func.args = vec![("c".into(), ir::IrType::Bool)];
+        for label in ["entry", "left", "right", "join"] {
+            func.add_block(label).unwrap();
+        }
+        func.add_edge(0, 1);
+        func.add_edge(0, 2);
+        func.add_edge(1, 3);
+        func.add_edge(2, 3);
+        func.blocks[0].instrs = vec![IrInstruction::Br {
+            cond: "c".into(),
+            then_lbl: "left".into(),
+            else_lbl: "right".into(),
+        }];
+        for b in [1, 2] {
+            func.blocks[b].instrs = vec![IrInstruction::Jmp {
+                label: "join".into(),
+            }];
+        }
+        func.blocks[3].instrs = vec![
+            IrInstruction::Phi {
+                dest: "x".into(),
+                sources: vec![(1, Some("1".into())), (2, Some("2".into()))],
+                ty: None,
+            },
+            IrInstruction::Print {
+                values: vec!["x".into()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+        let mut module = IrModule::default();
+        module.add_function(func);
+
+        let (mut out, mut dumps) = (Vec::new(), Vec::new());
+        let opts = parse(&["in.py", "--dump-ssa"]);
+        compile(module, &opts, &mut out, &mut dumps).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("\nmain:\n"));
+        // the SSA dump is taken before the phis are eliminated
+        assert!(String::from_utf8(dumps).unwrap().contains("Phi {"));
+    }
+}
@@ -0,0 +1,18 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use frontend::Lexer;
+
+/// 2 500 lines of `let total = step`, 10 000 tokens with a keyword and two
+/// identifiers on every line
+fn source() -> String {
+    "let total = step\n".repeat(2_500)
+}
+
+fn lex_10k_tokens(c: &mut Criterion) {
+    let src = source();
+    c.bench_function("lex 10k tokens", |b| {
+        b.iter(|| Lexer::new(black_box(&src)).scan_all().unwrap())
+    });
+}
+
+criterion_group!(benches, lex_10k_tokens);
+criterion_main!(benches);
@@ -1,7 +1,25 @@
 use log::error;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 use thiserror::Error;
 
+/// Built once, every identifier gets looked up in it
+static KEYWORDS: LazyLock<HashMap<&'static str, TokenType>> = LazyLock::new(|| {
+    HashMap::from([
+        ("def", TokenType::Def),
+        ("if", TokenType::If),
+        ("elseif", TokenType::ElseIf),
+        ("else", TokenType::Else),
+        ("let", TokenType::Let),
+        ("return", TokenType::Return),
+        ("type", TokenType::TypeRecord),
+        ("not", TokenType::Not),
+        ("with", TokenType::With),
+        ("match", TokenType::Match),
+        ("from", TokenType::From),
+    ])
+});
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     Def, // def func()...
@@ -58,22 +76,45 @@ pub enum TokenType {
     Eof,
 }
 
+/// Byte range of a token, along with the line and column it starts at
 #[derive(Debug, Clone, Default)]
 pub struct Span {
     start: usize,
     end: usize,
+    line: usize,
+    col: usize,
 }
 
 impl Span {
-    pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Lexer<'src> {
     input: &'src str,
-    span: Span,
     pos: usize,
     line: usize,
     col: usize,
@@ -85,6 +126,16 @@ pub struct Token {
     span: Span,
 }
 
+impl Token {
+    pub fn token_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum LexerError {
     #[error("Lexing failed due to one or more errors.")]
@@ -102,7 +153,6 @@ impl<'src> Lexer<'src> {
             col: 0,
             pos: 0,
             line: 1,
-            span: Span::default(),
         }
     }
 
@@ -110,6 +160,7 @@ impl<'src> Lexer<'src> {
         self.input[self.pos..].chars().next()
     }
 
+    #[allow(dead_code)]
     fn peek_next(&self) -> Option<char> {
         self.input[self.pos..].chars().nth(1)
     }
@@ -186,20 +237,6 @@ impl<'src> Lexer<'src> {
     }
 
     fn handle_identifier(&mut self) -> Result<TokenType> {
-        let keyword = HashMap::from([
-            ("def", TokenType::Def),
-            ("if", TokenType::If),
-            ("elseif", TokenType::ElseIf),
-            ("else", TokenType::Else),
-            ("let", TokenType::Let),
-            ("return", TokenType::Return),
-            ("type", TokenType::TypeRecord),
-            ("not", TokenType::Not),
-            ("with", TokenType::With),
-            ("match", TokenType::Match),
-            ("from", TokenType::From),
-        ]);
-
         let base = self.pos;
         while let Some(ch) = self.peek() {
             if ch.is_ascii_alphabetic() || ch == '_' {
@@ -211,29 +248,32 @@ impl<'src> Lexer<'src> {
 
         let id = &self.input[base..self.pos];
 
-        if let Some(token_type) = keyword.get(&id) {
+        if let Some(token_type) = KEYWORDS.get(id) {
             return Ok(token_type.to_owned());
         }
 
         Ok(TokenType::Identifier(id.to_string()))
     }
 
+    /// Consumes `strs` if the input continues with it. Goes through `advance`
+    /// so line and column keep up
     fn accept(&mut self, strs: &str) -> bool {
-        let end = self.pos + strs.len();
-
-        if end <= self.input.len() && self.input[self.pos..].starts_with(strs) {
-            self.pos += strs.len();
-            return true;
+        if !self.input[self.pos..].starts_with(strs) {
+            return false;
         }
 
-        false
+        for _ in strs.chars() {
+            self.advance();
+        }
+        true
     }
 
     fn accept_multichar(&mut self, strs: &str, token_type: TokenType) -> Option<Token> {
+        let (base, line, col) = (self.pos, self.line, self.col);
         if self.accept(strs) {
             return Some(Token {
                 token_type,
-                span: Span::new(self.pos, self.pos + strs.len()),
+                span: Span::new(base, self.pos, line, col),
             });
         }
         None
@@ -242,12 +282,12 @@ impl<'src> Lexer<'src> {
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace_and_comments();
 
-        let base = self.pos;
+        let (base, line, col) = (self.pos, self.line, self.col);
 
         if self.is_end() {
             return Token {
                 token_type: TokenType::Eof,
-                span: Span::new(base, self.pos),
+                span: Span::new(base, self.pos, line, col),
             };
         }
 
@@ -256,7 +296,7 @@ impl<'src> Lexer<'src> {
             let number = self.handle_number();
 
             return Token {
-                span: Span::new(base, self.pos),
+                span: Span::new(base, self.pos, line, col),
                 token_type: number,
             };
         }
@@ -265,7 +305,7 @@ impl<'src> Lexer<'src> {
             let id = self.handle_identifier();
 
             return Token {
-                span: Span::new(base, self.pos),
+                span: Span::new(base, self.pos, line, col),
                 token_type: id.unwrap(),
             };
         }
@@ -393,7 +433,7 @@ impl<'src> Lexer<'src> {
         // handle single chars
         Token {
             token_type: kind,
-            span: Span::new(base, self.pos),
+            span: Span::new(base, self.pos, line, col),
         }
     }
 
@@ -449,4 +489,52 @@ mod tests {
             "Expected: `Identifier`"
         );
     }
+
+    #[test]
+    fn test_leading_underscore_identifiers() {
+        let tokens: Vec<TokenType> = Lexer::new("_private_var __init _")
+            .scan_all()
+            .unwrap()
+            .into_iter()
+            .map(|tok| tok.token_type().clone())
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Identifier("_private_var".into()),
+                TokenType::Identifier("__init".into()),
+                TokenType::Identifier("_".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multichar_tokens_keep_columns() {
+        let mut lex = Lexer::new("a -> b\n  x == y");
+        let cols: Vec<(TokenType, usize, usize)> = lex
+            .scan_all()
+            .unwrap()
+            .into_iter()
+            .map(|tok| {
+                let span = tok.span();
+                (tok.token_type().clone(), span.line(), span.col())
+            })
+            .collect();
+
+        assert_eq!(
+            cols,
+            vec![
+                (TokenType::Identifier("a".into()), 1, 0),
+                (TokenType::Arrow, 1, 2),
+                (TokenType::Identifier("b".into()), 1, 5),
+                (TokenType::Identifier("x".into()), 2, 2),
+                (TokenType::EqEq, 2, 4),
+                (TokenType::Identifier("y".into()), 2, 7),
+            ]
+        );
+
+        let arrow = Lexer::new("a -> b").scan_all().unwrap()[1].clone();
+        assert_eq!((arrow.span().start(), arrow.span().end()), (2, 4));
+    }
 }
@@ -1,26 +1,139 @@
 use crate::BlockID;
+use crate::PerFunctionSSAData;
 use anyhow::Result;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CfgError {
+    #[error("Block label `{0}` is already used in this function")]
+    DuplicateLabel(String),
+    #[error("Jump to `{0}`, but no block has that label")]
+    UndefinedLabel(String),
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Int(i64),
     Bool(bool),
+    Str(String),
+    #[cfg(feature = "float")]
+    Float(f64),
 }
 
-#[derive(Debug, Clone)]
+/// Writes the literal the way it appears as an operand
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Int(i) => write!(f, "{}", i),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Str(s) => write!(f, "{:?}", s),
+            // Debug keeps the `.0` on whole numbers
+            #[cfg(feature = "float")]
+            Literal::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+/// Type of a value, `Void` is only meant for functions that return nothing
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IrType {
+    I64,
+    I32,
+    Bool,
+    Void,
+    Ptr(Box<IrType>),
+    #[cfg(feature = "float")]
+    F64,
+}
+
+/// Functions should go through `add_function`/`retain_functions`, which keep
+/// `func_to_idx` in sync with `functions`
+#[derive(Debug, Clone, Default)]
 pub struct IrModule {
     pub functions: Vec<IrFunction>,
+    pub globals: Vec<GlobalVar>,
+    pub func_to_idx: HashMap<String, usize>,
+}
+
+impl IrModule {
+    /// Adds `func`, replacing the function of the same name if there is one
+    pub fn add_function(&mut self, func: IrFunction) {
+        match self.func_to_idx.get(&func.name) {
+            Some(&idx) => self.functions[idx] = func,
+            None => {
+                self.func_to_idx
+                    .insert(func.name.clone(), self.functions.len());
+                self.functions.push(func);
+            }
+        }
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<&IrFunction> {
+        self.func_to_idx.get(name).map(|&idx| &self.functions[idx])
+    }
+
+    pub fn get_function_mut(&mut self, name: &str) -> Option<&mut IrFunction> {
+        self.func_to_idx
+            .get(name)
+            .map(|&idx| &mut self.functions[idx])
+    }
+
+    /// Keeps the functions `keep` returns true for, the order doesn't change
+    pub fn retain_functions(&mut self, keep: impl FnMut(&IrFunction) -> bool) {
+        self.functions.retain(keep);
+        self.func_to_idx = self
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(idx, f)| (f.name.clone(), idx))
+            .collect();
+    }
+}
+
+/// Two modules are equal when they define the same set of functions, see
+/// `IrFunction` for what makes two functions the same
+impl PartialEq for IrModule {
+    fn eq(&self, other: &Self) -> bool {
+        let functions: HashSet<&IrFunction> = self.functions.iter().collect();
+        functions == other.functions.iter().collect()
+    }
+}
+
+impl Eq for IrModule {}
+
+/// Module-level constant, emitted into `.rodata` by the backend
+#[derive(Debug, Clone)]
+pub struct GlobalVar {
+    pub name: String,
+    pub value: Literal,
 }
 
 #[derive(Debug, Clone)]
 pub struct IrFunction {
     pub name: String,
-    pub args: Vec<String>,
+    pub args: Vec<(String, IrType)>,
+    pub ret_ty: IrType,
     pub blocks: Vec<IrBasicBlock>,
     pub label_to_idx: HashMap<String, usize>,
 }
 
+/// A function is identified by its name, so it can key analysis results.
+/// The body isn't compared, an optimized function is still the same function
+impl PartialEq for IrFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for IrFunction {}
+
+impl std::hash::Hash for IrFunction {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct IrBasicBlock {
     pub label: String,
@@ -35,12 +148,19 @@ impl IrFunction {
         Self {
             name: func_name.to_string(),
             args: Vec::new(),
+            ret_ty: IrType::Void,
             blocks: Vec::new(),
             label_to_idx: HashMap::new(),
         }
     }
 
-    pub fn add_block(&mut self, label: &str) -> usize {
+    /// Adds an empty block and returns its index, labels have to be unique
+    /// within the function or the old block could no longer be found by name
+    pub fn add_block(&mut self, label: &str) -> std::result::Result<usize, CfgError> {
+        if self.label_to_idx.contains_key(label) {
+            return Err(CfgError::DuplicateLabel(label.to_string()));
+        }
+
         // current block we're on
         let idx = self.blocks.len();
 
@@ -56,10 +176,16 @@ impl IrFunction {
         self.label_to_idx.insert(label.to_string(), idx);
 
         // return index of newly added block index
-        idx
+        Ok(idx)
     }
 
+    /// Adds the edge `from -> to`, an edge that already exists is left alone so
+    /// phis don't end up with a source slot per duplicate
     pub fn add_edge(&mut self, from: usize, to: usize) {
+        if self.blocks[from].succs.contains(&to) {
+            return;
+        }
+
         self.blocks[from].succs.push(to);
         self.blocks[to].preds.push(from);
     }
@@ -71,6 +197,101 @@ impl IrFunction {
     pub fn block_index(&self, label: &String) -> Option<usize> {
         self.label_to_idx.get(label).copied()
     }
+
+    /// Block the function starts in, the one labelled `entry`, or block 0 when
+    /// no block has that label
+    pub fn entry_block(&self) -> BlockID {
+        self.label_to_idx.get("entry").copied().unwrap_or(0)
+    }
+
+    /// Reverse post-order of the blocks reachable from the entry, every block
+    /// shows up before its successors (ignoring back edges)
+    pub fn rpo(&self) -> Vec<BlockID> {
+        let mut order = Vec::with_capacity(self.blocks.len());
+        if self.blocks.is_empty() {
+            return order;
+        }
+
+        let entry = self.entry_block();
+        let mut visited = vec![false; self.blocks.len()];
+        // (block, index of the next successor to visit)
+        let mut stack: Vec<(BlockID, usize)> = vec![(entry, 0)];
+        visited[entry] = true;
+
+        while let Some((b, next)) = stack.pop() {
+            if let Some(&s) = self.blocks[b].succs.get(next) {
+                stack.push((b, next + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                order.push(b);
+            }
+        }
+
+        order.reverse();
+        order
+    }
+}
+
+impl IrBasicBlock {
+    /// Adds a phi after the phis already at the top of the block
+    pub fn push_phi(&mut self, phi: IrInstruction) {
+        let pos = self.phi_count();
+        self.instrs.insert(pos, phi);
+    }
+
+    /// Adds an instruction at the top of the block, right after its phis
+    pub fn prepend_instr(&mut self, instr: IrInstruction) {
+        let pos = self.phi_count();
+        self.instrs.insert(pos, instr);
+    }
+
+    fn phi_count(&self) -> usize {
+        self.instrs
+            .iter()
+            .take_while(|i| matches!(i, IrInstruction::Phi { .. }))
+            .count()
+    }
+
+    /// Points the edge coming in from `old` at `new`, phi sources included
+    pub fn replace_pred(&mut self, old: BlockID, new: BlockID) {
+        for p in self.preds.iter_mut().filter(|p| **p == old) {
+            *p = new;
+        }
+        for instr in self.instrs.iter_mut() {
+            if let IrInstruction::Phi { sources, .. } = instr {
+                for (p, _) in sources.iter_mut().filter(|(p, _)| *p == old) {
+                    *p = new;
+                }
+            }
+        }
+    }
+
+    /// Drops the edge coming in from `pred` along with its phi sources
+    pub fn remove_pred(&mut self, pred: BlockID) {
+        self.preds.retain(|&p| p != pred);
+        for instr in self.instrs.iter_mut() {
+            if let IrInstruction::Phi { sources, .. } = instr {
+                sources.retain(|(p, _)| *p != pred);
+            }
+        }
+    }
+
+    /// Adds an instruction at the end of the block, but before its terminator
+    pub fn push_instr(&mut self, instr: IrInstruction) {
+        let pos = match self.terminator() {
+            Some(_) => self.instrs.len() - 1,
+            None => self.instrs.len(),
+        };
+        self.instrs.insert(pos, instr);
+    }
+
+    /// The `Br`/`Jmp`/`Ret` ending the block, if it has one
+    pub fn terminator(&self) -> Option<&IrInstruction> {
+        self.instrs.last().filter(|i| i.is_terminator())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -78,24 +299,85 @@ pub enum IrInstruction {
     // == Arithematic ==
     Add {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Mul {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Sub {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Div {
         dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    // == Shifts ==
+    Shl {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    // arithmetic (signed) shift right
+    Shr {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    // logical (unsigned) shift right
+    UShr {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    // == Floating point ==
+    #[cfg(feature = "float")]
+    FAdd {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    #[cfg(feature = "float")]
+    FSub {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    #[cfg(feature = "float")]
+    FMul {
+        dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    #[cfg(feature = "float")]
+    FDiv {
+        dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
@@ -103,30 +385,35 @@ pub enum IrInstruction {
     // == Comparsion ==
     Eq {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Lt {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Gt {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Ge {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     Le {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
@@ -134,17 +421,27 @@ pub enum IrInstruction {
     // == Logical Operator ==
     Not {
         dest: String,
+        ty: Option<IrType>,
         args: String,
     },
 
     Or {
         dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
 
     And {
         dest: String,
+        ty: Option<IrType>,
+        lhs: String,
+        rhs: String,
+    },
+
+    Xor {
+        dest: String,
+        ty: Option<IrType>,
         lhs: String,
         rhs: String,
     },
@@ -153,7 +450,8 @@ pub enum IrInstruction {
     Call {
         target_func: String,
         args: Vec<String>,
-        dest: Option<String>,
+        dest: CallDest,
+        ty: Option<IrType>,
     },
 
     Br {
@@ -171,13 +469,25 @@ pub enum IrInstruction {
     },
 
     Phi {
-        dest: String,                 // value the be dictated by previous values
-        sources: Vec<Option<String>>, // this will store the blocks id of preds for blocks
+        dest: String, // value the be dictated by previous values
+        // (pred the value flows in from, value), `None` until renaming fills it in
+        sources: Vec<(BlockID, Option<String>)>,
+        ty: Option<IrType>,
+    },
+
+    // dest = cond ? true_val : false_val, without branching
+    Select {
+        dest: String,
+        cond: String,
+        true_val: String,
+        false_val: String,
+        ty: Option<IrType>,
     },
 
     // == Literals ==
     Const {
         dest: String,
+        ty: Option<IrType>,
         value: Literal,
     },
 
@@ -188,11 +498,77 @@ pub enum IrInstruction {
 
     Assign {
         lhs: String,
-        rhs: String,
+        rhs: AssignRhs,
+        ty: Option<IrType>,
     },
 }
 
+/// What an `Assign` copies, a variable or a literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignRhs {
+    Var(String),
+    Const(Literal),
+}
+
+impl AssignRhs {
+    /// Operands are plain strings everywhere else, the ones that parse as a
+    /// literal become a `Const`
+    pub fn from_operand(operand: &str) -> Self {
+        if let Ok(i) = operand.parse::<i64>() {
+            AssignRhs::Const(Literal::Int(i))
+        } else if let Ok(b) = operand.parse::<bool>() {
+            AssignRhs::Const(Literal::Bool(b))
+        } else {
+            AssignRhs::Var(operand.to_string())
+        }
+    }
+}
+
+/// Where the result of a `Call` goes. A `Void` call has no result, so there
+/// is no name anything could read it through
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallDest {
+    Void,
+    Named(String),
+}
+
+impl CallDest {
+    pub fn name(&self) -> Option<&String> {
+        match self {
+            CallDest::Void => None,
+            CallDest::Named(name) => Some(name),
+        }
+    }
+
+    pub fn name_mut(&mut self) -> Option<&mut String> {
+        match self {
+            CallDest::Void => None,
+            CallDest::Named(name) => Some(name),
+        }
+    }
+}
+
 impl IrInstruction {
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            IrInstruction::Br { .. } | IrInstruction::Jmp { .. } | IrInstruction::Ret { .. }
+        )
+    }
+
+    /// Does something besides defining its dest (output, control flow, or
+    /// whatever the callee does), so it has to stay even if nothing reads it
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            IrInstruction::Print { .. }
+                | IrInstruction::Call { .. }
+                | IrInstruction::Ret { .. }
+                | IrInstruction::Br { .. }
+                | IrInstruction::Jmp { .. }
+        )
+    }
+
     // Returns a slice of a defined variable
     // describes what name does this instruction *write*
     pub fn defs(&self) -> &[String] {
@@ -201,6 +577,9 @@ impl IrInstruction {
             | IrInstruction::Sub { dest, .. }
             | IrInstruction::Mul { dest, .. }
             | IrInstruction::Div { dest, .. }
+            | IrInstruction::Shl { dest, .. }
+            | IrInstruction::Shr { dest, .. }
+            | IrInstruction::UShr { dest, .. }
             | IrInstruction::Eq { dest, .. }
             | IrInstruction::Lt { dest, .. }
             | IrInstruction::Gt { dest, .. }
@@ -208,25 +587,98 @@ impl IrInstruction {
             | IrInstruction::Ge { dest, .. }
             | IrInstruction::Or { dest, .. }
             | IrInstruction::And { dest, .. }
+            | IrInstruction::Xor { dest, .. }
             | IrInstruction::Not { dest, .. }
             | IrInstruction::Const { dest, .. }
             // TODO: Maybe we should remove the assign?
             // Find something else to use
             | IrInstruction::Assign { lhs: dest, .. }
-            | IrInstruction::Phi { dest, .. } => std::slice::from_ref(dest),
+            | IrInstruction::Phi { dest, .. }
+            | IrInstruction::Select { dest, .. } => std::slice::from_ref(dest),
 
-            IrInstruction::Call { dest, .. } => {
-                if let Some(d) = dest {
-                    std::slice::from_ref(d)
-                } else {
-                    &[]
-                }
-            },
+            #[cfg(feature = "float")]
+            IrInstruction::FAdd { dest, .. }
+            | IrInstruction::FSub { dest, .. }
+            | IrInstruction::FMul { dest, .. }
+            | IrInstruction::FDiv { dest, .. } => std::slice::from_ref(dest),
+
+            IrInstruction::Call {
+                dest: CallDest::Named(dest),
+                ..
+            } => std::slice::from_ref(dest),
 
             _ => &[],
         }
     }
 
+    /// Same as `defs`, along with the type of each name. Untyped dests fall
+    /// back to what the opcode produces: comparisons and `Not` give a `Bool`,
+    /// constants the type of their literal, anything else an `I64`
+    pub fn defs_typed(&self) -> Vec<(String, IrType)> {
+        let ty = match self {
+            IrInstruction::Add { ty, .. }
+            | IrInstruction::Sub { ty, .. }
+            | IrInstruction::Mul { ty, .. }
+            | IrInstruction::Div { ty, .. }
+            | IrInstruction::Shl { ty, .. }
+            | IrInstruction::Shr { ty, .. }
+            | IrInstruction::UShr { ty, .. }
+            | IrInstruction::Eq { ty, .. }
+            | IrInstruction::Lt { ty, .. }
+            | IrInstruction::Gt { ty, .. }
+            | IrInstruction::Le { ty, .. }
+            | IrInstruction::Ge { ty, .. }
+            | IrInstruction::Or { ty, .. }
+            | IrInstruction::And { ty, .. }
+            | IrInstruction::Xor { ty, .. }
+            | IrInstruction::Not { ty, .. }
+            | IrInstruction::Const { ty, .. }
+            | IrInstruction::Assign { ty, .. }
+            | IrInstruction::Phi { ty, .. }
+            | IrInstruction::Select { ty, .. }
+            | IrInstruction::Call { ty, .. } => ty.clone(),
+            #[cfg(feature = "float")]
+            IrInstruction::FAdd { ty, .. }
+            | IrInstruction::FSub { ty, .. }
+            | IrInstruction::FMul { ty, .. }
+            | IrInstruction::FDiv { ty, .. } => ty.clone(),
+            _ => None,
+        };
+
+        let fallback = match self {
+            IrInstruction::Eq { .. }
+            | IrInstruction::Lt { .. }
+            | IrInstruction::Gt { .. }
+            | IrInstruction::Le { .. }
+            | IrInstruction::Ge { .. }
+            | IrInstruction::Not { .. } => IrType::Bool,
+            IrInstruction::Const {
+                value: Literal::Bool(_),
+                ..
+            }
+            | IrInstruction::Assign {
+                rhs: AssignRhs::Const(Literal::Bool(_)),
+                ..
+            } => IrType::Bool,
+            #[cfg(feature = "float")]
+            IrInstruction::FAdd { .. }
+            | IrInstruction::FSub { .. }
+            | IrInstruction::FMul { .. }
+            | IrInstruction::FDiv { .. }
+            | IrInstruction::Const {
+                value: Literal::Float(_),
+                ..
+            } => IrType::F64,
+            _ => IrType::I64,
+        };
+        let ty = ty.unwrap_or(fallback);
+
+        self.defs()
+            .iter()
+            .map(|d| (d.clone(), ty.clone()))
+            .collect()
+    }
+
     // describes what name does this instruction *reads*
     pub fn uses(&self) -> Vec<String> {
         match self {
@@ -234,25 +686,105 @@ impl IrInstruction {
             | IrInstruction::Sub { lhs, rhs, .. }
             | IrInstruction::Mul { lhs, rhs, .. }
             | IrInstruction::Div { lhs, rhs, .. }
+            | IrInstruction::Shl { lhs, rhs, .. }
+            | IrInstruction::Shr { lhs, rhs, .. }
+            | IrInstruction::UShr { lhs, rhs, .. }
             | IrInstruction::Eq { lhs, rhs, .. }
             | IrInstruction::Lt { lhs, rhs, .. }
             | IrInstruction::Gt { lhs, rhs, .. }
             | IrInstruction::Ge { lhs, rhs, .. }
             | IrInstruction::Le { lhs, rhs, .. }
             | IrInstruction::Or { lhs, rhs, .. }
-            | IrInstruction::And { lhs, rhs, .. } => vec![lhs.to_string(), rhs.to_string()],
+            | IrInstruction::And { lhs, rhs, .. }
+            | IrInstruction::Xor { lhs, rhs, .. } => vec![lhs.to_string(), rhs.to_string()],
+
+            #[cfg(feature = "float")]
+            IrInstruction::FAdd { lhs, rhs, .. }
+            | IrInstruction::FSub { lhs, rhs, .. }
+            | IrInstruction::FMul { lhs, rhs, .. }
+            | IrInstruction::FDiv { lhs, rhs, .. } => vec![lhs.to_string(), rhs.to_string()],
 
             IrInstruction::Not { args, .. } => vec![args.to_string()],
+            IrInstruction::Assign {
+                rhs: AssignRhs::Var(var),
+                ..
+            } => vec![var.to_string()],
 
             IrInstruction::Br { cond, .. } => vec![cond.to_string()],
             IrInstruction::Call { args, .. } => args.to_vec(),
             IrInstruction::Ret { args, .. } => args.to_vec(),
-            IrInstruction::Phi { sources, .. } => sources.iter().flatten().cloned().collect(),
+            IrInstruction::Phi { sources, .. } => {
+                sources.iter().filter_map(|(_, src)| src.clone()).collect()
+            }
+            IrInstruction::Select {
+                cond,
+                true_val,
+                false_val,
+                ..
+            } => vec![
+                cond.to_string(),
+                true_val.to_string(),
+                false_val.to_string(),
+            ],
 
             IrInstruction::Print { values, .. } => values.to_vec(),
             _ => Vec::new(),
         }
     }
+
+    /// Every name the instruction reads or writes, for renaming in place.
+    /// Labels and call targets aren't names, so they're left out
+    pub fn names_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            IrInstruction::Add { dest, lhs, rhs, .. }
+            | IrInstruction::Sub { dest, lhs, rhs, .. }
+            | IrInstruction::Mul { dest, lhs, rhs, .. }
+            | IrInstruction::Div { dest, lhs, rhs, .. }
+            | IrInstruction::Shl { dest, lhs, rhs, .. }
+            | IrInstruction::Shr { dest, lhs, rhs, .. }
+            | IrInstruction::UShr { dest, lhs, rhs, .. }
+            | IrInstruction::Eq { dest, lhs, rhs, .. }
+            | IrInstruction::Lt { dest, lhs, rhs, .. }
+            | IrInstruction::Gt { dest, lhs, rhs, .. }
+            | IrInstruction::Ge { dest, lhs, rhs, .. }
+            | IrInstruction::Le { dest, lhs, rhs, .. }
+            | IrInstruction::Or { dest, lhs, rhs, .. }
+            | IrInstruction::And { dest, lhs, rhs, .. }
+            | IrInstruction::Xor { dest, lhs, rhs, .. } => vec![dest, lhs, rhs],
+
+            #[cfg(feature = "float")]
+            IrInstruction::FAdd { dest, lhs, rhs, .. }
+            | IrInstruction::FSub { dest, lhs, rhs, .. }
+            | IrInstruction::FMul { dest, lhs, rhs, .. }
+            | IrInstruction::FDiv { dest, lhs, rhs, .. } => vec![dest, lhs, rhs],
+
+            IrInstruction::Not { dest, args, .. } => vec![dest, args],
+            IrInstruction::Assign { lhs, rhs, .. } => match rhs {
+                AssignRhs::Var(var) => vec![lhs, var],
+                AssignRhs::Const(_) => vec![lhs],
+            },
+            IrInstruction::Const { dest, .. } => vec![dest],
+
+            IrInstruction::Call { args, dest, .. } => {
+                args.iter_mut().chain(dest.name_mut()).collect()
+            }
+            IrInstruction::Br { cond, .. } => vec![cond],
+            IrInstruction::Jmp { .. } => Vec::new(),
+            IrInstruction::Ret { args } => args.iter_mut().collect(),
+            IrInstruction::Phi { dest, sources, .. } => std::iter::once(dest)
+                .chain(sources.iter_mut().filter_map(|(_, src)| src.as_mut()))
+                .collect(),
+            IrInstruction::Select {
+                dest,
+                cond,
+                true_val,
+                false_val,
+                ..
+            } => vec![dest, cond, true_val, false_val],
+
+            IrInstruction::Print { values } => values.iter_mut().collect(),
+        }
+    }
 }
 
 /// For getting the mapping of each variable block(s) where variable might be defined
@@ -270,12 +802,135 @@ pub fn collect_defs(func: &IrFunction) -> HashMap<String, Vec<BlockID>> {
     defs_map
 }
 
+/// A natural loop, found from one or more back edges into the same header
+#[derive(Debug, Clone)]
+pub struct NaturalLoop {
+    pub header: BlockID,
+    pub back_edge_src: BlockID,
+    pub body: Vec<BlockID>,
+}
+
+/// Finds every natural loop in `func`, `ssa` must hold the dominators of `func`
+///
+/// A back edge is an edge A -> B where B dominates A. The body of the loop is
+/// everything that can reach A without going through B. Back edges that share
+/// a header get merged into the same loop.
+pub fn find_natural_loops(func: &IrFunction, ssa: &PerFunctionSSAData) -> Vec<NaturalLoop> {
+    let mut loops: Vec<NaturalLoop> = Vec::new();
+
+    for (src, block) in func.blocks.iter().enumerate() {
+        for &header in &block.succs {
+            if !ssa.dominates(header, src) {
+                continue;
+            }
+
+            // backward DFS from the back-edge source, stopping at the header
+            let mut body: HashSet<BlockID> = HashSet::from([header]);
+            let mut stack = vec![src];
+            while let Some(b) = stack.pop() {
+                if body.insert(b) {
+                    stack.extend(func.blocks[b].preds.iter().copied());
+                }
+            }
+
+            match loops.iter_mut().find(|l| l.header == header) {
+                Some(existing) => {
+                    for b in body {
+                        if !existing.body.contains(&b) {
+                            existing.body.push(b);
+                        }
+                    }
+                    existing.body.sort();
+                }
+                None => {
+                    let mut body: Vec<BlockID> = body.into_iter().collect();
+                    body.sort();
+                    loops.push(NaturalLoop {
+                        header,
+                        back_edge_src: src,
+                        body,
+                    });
+                }
+            }
+        }
+    }
+
+    loops
+}
+
+/// Puts an empty block on every critical edge, an edge from a block with
+/// several successors into a block with several predecessors. Copies meant
+/// for only that edge (phi moves, reloads) then have a block of their own.
+///
+/// The new block just jumps on to the old target, whose phis now read their
+/// value from the new block instead.
+pub fn split_critical_edges(func: &mut IrFunction) {
+    let mut critical: Vec<(BlockID, BlockID)> = Vec::new();
+    for (from, block) in func.blocks.iter().enumerate() {
+        if block.succs.len() < 2 {
+            continue;
+        }
+        for &to in &block.succs {
+            if func.blocks[to].preds.len() > 1 {
+                critical.push((from, to));
+            }
+        }
+    }
+
+    for (from, to) in critical {
+        let to_lbl = func.blocks[to].label.clone();
+        let base = format!("{}.{}.split", func.blocks[from].label, to_lbl);
+        let mut label = base.clone();
+        let mut n = 0;
+        let mid = loop {
+            match func.add_block(&label) {
+                Ok(idx) => break idx,
+                Err(_) => {
+                    n += 1;
+                    label = format!("{}{}", base, n);
+                }
+            }
+        };
+
+        func.blocks[mid].instrs.push(IrInstruction::Jmp {
+            label: to_lbl.clone(),
+        });
+        func.blocks[mid].preds.push(from);
+        func.blocks[mid].succs.push(to);
+
+        for s in func.blocks[from].succs.iter_mut() {
+            if *s == to {
+                *s = mid;
+            }
+        }
+        match func.blocks[from].instrs.last_mut() {
+            Some(IrInstruction::Br {
+                then_lbl, else_lbl, ..
+            }) => {
+                for target in [then_lbl, else_lbl] {
+                    if *target == to_lbl {
+                        *target = label.clone();
+                    }
+                }
+            }
+            Some(IrInstruction::Jmp { label: target }) if *target == to_lbl => {
+                *target = label.clone();
+            }
+            _ => {}
+        }
+
+        func.blocks[to].replace_pred(from, mid);
+    }
+}
+
 //TODO: Need to fix this for working with our frontend
+#[allow(dead_code)]
 struct TmpTodo {}
 
 /// Converting Flat Functions into CFG
-fn convert_to_cfg(func: &TmpTodo) -> Result<IrFunction> {
-    let mut ir_func = IrFunction::new(&"todo");
+#[allow(dead_code)]
+fn convert_to_cfg(_func: &TmpTodo) -> Result<IrFunction> {
+    let mut ir_func = IrFunction::new("todo");
     split_into_blocks(&mut ir_func)?;
 
     wire_block_edges(&mut ir_func)?;
@@ -285,35 +940,39 @@ fn convert_to_cfg(func: &TmpTodo) -> Result<IrFunction> {
 
 /// This functions deals with converting the IR into true
 /// Control-Flow Graphs by wiring up the blocks
-fn wire_block_edges(func: &mut IrFunction) -> Result<()> {
+#[allow(dead_code)]
+pub(crate) fn wire_block_edges(func: &mut IrFunction) -> std::result::Result<(), CfgError> {
     // Build up the list of Successors & Predecessors fork
     for curr_block_idx in 0..func.blocks.len() {
-        if let Some(terminator) = func.blocks[curr_block_idx].instrs.last() {
-            match terminator {
-                IrInstruction::Br {
-                    then_lbl, else_lbl, ..
-                } => {
-                    let then_idx = func.block_index(then_lbl).unwrap();
-                    let else_idx = func.block_index(else_lbl).unwrap();
-
-                    func.add_edge(curr_block_idx, then_idx);
-                    func.add_edge(curr_block_idx, else_idx);
-                }
+        match func.blocks[curr_block_idx].terminator() {
+            Some(IrInstruction::Br {
+                then_lbl, else_lbl, ..
+            }) => {
+                let then_idx = func
+                    .block_index(then_lbl)
+                    .ok_or_else(|| CfgError::UndefinedLabel(then_lbl.clone()))?;
+                let else_idx = func
+                    .block_index(else_lbl)
+                    .ok_or_else(|| CfgError::UndefinedLabel(else_lbl.clone()))?;
+
+                func.add_edge(curr_block_idx, then_idx);
+                func.add_edge(curr_block_idx, else_idx);
+            }
 
-                IrInstruction::Jmp { label } => {
-                    let target_idx = func.block_index(label).unwrap();
-                    func.add_edge(curr_block_idx, target_idx);
-                }
+            Some(IrInstruction::Jmp { label }) => {
+                let target_idx = func
+                    .block_index(label)
+                    .ok_or_else(|| CfgError::UndefinedLabel(label.clone()))?;
+                func.add_edge(curr_block_idx, target_idx);
+            }
 
-                // TODO: I think I'll need to manage this later on?
-                IrInstruction::Ret { .. } => {}
+            // TODO: I think I'll need to manage this later on?
+            Some(_) => {}
 
-                // Fall through the next label, if needed so
-                _ => {
-                    // check to see if we're still within the range of the blocks list
-                    if curr_block_idx + 1 < func.blocks.len() - 1 {
-                        func.add_edge(curr_block_idx, curr_block_idx + 1);
-                    }
+            // No terminator, fall through to the next block if there is one
+            None => {
+                if curr_block_idx + 1 < func.blocks.len() {
+                    func.add_edge(curr_block_idx, curr_block_idx + 1);
                 }
             }
         }
@@ -323,6 +982,7 @@ fn wire_block_edges(func: &mut IrFunction) -> Result<()> {
 }
 
 // TODO: Need to finish this
-fn split_into_blocks(func: &mut IrFunction) -> Result<()> {
+#[allow(dead_code)]
+fn split_into_blocks(_func: &mut IrFunction) -> Result<()> {
     todo!();
 }
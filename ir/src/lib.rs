@@ -1,14 +1,27 @@
 pub mod cfg;
 pub mod ssa;
+pub use cfg::AssignRhs;
+pub use cfg::CallDest;
+pub use cfg::CfgError;
+pub use cfg::GlobalVar;
 pub use cfg::IrBasicBlock;
 pub use cfg::IrFunction;
 pub use cfg::IrInstruction;
 pub use cfg::IrModule;
+pub use cfg::IrType;
+pub use cfg::NaturalLoop;
+pub use cfg::find_natural_loops;
+pub use cfg::split_critical_edges;
+pub use ssa::PerFunctionSSAData;
 pub use ssa::SSAFormation;
+pub use ssa::iterated_df;
+pub use ssa::to_lcssa;
+pub use ssa::verify_ssa;
 
 /// Help with having more readable code
 pub type BlockID = usize;
 
+#[cfg(test)]
 macro_rules! function {
     () => {{
         fn f() {}
@@ -27,6 +40,7 @@ mod tests {
     use crate::cfg::{collect_defs, IrBasicBlock};
 
     use super::*;
+    use std::collections::{BTreeSet, HashMap};
 
     /// Build the 5-block “diamond” CFG:
     ///
@@ -40,38 +54,18 @@ mod tests {
     ///      │
     ///      5
     fn diamond_cfg() -> IrFunction {
-        let block_labels = ["entry", "A", "B", "C", "D", "Exit"];
-
-        let preds = vec![
-            Vec::new(), // 0: entry
-            vec![0],    // 1: A
-            vec![1],    // 2: B
-            vec![1],    // 3: C
-            vec![2, 3], // 4: D (preds are 2 & 3)
-            vec![4],    // 5: exit
-        ];
-
-        let mut blocks = Vec::new();
-        for (i, &label) in block_labels.iter().enumerate() {
-            blocks.push(IrBasicBlock {
-                label: label.to_string(),
-                instrs: Vec::new(),
-                preds: preds[i].clone(),
-                succs: Vec::new(),
-            });
+        let mut func = IrFunction::new("diamond");
+        for label in ["entry", "A", "B", "C", "D", "Exit"] {
+            func.add_block(label).unwrap();
         }
 
-        let mut label_to_idx = std::collections::HashMap::new();
-        for (i, &label) in block_labels.iter().enumerate() {
-            label_to_idx.insert(label.to_string(), i);
-        }
-
-        IrFunction {
-            name: "diamond".to_string(),
-            args: Vec::new(),
-            blocks,
-            label_to_idx,
-        }
+        func.add_edge(0, 1);
+        func.add_edge(1, 2);
+        func.add_edge(1, 3);
+        func.add_edge(2, 4); // D's preds are 2 & 3
+        func.add_edge(3, 4);
+        func.add_edge(4, 5);
+        func
     }
 
     #[test]
@@ -79,7 +73,8 @@ mod tests {
         let func = diamond_cfg();
 
         let mut temp_funcs = vec![func];
-        let mut ssa = SSAFormation::new(&mut temp_funcs).unwrap();
+        let ssa = SSAFormation::new(&mut temp_funcs).unwrap();
+        let mut ssa = ssa.function(&temp_funcs[0].name).unwrap().clone();
 
         // IDOM Compute
         ssa.compute_idom(&temp_funcs[0]).unwrap();
@@ -95,18 +90,71 @@ mod tests {
         ssa.compute_df(&temp_funcs[0]).unwrap();
         let df = &ssa.dom_frontier;
         println!("  DomFrontier: {:?}", &df);
-        assert_eq!(df.get(&2).unwrap().clone(), vec![4]);
-        assert_eq!(df.get(&3).unwrap().clone(), vec![4]);
+        assert_eq!(df[&2], BTreeSet::from([4]));
+        assert_eq!(df[&3], BTreeSet::from([4]));
 
         ssa.build_dom_tree().unwrap();
 
         let dt = &ssa.dom_tree;
         println!("  DomTree: {:?}", dt);
-        assert_eq!(dt.get(&4).unwrap().clone(), vec![5]);
-        let mut kids = dt.get(&1).unwrap().clone();
-        kids.sort();
+        assert_eq!(dt[&4], BTreeSet::from([5]));
+        assert_eq!(dt[&1], BTreeSet::from([2, 3, 4]));
+    }
+
+    #[test]
+    fn test_unreachable_block_has_no_idom() {
+        let mut func = diamond_cfg();
+        let orphan = func.add_block("orphan").unwrap();
+        func.add_edge(orphan, 4);
+        let mut ssa = PerFunctionSSAData::default();
+
+        println!("Test Function: {}", function!());
+        ssa.compute_idom(&func).unwrap();
+        ssa.compute_df(&func).unwrap();
+        println!("  Idom: {:?}", ssa.idom);
+        assert!(!ssa.idom.contains_key(&orphan));
+        assert_eq!(ssa.idom[&4], 1);
+        assert!(!ssa.dom_frontier.contains_key(&orphan));
+    }
+
+    #[test]
+    fn test_dominates_on_diamond() {
+        let func = diamond_cfg();
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.compute_idom(&func).unwrap();
+
+        println!("Test Function: {}", function!());
+        assert!(ssa.dominates(1, 4));
+        assert!(ssa.dominates(0, 5));
+        assert!(ssa.dominates(3, 3));
+        assert!(!ssa.dominates(2, 3));
+        assert!(!ssa.dominates(2, 4));
+        assert!(!ssa.dominates(4, 1));
+    }
+
+    #[test]
+    fn test_post_idom_and_post_dom_tree_on_diamond() {
+        let func = diamond_cfg();
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.build_post_dom_tree(&func).unwrap();
+
+        // 6 is the virtual exit
+        let pdom = &ssa.post_idom;
+        println!("Test Function: {}", function!());
+        println!("  PostIdom: {:?}", pdom);
+        assert_eq!(pdom[&6], 6);
+        assert_eq!(pdom[&5], 6);
+        assert_eq!(pdom[&4], 5);
+        assert_eq!(pdom[&3], 4);
+        assert_eq!(pdom[&2], 4);
+        assert_eq!(pdom[&1], 4);
+        assert_eq!(pdom[&0], 1);
 
-        assert_eq!(kids, vec![2, 3, 4]);
+        let pdt = &ssa.post_dom_tree;
+        println!("  PostDomTree: {:?}", pdt);
+        assert_eq!(pdt[&4], BTreeSet::from([1, 2, 3]));
+        assert_eq!(pdt[&1], BTreeSet::from([0]));
+        assert_eq!(pdt[&6], BTreeSet::from([5]));
     }
 
     /// Helper function for creating multiple definitions for further testing
@@ -116,12 +164,14 @@ mod tests {
         // by block D (maybe)
         let def_x_b = IrInstruction::Assign {
             lhs: "x".to_string(),
-            rhs: "5".to_string(),
+            rhs: AssignRhs::Const(cfg::Literal::Int(5)),
+            ty: None,
         };
 
         let def_x_c = IrInstruction::Assign {
             lhs: "x".to_string(),
-            rhs: "10".to_string(),
+            rhs: AssignRhs::Const(cfg::Literal::Int(10)),
+            ty: None,
         };
 
         // index 2 is block B
@@ -162,7 +212,7 @@ mod tests {
         create_def_sites(&mut func).unwrap();
         let defs_map = collect_defs(&func);
         let mut temp_funcs = vec![func];
-        let ssa = SSAFormation::new(&mut temp_funcs).unwrap();
+        let _ssa = SSAFormation::new(&mut temp_funcs).unwrap();
 
         println!("Test Function: {}", function!());
         //let x_defintion_sites = defs_map.get("x").unwrap();
@@ -175,4 +225,661 @@ mod tests {
             }
         }
     }
+
+    /// Build a CFG with a loop that has two back edges into the same header:
+    ///
+    ///      0
+    ///      │
+    ///      1 <──┐
+    ///     / \   │
+    ///    4   2 ─┤
+    ///        │  │
+    ///        3 ─┘
+    fn loop_cfg() -> IrFunction {
+        let mut func = IrFunction::new("looping");
+        for label in ["entry", "header", "body", "latch", "exit"] {
+            func.add_block(label).unwrap();
+        }
+
+        func.add_edge(0, 1);
+        func.add_edge(1, 2);
+        func.add_edge(1, 4);
+        func.add_edge(2, 3);
+        func.add_edge(2, 1);
+        func.add_edge(3, 1);
+        func
+    }
+
+    #[test]
+    fn test_find_natural_loops_merges_back_edges() {
+        let func = loop_cfg();
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.compute_idom(&func).unwrap();
+
+        let loops = find_natural_loops(&func, &ssa);
+        println!("Test Function: {}", function!());
+        println!("  Loops: {:?}", loops);
+
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].header, 1);
+        assert_eq!(loops[0].body, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_add_block_rejects_duplicate_labels() {
+        let mut func = IrFunction::new("dup_label");
+        let entry = func.add_block("entry").unwrap();
+
+        println!("Test Function: {}", function!());
+        assert_eq!(
+            func.add_block("entry"),
+            Err(CfgError::DuplicateLabel("entry".to_string()))
+        );
+        // the first block keeps its name and nothing was added
+        assert_eq!(func.blocks.len(), 1);
+        assert_eq!(func.block_index(&"entry".to_string()), Some(entry));
+    }
+
+    #[test]
+    fn test_wiring_a_jump_to_a_missing_label_fails() {
+        let mut func = IrFunction::new("typo");
+        let entry = func.add_block("entry").unwrap();
+        let exit = func.add_block("exit").unwrap();
+        func.append_instr(
+            entry,
+            &IrInstruction::Br {
+                cond: "c".to_string(),
+                then_lbl: "exit".to_string(),
+                else_lbl: "exti".to_string(),
+            },
+        );
+        func.append_instr(exit, &IrInstruction::Ret { args: Vec::new() });
+
+        println!("Test Function: {}", function!());
+        assert_eq!(
+            cfg::wire_block_edges(&mut func),
+            Err(CfgError::UndefinedLabel("exti".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_wiring_falls_through_blocks_without_a_terminator() {
+        let mut func = IrFunction::new("fallthrough");
+        let entry = func.add_block("entry").unwrap();
+        let body = func.add_block("body").unwrap();
+        let exit = func.add_block("exit").unwrap();
+        func.append_instr(
+            entry,
+            &IrInstruction::Print {
+                values: vec!["1".to_string()],
+            },
+        );
+        func.append_instr(exit, &IrInstruction::Ret { args: Vec::new() });
+
+        println!("Test Function: {}", function!());
+        assert_eq!(cfg::wire_block_edges(&mut func), Ok(()));
+        assert_eq!(func.blocks[entry].succs, vec![body]);
+        assert_eq!(func.blocks[body].succs, vec![exit]);
+        assert!(func.blocks[exit].succs.is_empty());
+    }
+
+    #[test]
+    fn test_add_edge_ignores_duplicates() {
+        let mut func = IrFunction::new("dup");
+        func.add_block("a").unwrap();
+        func.add_block("b").unwrap();
+
+        func.add_edge(0, 1);
+        func.add_edge(0, 1);
+
+        println!("Test Function: {}", function!());
+        assert_eq!(func.blocks[0].succs, vec![1]);
+        assert_eq!(func.blocks[1].preds, vec![0]);
+    }
+
+    #[test]
+    fn test_block_helpers_keep_phis_and_terminator_in_place() {
+        let mut block = IrBasicBlock {
+            label: "b".to_string(),
+            instrs: Vec::new(),
+            preds: vec![0, 1],
+            succs: Vec::new(),
+        };
+        assert!(block.terminator().is_none());
+
+        block.push_instr(IrInstruction::Jmp {
+            label: "exit".to_string(),
+        });
+        block.push_instr(IrInstruction::Assign {
+            lhs: "y".to_string(),
+            rhs: AssignRhs::Const(cfg::Literal::Int(1)),
+            ty: None,
+        });
+        block.push_phi(IrInstruction::Phi {
+            dest: "x".to_string(),
+            sources: vec![(0, None), (1, None)],
+            ty: None,
+        });
+        block.push_phi(IrInstruction::Phi {
+            dest: "z".to_string(),
+            sources: vec![(0, None), (1, None)],
+            ty: None,
+        });
+        block.prepend_instr(IrInstruction::Assign {
+            lhs: "w".to_string(),
+            rhs: AssignRhs::Const(cfg::Literal::Int(0)),
+            ty: None,
+        });
+
+        println!("Test Function: {}", function!());
+        println!("  Instrs: {:?}", block.instrs);
+        let order: Vec<&str> = block
+            .instrs
+            .iter()
+            .map(|i| match i {
+                IrInstruction::Phi { dest, .. } => dest.as_str(),
+                IrInstruction::Assign { lhs, .. } => lhs.as_str(),
+                IrInstruction::Jmp { .. } => "jmp",
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(order, vec!["x", "z", "w", "y", "jmp"]);
+        assert!(matches!(
+            block.terminator(),
+            Some(IrInstruction::Jmp { .. })
+        ));
+    }
+
+    #[test]
+    fn test_phi_sources_follow_their_preds() {
+        let mut func = diamond_cfg();
+        func.blocks[4].push_phi(IrInstruction::Phi {
+            dest: "x".to_string(),
+            sources: vec![(3, Some("b".to_string())), (2, Some("a".to_string()))],
+            ty: Some(IrType::I64),
+        });
+
+        println!("Test Function: {}", function!());
+        // the order of the sources doesn't have to match the preds
+        assert!(verify_ssa(&func).is_ok());
+
+        func.blocks[4].replace_pred(2, 1);
+        assert_eq!(func.blocks[4].preds, vec![1, 3]);
+        assert!(matches!(
+            &func.blocks[4].instrs[0],
+            IrInstruction::Phi { sources, .. } if sources[1] == (1, Some("a".to_string()))
+        ));
+
+        func.blocks[4].remove_pred(3);
+        assert_eq!(func.blocks[4].preds, vec![1]);
+        assert_eq!(func.blocks[4].instrs[0].uses(), vec!["a"]);
+
+        // a source from a block that isn't a pred
+        func.blocks[4].preds = vec![2];
+        assert!(verify_ssa(&func).is_err());
+    }
+
+    #[test]
+    fn test_split_critical_edges() {
+        // entry either skips `then` or goes through it, join merges both
+        let mut func = IrFunction::new("skip");
+        let entry = func.add_block("entry").unwrap();
+        let then_b = func.add_block("then").unwrap();
+        let join = func.add_block("join").unwrap();
+        func.add_edge(entry, then_b);
+        func.add_edge(entry, join);
+        func.add_edge(then_b, join);
+        func.blocks[entry].instrs = vec![IrInstruction::Br {
+            cond: "c".to_string(),
+            then_lbl: "then".to_string(),
+            else_lbl: "join".to_string(),
+        }];
+        func.blocks[then_b].instrs = vec![IrInstruction::Jmp {
+            label: "join".to_string(),
+        }];
+        func.blocks[join].instrs = vec![
+            IrInstruction::Phi {
+                dest: "x".to_string(),
+                sources: vec![
+                    (entry, Some("e".to_string())),
+                    (then_b, Some("t".to_string())),
+                ],
+                ty: Some(IrType::I64),
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        split_critical_edges(&mut func);
+
+        println!("Test Function: {}", function!());
+        // only entry -> join was critical
+        assert_eq!(func.blocks.len(), 4);
+        let mid = func.block_index(&"entry.join.split".to_string()).unwrap();
+        assert_eq!(func.blocks[entry].succs, vec![then_b, mid]);
+        assert_eq!(func.blocks[mid].preds, vec![entry]);
+        assert_eq!(func.blocks[mid].succs, vec![join]);
+        assert_eq!(func.blocks[join].preds, vec![mid, then_b]);
+        assert!(matches!(
+            func.blocks[entry].terminator(),
+            Some(IrInstruction::Br { else_lbl, .. }) if *else_lbl == "entry.join.split"
+        ));
+        assert!(matches!(
+            func.blocks[mid].terminator(),
+            Some(IrInstruction::Jmp { label }) if label == "join"
+        ));
+        assert!(matches!(
+            &func.blocks[join].instrs[0],
+            IrInstruction::Phi { sources, .. } if sources[0] == (mid, Some("e".to_string()))
+        ));
+        assert!(verify_ssa(&func).is_ok());
+
+        // nothing critical is left
+        split_critical_edges(&mut func);
+        assert_eq!(func.blocks.len(), 4);
+    }
+
+    #[test]
+    fn test_entry_block_prefers_the_entry_label() {
+        let mut func = IrFunction::new("late_entry");
+        let exit = func.add_block("exit").unwrap();
+        assert_eq!(func.entry_block(), 0);
+
+        let entry = func.add_block("entry").unwrap();
+        func.add_edge(entry, exit);
+
+        println!("Test Function: {}", function!());
+        assert_eq!(func.entry_block(), entry);
+        assert_eq!(func.rpo(), vec![entry, exit]);
+    }
+
+    #[test]
+    fn test_ssa_with_entry_not_at_index_zero() {
+        let mut func = IrFunction::new("entry_last");
+        let a = func.add_block("a").unwrap();
+        let b = func.add_block("b").unwrap();
+        let entry = func.add_block("entry").unwrap();
+        func.add_edge(entry, a);
+        func.add_edge(entry, b);
+        func.add_edge(a, b);
+
+        let mut temp_funcs = vec![func];
+        let ssa = SSAFormation::new(&mut temp_funcs).unwrap();
+
+        println!("Test Function: {}", function!());
+        let data = &ssa.functions["entry_last"];
+        println!("  Idom: {:?}", data.idom);
+        assert_eq!(data.idom[&a], entry);
+        assert_eq!(data.idom[&b], entry);
+    }
+
+    #[test]
+    fn test_void_call_results_cannot_be_read() {
+        let call = |dest: CallDest| IrInstruction::Call {
+            target_func: "log".to_string(),
+            args: vec!["x".to_string()],
+            dest,
+            ty: Some(IrType::Void),
+        };
+        let mut func = IrFunction::new("caller");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            call(CallDest::Void),
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        println!("Test Function: {}", function!());
+        assert!(func.blocks[entry].instrs[0].defs().is_empty());
+        assert!(verify_ssa(&func).is_ok());
+
+        // naming the result is how it could end up being read
+        func.blocks[entry].instrs[0] = call(CallDest::Named("r".to_string()));
+        assert!(verify_ssa(&func).is_ok());
+        func.blocks[entry].push_instr(IrInstruction::Print {
+            values: vec!["r".to_string()],
+        });
+        assert!(verify_ssa(&func).is_err());
+    }
+
+    #[test]
+    fn test_rename_fills_back_edge_phi_sources() {
+        let mut func = loop_cfg();
+        let assign = |lhs: &str, rhs: &str| IrInstruction::Assign {
+            lhs: lhs.to_string(),
+            rhs: AssignRhs::from_operand(rhs),
+            ty: None,
+        };
+        func.blocks[0].push_instr(assign("x", "0"));
+        func.blocks[2].push_instr(IrInstruction::Add {
+            dest: "x".to_string(),
+            lhs: "x".to_string(),
+            rhs: "1".to_string(),
+            ty: None,
+        });
+
+        let mut temp_funcs = vec![func];
+        SSAFormation::new(&mut temp_funcs).unwrap();
+
+        println!("Test Function: {}", function!());
+        let header = &temp_funcs[0].blocks[1];
+        println!("  Header: {:?}", header.instrs);
+        let IrInstruction::Phi { dest, sources, .. } = &header.instrs[0] else {
+            panic!("expected a phi at the top of the header");
+        };
+
+        // the body reads the phi and redefines x
+        let IrInstruction::Add {
+            dest: body_def,
+            lhs,
+            ..
+        } = &temp_funcs[0].blocks[2].instrs[0]
+        else {
+            panic!("expected the add in the body");
+        };
+        assert_eq!(lhs, dest);
+
+        // preds of the header are [entry, body, latch]
+        assert_eq!(sources[0], (0, Some("x$1".to_string())));
+        assert_eq!(sources[1], (2, Some(body_def.clone())));
+        assert_eq!(sources[2], (3, Some(body_def.clone())));
+    }
+
+    #[test]
+    fn test_defs_typed_falls_back_to_the_opcode_type() {
+        let cmp = IrInstruction::Lt {
+            dest: "c".to_string(),
+            lhs: "a".to_string(),
+            rhs: "b".to_string(),
+            ty: None,
+        };
+        let narrow = IrInstruction::Add {
+            dest: "s".to_string(),
+            lhs: "a".to_string(),
+            rhs: "b".to_string(),
+            ty: Some(IrType::I32),
+        };
+        let untyped = IrInstruction::Add {
+            dest: "t".to_string(),
+            lhs: "a".to_string(),
+            rhs: "b".to_string(),
+            ty: None,
+        };
+
+        println!("Test Function: {}", function!());
+        assert_eq!(cmp.defs_typed(), vec![("c".to_string(), IrType::Bool)]);
+        assert_eq!(narrow.defs_typed(), vec![("s".to_string(), IrType::I32)]);
+        assert_eq!(untyped.defs_typed(), vec![("t".to_string(), IrType::I64)]);
+        let ret = IrInstruction::Ret { args: Vec::new() };
+        assert!(ret.defs_typed().is_empty());
+    }
+
+    #[test]
+    fn test_verify_checks_returns_against_ret_ty() {
+        let mut func = IrFunction::new("answer");
+        let entry = func.add_block("entry").unwrap();
+        func.append_instr(
+            entry,
+            &IrInstruction::Assign {
+                lhs: "x".to_string(),
+                rhs: AssignRhs::Const(cfg::Literal::Int(42)),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            entry,
+            &IrInstruction::Ret {
+                args: vec!["x".to_string()],
+            },
+        );
+
+        let mut temp_funcs = vec![func];
+        SSAFormation::new(&mut temp_funcs).unwrap();
+        let mut func = temp_funcs.remove(0);
+
+        println!("Test Function: {}", function!());
+        // `new` defaults to a void function
+        let err = verify_ssa(&func).unwrap_err();
+        println!("  Error: {}", err);
+
+        func.ret_ty = IrType::I64;
+        verify_ssa(&func).unwrap();
+
+        func.ret_ty = IrType::Bool;
+        assert!(verify_ssa(&func).is_err());
+    }
+
+    #[test]
+    fn test_iterated_df_on_diamond() {
+        let func = diamond_cfg();
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.compute_idom(&func).unwrap();
+        ssa.compute_df(&func).unwrap();
+
+        println!("Test Function: {}", function!());
+        let df_plus = ssa.iterated_df(&[2, 3]);
+        println!("  DF+: {:?}", df_plus);
+        assert_eq!(df_plus, BTreeSet::from([4]));
+        assert!(ssa.iterated_df(&[1]).is_empty());
+    }
+
+    #[test]
+    fn test_assign_only_reads_a_variable_rhs() {
+        let copy = IrInstruction::Assign {
+            lhs: "x".to_string(),
+            rhs: AssignRhs::from_operand("y"),
+            ty: None,
+        };
+        let constant = IrInstruction::Assign {
+            lhs: "b".to_string(),
+            rhs: AssignRhs::from_operand("true"),
+            ty: None,
+        };
+
+        println!("Test Function: {}", function!());
+        assert_eq!(copy.uses(), vec!["y"]);
+        assert!(constant.uses().is_empty());
+        assert_eq!(constant.defs_typed(), vec![("b".to_string(), IrType::Bool)]);
+    }
+
+    #[test]
+    fn test_ssa_keeps_dominators_per_function() {
+        let mut funcs = vec![diamond_cfg(), loop_cfg()];
+        let ssa = SSAFormation::new(&mut funcs).unwrap();
+
+        println!("Test Function: {}", function!());
+        let diamond = ssa.function("diamond").unwrap();
+        let looping = ssa.function("looping").unwrap();
+        println!("  Diamond Idom: {:?}", diamond.idom);
+        println!("  Looping Idom: {:?}", looping.idom);
+
+        // the loop was processed last, it must not overwrite the diamond
+        assert_eq!(diamond.idom.len(), 6);
+        assert_eq!(diamond.idom[&5], 4);
+        assert_eq!(diamond.dom_frontier[&2], BTreeSet::from([4]));
+
+        assert_eq!(looping.idom.len(), 5);
+        assert_eq!(looping.idom[&4], 1);
+        assert_eq!(looping.dom_frontier[&3], BTreeSet::from([1]));
+
+        assert!(ssa.function("missing").is_none());
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn test_float_ops_define_f64_values() {
+        let mut func = IrFunction::new("scale");
+        let entry = func.add_block("entry").unwrap();
+        func.append_instr(
+            entry,
+            &IrInstruction::FMul {
+                dest: "y".to_string(),
+                lhs: "x".to_string(),
+                rhs: "1.5".to_string(),
+                ty: None,
+            },
+        );
+
+        println!("Test Function: {}", function!());
+        let instr = &func.blocks[entry].instrs[0];
+        assert_eq!(instr.uses(), vec!["x", "1.5"]);
+        assert_eq!(instr.defs_typed(), vec![("y".to_string(), IrType::F64)]);
+        assert_eq!(collect_defs(&func)["y"], vec![entry]);
+        assert_eq!(cfg::Literal::Float(2.0).to_string(), "2.0");
+    }
+
+    #[test]
+    fn test_module_looks_functions_up_by_name() {
+        let mut module = IrModule::default();
+        module.add_function(IrFunction::new("main"));
+        module.add_function(IrFunction::new("helper"));
+
+        println!("Test Function: {}", function!());
+        assert_eq!(module.get_function("helper").unwrap().name, "helper");
+        assert!(module.get_function("missing").is_none());
+
+        // adding a function again replaces it in place
+        let mut helper = IrFunction::new("helper");
+        helper.add_block("entry").unwrap();
+        module.add_function(helper);
+        assert_eq!(module.functions.len(), 2);
+        assert_eq!(module.get_function("helper").unwrap().blocks.len(), 1);
+
+        module.get_function_mut("main").unwrap().ret_ty = IrType::I64;
+        assert_eq!(module.functions[0].ret_ty, IrType::I64);
+
+        // indices shift once main is gone
+        module.retain_functions(|f| f.name != "main");
+        assert_eq!(module.func_to_idx["helper"], 0);
+        assert!(module.get_function("main").is_none());
+    }
+
+    #[test]
+    fn test_functions_compare_by_name() {
+        let mut optimized = IrFunction::new("main");
+        optimized.add_block("entry").unwrap();
+
+        println!("Test Function: {}", function!());
+        assert_eq!(optimized, IrFunction::new("main"));
+        assert_ne!(optimized, IrFunction::new("helper"));
+
+        let mut block_counts: HashMap<IrFunction, usize> = HashMap::new();
+        block_counts.insert(IrFunction::new("main"), 0);
+        block_counts.insert(optimized.clone(), optimized.blocks.len());
+        assert_eq!(block_counts.len(), 1);
+        assert_eq!(block_counts[&IrFunction::new("main")], 1);
+
+        // modules only care about which functions they hold, not the order
+        let mut a = IrModule::default();
+        a.add_function(IrFunction::new("main"));
+        a.add_function(IrFunction::new("helper"));
+        let mut b = IrModule::default();
+        b.add_function(IrFunction::new("helper"));
+        b.add_function(optimized);
+        assert_eq!(a, b);
+
+        b.retain_functions(|f| f.name == "main");
+        assert_ne!(a, b);
+    }
+
+    /// A loop that can be left from its header or from the middle of its body,
+    /// `i` is printed once both exits meet again
+    ///
+    /// 0 -> 1 (header) -> 2 (body) -> 3 (latch) -> 1
+    ///      1 -> 4 (done) -> 6 (join)
+    ///      2 -> 5 (out)  -> 6
+    fn loop_with_two_exits() -> IrFunction {
+        let mut func = IrFunction::new("two_exits");
+        for label in ["entry", "header", "body", "latch", "done", "out", "join"] {
+            func.add_block(label).unwrap();
+        }
+        func.add_edge(0, 1);
+        func.add_edge(1, 2);
+        func.add_edge(1, 4);
+        func.add_edge(2, 5);
+        func.add_edge(2, 3);
+        func.add_edge(3, 1);
+        func.add_edge(4, 6);
+        func.add_edge(5, 6);
+
+        let jmp = |label: &str| IrInstruction::Jmp {
+            label: label.to_string(),
+        };
+        func.blocks[0].instrs = vec![jmp("header")];
+        func.blocks[1].instrs = vec![
+            IrInstruction::Phi {
+                dest: "i".to_string(),
+                sources: vec![(0, Some("0".to_string())), (3, Some("i2".to_string()))],
+                ty: Some(IrType::I64),
+            },
+            IrInstruction::Lt {
+                dest: "c".to_string(),
+                lhs: "i".to_string(),
+                rhs: "10".to_string(),
+                ty: None,
+            },
+            IrInstruction::Br {
+                cond: "c".to_string(),
+                then_lbl: "body".to_string(),
+                else_lbl: "done".to_string(),
+            },
+        ];
+        func.blocks[2].instrs = vec![
+            IrInstruction::Add {
+                dest: "i2".to_string(),
+                lhs: "i".to_string(),
+                rhs: "1".to_string(),
+                ty: None,
+            },
+            IrInstruction::Eq {
+                dest: "d".to_string(),
+                lhs: "i2".to_string(),
+                rhs: "5".to_string(),
+                ty: None,
+            },
+            IrInstruction::Br {
+                cond: "d".to_string(),
+                then_lbl: "out".to_string(),
+                else_lbl: "latch".to_string(),
+            },
+        ];
+        func.blocks[3].instrs = vec![jmp("header")];
+        func.blocks[4].instrs = vec![jmp("join")];
+        func.blocks[5].instrs = vec![jmp("join")];
+        func.blocks[6].instrs = vec![
+            IrInstruction::Print {
+                values: vec!["i".to_string()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+        func
+    }
+
+    #[test]
+    fn test_lcssa_closes_values_at_every_exit() {
+        let mut func = loop_with_two_exits();
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.compute_idom(&func).unwrap();
+        let loops = find_natural_loops(&func, &ssa);
+        assert_eq!(loops.len(), 1);
+
+        to_lcssa(&mut func, &loops, &ssa);
+
+        println!("Test Function: {}", function!());
+        let phi = |b: BlockID| match &func.blocks[b].instrs[0] {
+            IrInstruction::Phi { dest, sources, .. } => (dest.clone(), sources.clone()),
+            other => panic!("expected a phi, found {:?}", other),
+        };
+        let from = |pred: BlockID, name: &str| (pred, Some(name.to_string()));
+
+        // one phi per exit, both reading `i` on the edge out of the loop
+        assert_eq!(phi(4), ("i.lcssa1".to_string(), vec![from(1, "i")]));
+        assert_eq!(phi(5), ("i.lcssa2".to_string(), vec![from(2, "i")]));
+        let merged = vec![from(4, "i.lcssa1"), from(5, "i.lcssa2")];
+        assert_eq!(phi(6), ("i.lcssa0".to_string(), merged));
+        assert_eq!(func.blocks[6].instrs[1].uses(), vec!["i.lcssa0"]);
+
+        // uses inside the loop keep reading `i`, and nothing else leaves it
+        assert_eq!(func.blocks[2].instrs[0].uses(), vec!["i", "1"]);
+        assert_eq!(func.blocks[3].instrs.len(), 1);
+        assert!(verify_ssa(&func).is_ok());
+    }
 }
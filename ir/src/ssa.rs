@@ -1,10 +1,14 @@
+use crate::cfg::AssignRhs;
+use crate::cfg::CallDest;
 use crate::cfg::collect_defs;
 use crate::cfg::IrFunction;
 use crate::cfg::IrModule;
+use crate::cfg::IrType;
+use crate::cfg::NaturalLoop;
 use crate::BlockID;
 use crate::IrInstruction;
-use anyhow::Result;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 /// Set up the Dominator Trees and Dominance Frontier
 /// Using the Cytron algo for creating a SSA
@@ -17,11 +21,24 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 ///2.Build the immediate-dominator (idom) tree.
 ///3.Compute each node’s DF (in a single pass over the CFG + dom-tree).
 ///4.Place ϕ-nodes for each variable at all blocks in the union of DF(definition blocks).
+///
+/// Every function gets its own dominator data, keyed by its name
 #[derive(Debug, Default)]
 pub struct SSAFormation {
+    pub functions: HashMap<String, PerFunctionSSAData>,
+}
+
+/// Dominator and post-dominator data of a single function. Children and
+/// frontiers are kept sorted so walking them doesn't depend on hash order
+#[derive(Debug, Default, Clone)]
+pub struct PerFunctionSSAData {
     pub idom: HashMap<BlockID, BlockID>,
-    pub dom_tree: HashMap<BlockID, Vec<BlockID>>,
-    pub dom_frontier: BTreeMap<BlockID, Vec<BlockID>>,
+    pub dom_tree: HashMap<BlockID, BTreeSet<BlockID>>,
+    pub dom_frontier: BTreeMap<BlockID, BTreeSet<BlockID>>,
+    // Post-dominators live on the reversed CFG, rooted at a virtual exit
+    // node whose ID is `func.blocks.len()`
+    pub post_idom: HashMap<BlockID, BlockID>,
+    pub post_dom_tree: HashMap<BlockID, BTreeSet<BlockID>>,
 }
 
 /// Convert our IrModule into a true SSA form
@@ -39,12 +56,13 @@ impl SSAFormation {
         let mut out = SSAFormation::default();
 
         for func in funcs {
-            out.compute_idom(func)?;
-            out.compute_df(func)?;
-            out.build_dom_tree()?;
+            let mut data = PerFunctionSSAData::default();
+            data.compute_idom(func)?;
+            data.compute_df(func)?;
+            data.build_dom_tree()?;
 
             let def_sites_map = collect_defs(func);
-            out.phi_insert(func, &def_sites_map);
+            data.phi_insert(func, &def_sites_map);
 
             let mut counter: HashMap<String, BlockID> = HashMap::new();
             let mut stacks: HashMap<String, Vec<String>> = HashMap::new();
@@ -53,34 +71,56 @@ impl SSAFormation {
                 counter.insert(var.clone(), 0);
                 stacks.insert(var.clone(), Vec::new());
             }
-            rename_pass(0, &out.dom_tree, func, &mut counter, &mut stacks);
+            let phi_vars = collect_phi_vars(func);
+            rename_pass(
+                func.entry_block(),
+                &data.dom_tree,
+                func,
+                &mut counter,
+                &mut stacks,
+                &phi_vars,
+            );
+
+            out.functions.insert(func.name.clone(), data);
         }
 
         Ok(out)
     }
 
+    /// Dominator data of the function called `name`
+    pub fn function(&self, name: &str) -> Option<&PerFunctionSSAData> {
+        self.functions.get(name)
+    }
+}
+
+impl PerFunctionSSAData {
     // TODO: Later in the future implement lengauer_tarjan_idom
+    /// Immediate dominators of every block reachable from the entry, blocks
+    /// that can't be reached are left out of `idom`
     pub fn compute_idom(&mut self, func: &IrFunction) -> Result<()> {
         let n = func.blocks.len();
         // usize::MAX means the idom is an unknown for now
         let mut idom_vec = vec![usize::MAX; n];
 
         // entry point to entry
-        idom_vec[0] = 0;
+        let entry = func.entry_block();
+        idom_vec[entry] = entry;
+
+        // The fingers climb by reverse post-order number, a dominator always
+        // comes before the blocks it dominates no matter where it sits in `blocks`
+        let rpo = func.rpo();
+        let mut rpo_num = vec![usize::MAX; n];
+        for (i, &b) in rpo.iter().enumerate() {
+            rpo_num[b] = i;
+        }
 
         // find the fix-point of the loop
         loop {
             let mut changed = false;
-            // b_idx = block index
-            // starting from block 1 because idom[0] is 0
-            for b in 1..n {
+            // skipping the entry because it's its own idom
+            for &b in rpo.iter().skip(1) {
                 let preds = &func.blocks[b].preds;
 
-                // Skip for if preds empty, we care for the preds because of the idom
-                if preds.is_empty() {
-                    continue;
-                }
-
                 let mut new_idom = match preds.iter().find(|&&p| idom_vec[p] != usize::MAX) {
                     Some(&p) => p,
                     None => continue,
@@ -98,10 +138,10 @@ impl SSAFormation {
                     let mut finger1 = p;
                     let mut finger2 = new_idom;
                     while finger1 != finger2 {
-                        while finger1 > finger2 {
+                        while rpo_num[finger1] > rpo_num[finger2] {
                             finger1 = idom_vec[finger1];
                         }
-                        while finger2 > finger1 {
+                        while rpo_num[finger2] > rpo_num[finger1] {
                             finger2 = idom_vec[finger2];
                         }
                     }
@@ -121,8 +161,9 @@ impl SSAFormation {
 
         self.idom.clear();
         for (block, &dom) in idom_vec.iter().enumerate() {
+            // unreachable from the entry, nothing dominates it
             if dom == usize::MAX {
-                panic!("could not compute idom for Block {}", block);
+                continue;
             }
             self.idom.insert(block, dom);
         }
@@ -142,16 +183,19 @@ impl SSAFormation {
                 continue;
             }
 
-            let idom_b = *self.idom.get(&b).expect("idom wasn't computed");
+            // unreachable blocks have no frontier to be part of
+            let Some(&idom_b) = self.idom.get(&b) else {
+                continue;
+            };
 
             for &p in &block.preds {
+                if !self.idom.contains_key(&p) {
+                    continue;
+                }
                 let mut runner = p;
 
                 while runner != idom_b {
-                    let entry = self.dom_frontier.entry(runner).or_default();
-                    if !entry.contains(&b) {
-                        entry.push(b);
-                    }
+                    self.dom_frontier.entry(runner).or_default().insert(b);
 
                     // climbing up the pred, the one runner is equal to
                     runner = *self.idom.get(&runner).unwrap();
@@ -168,7 +212,119 @@ impl SSAFormation {
         for (&b, &p) in &self.idom {
             // make sure we've skipped the entry
             if b != p {
-                self.dom_tree.entry(p).or_default().push(b);
+                self.dom_tree.entry(p).or_default().insert(b);
+            }
+        }
+        Ok(())
+    }
+
+    /// Immediate post-dominators, using the same Cooper et al. fix-point as
+    /// `compute_idom` but over the reversed CFG. Every block without successors
+    /// flows into a virtual exit node (`func.blocks.len()`), which is the root.
+    /// Blocks that can never reach an exit are left out of the map.
+    pub fn compute_post_idom(func: &IrFunction) -> Result<HashMap<BlockID, BlockID>> {
+        let n = func.blocks.len();
+        let exit = n;
+
+        // On the reversed CFG the successors of a block are its preds, and the
+        // virtual exit flows into every block that returns
+        let rev_succs = |b: BlockID| -> Vec<BlockID> {
+            if b == exit {
+                (0..n)
+                    .filter(|&x| func.blocks[x].succs.is_empty())
+                    .collect()
+            } else {
+                func.blocks[b].preds.clone()
+            }
+        };
+        let rev_preds = |b: BlockID| -> Vec<BlockID> {
+            if func.blocks[b].succs.is_empty() {
+                vec![exit]
+            } else {
+                func.blocks[b].succs.clone()
+            }
+        };
+
+        // post-order numbers on the reversed CFG, the intersection climbs by them
+        let mut po_num = vec![usize::MAX; n + 1];
+        let mut postorder: Vec<BlockID> = Vec::with_capacity(n + 1);
+        let mut visited = vec![false; n + 1];
+        let mut stack: Vec<(BlockID, usize)> = vec![(exit, 0)];
+        visited[exit] = true;
+        while let Some((b, next)) = stack.pop() {
+            let succs = rev_succs(b);
+            if let Some(&s) = succs.get(next) {
+                stack.push((b, next + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                po_num[b] = postorder.len();
+                postorder.push(b);
+            }
+        }
+
+        let mut ipdom = vec![usize::MAX; n + 1];
+        ipdom[exit] = exit;
+
+        loop {
+            let mut changed = false;
+
+            // reverse post-order, skipping the virtual exit
+            for &b in postorder.iter().rev().skip(1) {
+                let preds = rev_preds(b);
+
+                let mut new_ipdom = match preds.iter().find(|&&p| ipdom[p] != usize::MAX) {
+                    Some(&p) => p,
+                    None => continue,
+                };
+
+                for &p in preds.iter() {
+                    if p == new_ipdom || ipdom[p] == usize::MAX {
+                        continue;
+                    }
+
+                    let mut finger1 = p;
+                    let mut finger2 = new_ipdom;
+                    while finger1 != finger2 {
+                        while po_num[finger1] < po_num[finger2] {
+                            finger1 = ipdom[finger1];
+                        }
+                        while po_num[finger2] < po_num[finger1] {
+                            finger2 = ipdom[finger2];
+                        }
+                    }
+                    new_ipdom = finger1;
+                }
+
+                if ipdom[b] != new_ipdom {
+                    ipdom[b] = new_ipdom;
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        Ok(ipdom
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, p)| p != usize::MAX)
+            .collect())
+    }
+
+    /// Computes the post-dominators of `func` and builds the post-dominator tree
+    pub fn build_post_dom_tree(&mut self, func: &IrFunction) -> Result<()> {
+        self.post_idom = PerFunctionSSAData::compute_post_idom(func)?;
+        self.post_dom_tree.clear();
+
+        for (&b, &p) in &self.post_idom {
+            // skip the virtual exit
+            if b != p {
+                self.post_dom_tree.entry(p).or_default().insert(b);
             }
         }
         Ok(())
@@ -178,40 +334,99 @@ impl SSAFormation {
         for (var, blocks_with_defs) in def_sites_map {
             // `var` - the Variable we're looking for
             // `blocks_with_defs` - blocks where `var` is defined at
-            let mut worklist: Vec<BlockID> = blocks_with_defs.clone();
-            let mut has_phi: HashSet<BlockID> = blocks_with_defs.iter().cloned().collect();
-
-            while let Some(block_id_def) = worklist.pop() {
-                if let Some(frontier) = self.dom_frontier.get(&block_id_def) {
-                    for &m in frontier {
-                        if has_phi.insert(m) {
-                            let block = &mut func.blocks[m];
-                            block.instrs.insert(
-                                0,
-                                IrInstruction::Phi {
-                                    dest: var.clone(),
-                                    sources: vec![None; block.preds.len()],
-                                },
-                            );
-
-                            worklist.push(m);
-                        }
-                    }
+            for m in self.iterated_df(blocks_with_defs) {
+                let block = &mut func.blocks[m];
+                let sources = block.preds.iter().map(|&p| (p, None)).collect();
+                block.push_phi(IrInstruction::Phi {
+                    dest: var.clone(),
+                    sources,
+                    ty: None,
+                });
+            }
+        }
+    }
+
+    /// DF+ of `seeds`, see [`iterated_df`]
+    pub fn iterated_df(&self, seeds: &[BlockID]) -> BTreeSet<BlockID> {
+        iterated_df(&self.dom_frontier, seeds)
+    }
+
+    /// Climbs the idom chain from `b` to check if `a` dominates it, every
+    /// block dominates itself. Blocks without an idom (unreachable ones) are
+    /// only dominated by themselves
+    pub fn dominates(&self, a: BlockID, b: BlockID) -> bool {
+        let mut runner = b;
+        loop {
+            if runner == a {
+                return true;
+            }
+            match self.idom.get(&runner) {
+                Some(&parent) if parent != runner => runner = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Iterated dominance frontier: keeps adding the frontier of every block found
+/// so far until nothing new shows up. These are the blocks where values
+/// defined in `seeds` meet, i.e. where phis go.
+pub fn iterated_df(
+    dom_frontier: &BTreeMap<BlockID, BTreeSet<BlockID>>,
+    seeds: &[BlockID],
+) -> BTreeSet<BlockID> {
+    let mut result: BTreeSet<BlockID> = BTreeSet::new();
+    let mut worklist: Vec<BlockID> = seeds.to_vec();
+
+    while let Some(b) = worklist.pop() {
+        if let Some(frontier) = dom_frontier.get(&b) {
+            for &m in frontier {
+                if result.insert(m) {
+                    worklist.push(m);
                 }
             }
         }
     }
+
+    result
+}
+
+/// Maps every phi, by (block, position), to the variable it was placed for.
+/// Must be collected before renaming since the phi's dest gets overwritten
+pub fn collect_phi_vars(func: &IrFunction) -> HashMap<(BlockID, usize), String> {
+    let mut phi_vars = HashMap::new();
+
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, instr) in block.instrs.iter().enumerate() {
+            if let IrInstruction::Phi { dest, .. } = instr {
+                phi_vars.insert((b, i), dest.clone());
+            }
+        }
+    }
+
+    phi_vars
 }
 
 /// Rename pass for all the blocks, it'll convert every indiviual variables in each block
 /// with it's own unique name
+///
+/// `phi_vars` comes from [`collect_phi_vars`], it's how phi sources get looked up
+/// on the stacks once the phi's own dest has been renamed
 pub fn rename_pass(
     block_id: BlockID,
-    dom_tree: &HashMap<BlockID, Vec<BlockID>>,
+    dom_tree: &HashMap<BlockID, BTreeSet<BlockID>>,
     func: &mut IrFunction,
     counter: &mut HashMap<String, BlockID>,
     stacks: &mut HashMap<String, Vec<String>>,
+    phi_vars: &HashMap<(BlockID, usize), String>,
 ) {
+    // the original names defined here, so their stack entries can be popped on the way out
+    let defined: Vec<String> = func.blocks[block_id]
+        .instrs
+        .iter()
+        .flat_map(|instr| instr.defs().to_vec())
+        .collect();
+
     {
         let blocks = &mut func.blocks;
         // Manage all the Phi-nodes block
@@ -225,58 +440,81 @@ pub fn rename_pass(
             // TODO: Maybe find a better way of handling this? This relates
             // to the ID opcode for Bril...
             match instr {
-                IrInstruction::Assign { lhs, rhs } => {
-                    *rhs = current_name(rhs, stacks);
+                IrInstruction::Assign { lhs, rhs, .. } => {
+                    if let AssignRhs::Var(var) = rhs {
+                        *var = current_name(var, stacks);
+                    }
                     *lhs = create_new_name(lhs, counter, stacks);
                 }
 
-                IrInstruction::Not { dest, args } => {
+                IrInstruction::Not { dest, args, .. } => {
                     *args = current_name(args, stacks);
                     *dest = create_new_name(dest, counter, stacks);
                 }
 
                 // TODO: Added more instructions
-                IrInstruction::Add { lhs, rhs, dest }
-                | IrInstruction::Mul { lhs, rhs, dest }
-                | IrInstruction::Sub { lhs, rhs, dest }
-                | IrInstruction::Div { lhs, rhs, dest }
-                | IrInstruction::Eq { lhs, rhs, dest }
-                | IrInstruction::Lt { lhs, rhs, dest }
-                | IrInstruction::Gt { lhs, rhs, dest }
-                | IrInstruction::Ge { lhs, rhs, dest }
-                | IrInstruction::Le { lhs, rhs, dest }
-                | IrInstruction::Or { lhs, rhs, dest }
-                | IrInstruction::And { lhs, rhs, dest } => {
+                IrInstruction::Add { lhs, rhs, dest, .. }
+                | IrInstruction::Mul { lhs, rhs, dest, .. }
+                | IrInstruction::Sub { lhs, rhs, dest, .. }
+                | IrInstruction::Div { lhs, rhs, dest, .. }
+                | IrInstruction::Shl { lhs, rhs, dest, .. }
+                | IrInstruction::Shr { lhs, rhs, dest, .. }
+                | IrInstruction::UShr { lhs, rhs, dest, .. }
+                | IrInstruction::Eq { lhs, rhs, dest, .. }
+                | IrInstruction::Lt { lhs, rhs, dest, .. }
+                | IrInstruction::Gt { lhs, rhs, dest, .. }
+                | IrInstruction::Ge { lhs, rhs, dest, .. }
+                | IrInstruction::Le { lhs, rhs, dest, .. }
+                | IrInstruction::Or { lhs, rhs, dest, .. }
+                | IrInstruction::And { lhs, rhs, dest, .. }
+                | IrInstruction::Xor { lhs, rhs, dest, .. } => {
+                    *lhs = current_name(lhs, stacks);
+                    *rhs = current_name(rhs, stacks);
+                    *dest = create_new_name(dest, counter, stacks);
+                }
+
+                #[cfg(feature = "float")]
+                IrInstruction::FAdd { lhs, rhs, dest, .. }
+                | IrInstruction::FSub { lhs, rhs, dest, .. }
+                | IrInstruction::FMul { lhs, rhs, dest, .. }
+                | IrInstruction::FDiv { lhs, rhs, dest, .. } => {
                     *lhs = current_name(lhs, stacks);
                     *rhs = current_name(rhs, stacks);
                     *dest = create_new_name(dest, counter, stacks);
                 }
 
                 IrInstruction::Call { args, dest, .. } => {
-                    if !args.is_empty() {
-                        for a in args.iter_mut() {
-                            *a = current_name(a, stacks);
-                        }
+                    for a in args.iter_mut() {
+                        *a = current_name(a, stacks);
                     }
 
-                    if let Some(d) = dest {
-                        *dest = Some(create_new_name(d, counter, stacks));
+                    if let CallDest::Named(d) = dest {
+                        *d = create_new_name(d, counter, stacks);
                     }
                 }
 
+                IrInstruction::Select {
+                    dest,
+                    cond,
+                    true_val,
+                    false_val,
+                    ..
+                } => {
+                    *cond = current_name(cond, stacks);
+                    *true_val = current_name(true_val, stacks);
+                    *false_val = current_name(false_val, stacks);
+                    *dest = create_new_name(dest, counter, stacks);
+                }
+
                 IrInstruction::Print { values } => {
-                    if !values.is_empty() {
-                        for a in values.iter_mut() {
-                            *a = current_name(a, stacks);
-                        }
+                    for a in values.iter_mut() {
+                        *a = current_name(a, stacks);
                     }
                 }
 
                 IrInstruction::Ret { args } => {
-                    if !args.is_empty() {
-                        for a in args.iter_mut() {
-                            *a = current_name(a, stacks);
-                        }
+                    for a in args.iter_mut() {
+                        *a = current_name(a, stacks);
                     }
                 }
 
@@ -289,15 +527,12 @@ pub fn rename_pass(
     // if needed
     for succ in func.blocks[block_id].succs.clone() {
         let succ_block = &mut func.blocks[succ];
-        for instr in succ_block.instrs.iter_mut() {
-            if let IrInstruction::Phi { dest, sources } = instr {
-                let idx = succ_block
-                    .preds
-                    .iter()
-                    .position(|&p| p == block_id)
-                    .unwrap();
-                // Source is the size of the preds
-                sources[idx] = Some(current_name(dest, stacks));
+        for (i, instr) in succ_block.instrs.iter_mut().enumerate() {
+            if let IrInstruction::Phi { sources, .. } = instr {
+                let var = &phi_vars[&(succ, i)];
+                for (_, src) in sources.iter_mut().filter(|(p, _)| *p == block_id) {
+                    *src = Some(current_name(var, stacks));
+                }
             }
         }
     }
@@ -305,20 +540,15 @@ pub fn rename_pass(
     // Recursively rename each immediate child of a block through the dominator tree
     if let Some(child_blocks) = dom_tree.get(&block_id) {
         for &child in child_blocks {
-            rename_pass(child, dom_tree, func, counter, stacks);
+            rename_pass(child, dom_tree, func, counter, stacks, phi_vars);
         }
     }
 
     // Now we have to pop all the values on the SSA rename stacks hashmap
     // in order to have a distinct values
-    for instr in &func.blocks[block_id].instrs {
-        //println!("Instr!! {:#?}", &instr);
-        //println!("Block!! {:#?}", &func.blocks[block_id]);
-        for var in instr.defs() {
-            //println!("Var!! {}", var);
-            if stacks.contains_key(var) {
-                stacks.get_mut(var).expect("Something is wrong twin").pop();
-            }
+    for var in defined {
+        if let Some(stk) = stacks.get_mut(&var) {
+            stk.pop();
         }
     }
 }
@@ -347,3 +577,290 @@ fn create_new_name(
         .push(new_var.clone());
     new_var
 }
+
+/// Puts `func` into loop-closed SSA form. Every value defined inside one of
+/// `loops` and used outside of it gets a phi in the exit blocks it leaves
+/// through, and the outside uses read those phis instead. Where several exits
+/// meet again, another phi merges them.
+///
+/// `ssa` must hold the dominators of `func`, the CFG itself isn't changed so
+/// they stay valid afterwards
+pub fn to_lcssa(func: &mut IrFunction, loops: &[NaturalLoop], ssa: &PerFunctionSSAData) {
+    let mut taken: HashSet<String> = func.args.iter().map(|(a, _)| a.clone()).collect();
+    for instr in func.blocks.iter().flat_map(|b| b.instrs.iter()) {
+        taken.extend(instr.defs().iter().cloned());
+    }
+
+    for lp in loops {
+        let body: HashSet<BlockID> = lp.body.iter().copied().collect();
+
+        let mut defined: Vec<(String, IrType, BlockID)> = Vec::new();
+        for &b in &lp.body {
+            for instr in &func.blocks[b].instrs {
+                defined.extend(instr.defs_typed().into_iter().map(|(d, ty)| (d, ty, b)));
+            }
+        }
+
+        for (var, ty, def_block) in defined {
+            let mut closer = LoopCloser {
+                func,
+                body: &body,
+                var: &var,
+                taken: &mut taken,
+                at_top: HashMap::new(),
+                phis: Vec::new(),
+                trivial: HashMap::new(),
+            };
+
+            // (block, instr, phi source) of every use outside the loop, along
+            // with the name it should read instead
+            let mut rewrites: Vec<(BlockID, usize, Option<usize>, String)> = Vec::new();
+            for (b, block) in func.blocks.iter().enumerate() {
+                if body.contains(&b) {
+                    continue;
+                }
+
+                for (i, instr) in block.instrs.iter().enumerate() {
+                    if let IrInstruction::Phi { sources, .. } = instr {
+                        for (s, (p, src)) in sources.iter().enumerate() {
+                            let p = *p;
+                            // coming straight out of the loop is already closed
+                            if src.as_deref() != Some(var.as_str())
+                                || body.contains(&p)
+                                || !ssa.dominates(def_block, p)
+                            {
+                                continue;
+                            }
+                            rewrites.push((b, i, Some(s), closer.value_out(p)));
+                        }
+                    } else if instr.uses().contains(&var) && ssa.dominates(def_block, b) {
+                        rewrites.push((b, i, None, closer.value_at_top(b)));
+                    }
+                }
+            }
+
+            let LoopCloser { phis, trivial, .. } = closer;
+            let resolve = |mut name: String| {
+                while let Some(next) = trivial.get(&name) {
+                    name = next.clone();
+                }
+                name
+            };
+
+            for (b, i, src, name) in rewrites {
+                let name = resolve(name);
+                let instr = &mut func.blocks[b].instrs[i];
+                match (instr, src) {
+                    (IrInstruction::Phi { sources, .. }, Some(s)) => sources[s].1 = Some(name),
+                    (instr, _) => instr
+                        .names_mut()
+                        .into_iter()
+                        .filter(|n| **n == var)
+                        .for_each(|n| *n = name.clone()),
+                }
+            }
+
+            for (b, dest, sources) in phis {
+                func.blocks[b].push_phi(IrInstruction::Phi {
+                    dest,
+                    sources: sources
+                        .into_iter()
+                        .map(|(p, s)| (p, Some(resolve(s))))
+                        .collect(),
+                    ty: Some(ty.clone()),
+                });
+            }
+        }
+    }
+}
+
+/// (block, dest, (pred, source) pairs) of a phi `to_lcssa` still has to insert
+type PendingPhi = (BlockID, String, Vec<(BlockID, String)>);
+
+/// Finds the name that holds `var` at a point outside the loop, making up the
+/// phis that are needed on the way
+struct LoopCloser<'a> {
+    func: &'a IrFunction,
+    body: &'a HashSet<BlockID>,
+    var: &'a str,
+    taken: &'a mut HashSet<String>,
+    // name of `var` at the top of a block outside the loop
+    at_top: HashMap<BlockID, String>,
+    phis: Vec<PendingPhi>,
+    // phis that turned out to merge a single value, and that value
+    trivial: HashMap<String, String>,
+}
+
+impl LoopCloser<'_> {
+    fn value_out(&mut self, b: BlockID) -> String {
+        if self.body.contains(&b) {
+            return self.var.to_string();
+        }
+        self.value_at_top(b)
+    }
+
+    fn value_at_top(&mut self, b: BlockID) -> String {
+        if let Some(name) = self.at_top.get(&b) {
+            return name.clone();
+        }
+
+        let func = self.func;
+        let preds = &func.blocks[b].preds;
+        let is_exit = preds.iter().any(|p| self.body.contains(p));
+        if !is_exit && preds.len() == 1 {
+            let name = self.value_out(preds[0]);
+            self.at_top.insert(b, name.clone());
+            return name;
+        }
+
+        // claimed before visiting the preds, a cycle back here reads the phi
+        let phi = self.fresh_name();
+        self.at_top.insert(b, phi.clone());
+        let sources: Vec<(BlockID, String)> =
+            preds.iter().map(|&p| (p, self.value_out(p))).collect();
+
+        // exits always get their phi, merges only when values actually differ
+        let mut others = sources.iter().map(|(_, s)| s).filter(|s| **s != phi);
+        if !is_exit
+            && let Some(first) = others.next()
+            && others.all(|s| s == first)
+        {
+            let first = first.clone();
+            self.trivial.insert(phi, first.clone());
+            self.at_top.insert(b, first.clone());
+            return first;
+        }
+
+        self.phis.push((b, phi.clone(), sources));
+        phi
+    }
+
+    fn fresh_name(&mut self) -> String {
+        let mut n = 0;
+        loop {
+            let name = format!("{}.lcssa{}", self.var, n);
+            if self.taken.insert(name.clone()) {
+                return name;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// Checks that `func` is well-formed SSA: every name is defined once, every
+/// phi has exactly one source per predecessor, nothing reads the result of a
+/// void call, and every `Ret` agrees with `ret_ty`
+pub fn verify_ssa(func: &IrFunction) -> Result<()> {
+    let mut types: HashMap<String, IrType> = func.args.iter().cloned().collect();
+
+    for block in &func.blocks {
+        for instr in &block.instrs {
+            for (name, ty) in instr.defs_typed() {
+                if types.insert(name.clone(), ty).is_some() {
+                    bail!("`{}` is defined more than once in {}", name, func.name);
+                }
+            }
+
+            if let IrInstruction::Phi { dest, sources, .. } = instr {
+                let mut from: Vec<BlockID> = sources.iter().map(|(p, _)| *p).collect();
+                let mut preds = block.preds.clone();
+                from.sort();
+                preds.sort();
+                if from != preds {
+                    bail!(
+                        "phi for `{}` in {} has sources from {:?} but its predecessors are {:?}",
+                        dest,
+                        block.label,
+                        from,
+                        preds
+                    );
+                }
+            }
+        }
+    }
+
+    // a void call named its result anyway, there's no value behind that name
+    for block in &func.blocks {
+        for instr in &block.instrs {
+            if let Some(name) = instr
+                .uses()
+                .into_iter()
+                .find(|u| types.get(u) == Some(&IrType::Void))
+            {
+                bail!(
+                    "{} reads `{}` in {}, but it's the result of a void call",
+                    func.name,
+                    name,
+                    block.label
+                );
+            }
+        }
+    }
+
+    for block in &func.blocks {
+        for instr in &block.instrs {
+            let IrInstruction::Ret { args } = instr else {
+                continue;
+            };
+
+            match (&func.ret_ty, args.as_slice()) {
+                (IrType::Void, []) => {}
+                (IrType::Void, _) => {
+                    bail!(
+                        "{} returns void but {} returns a value",
+                        func.name,
+                        block.label
+                    )
+                }
+                (ty, []) => bail!(
+                    "{} must return {:?} but {} returns nothing",
+                    func.name,
+                    ty,
+                    block.label
+                ),
+                (ty, [value]) => {
+                    if let Some(actual) = operand_type(value, &types)
+                        && !type_matches(ty, &actual)
+                    {
+                        bail!(
+                            "{} must return {:?} but {} returns `{}` of type {:?}",
+                            func.name,
+                            ty,
+                            block.label,
+                            value,
+                            actual
+                        );
+                    }
+                }
+                (_, _) => bail!(
+                    "{} returns more than one value in {}",
+                    func.name,
+                    block.label
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Type of a name or a literal operand, `None` if the name was never defined
+fn operand_type(operand: &str, types: &HashMap<String, IrType>) -> Option<IrType> {
+    if let Some(ty) = types.get(operand) {
+        return Some(ty.clone());
+    }
+
+    if operand.parse::<i64>().is_ok() {
+        Some(IrType::I64)
+    } else if operand.parse::<bool>().is_ok() {
+        Some(IrType::Bool)
+    } else {
+        None
+    }
+}
+
+/// Integers of either width are interchangeable, untyped values default to `I64`
+fn type_matches(expected: &IrType, actual: &IrType) -> bool {
+    let is_int = |t: &IrType| matches!(t, IrType::I64 | IrType::I32);
+    expected == actual || (is_int(expected) && is_int(actual))
+}
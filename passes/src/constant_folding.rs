@@ -1,12 +1,14 @@
-use crate::pass_manager::FunctionPass;
+use crate::pass_manager::TransformPass;
 use ir::cfg::Literal;
+use ir::AssignRhs;
 use ir::IrFunction;
 use ir::IrInstruction;
+use ir::IrType;
 
 /// Intraprocedural Constant Fold
 pub struct ConstantFoldPass {}
 
-impl FunctionPass for ConstantFoldPass {
+impl TransformPass for ConstantFoldPass {
     fn name(&self) -> &str {
         "ConstantFoldPass"
     }
@@ -16,7 +18,7 @@ impl FunctionPass for ConstantFoldPass {
             for instr in blocks.instrs.iter_mut() {
                 // TODO: Added more folds
                 match instr {
-                    IrInstruction::Add { dest, lhs, rhs } => {
+                    IrInstruction::Add { dest, lhs, rhs, ty } => {
                         if rhs.parse::<i64>().is_err() || lhs.parse::<i64>().is_err() {
                             continue;
                         }
@@ -27,10 +29,11 @@ impl FunctionPass for ConstantFoldPass {
                         *instr = IrInstruction::Const {
                             dest: dest.to_string(),
                             value: Literal::Int(sum),
+                            ty: ty.clone(),
                         };
                     }
 
-                    IrInstruction::Mul { dest, lhs, rhs } => {
+                    IrInstruction::Mul { dest, lhs, rhs, ty } => {
                         if rhs.parse::<i64>().is_err() || lhs.parse::<i64>().is_err() {
                             continue;
                         }
@@ -41,6 +44,86 @@ impl FunctionPass for ConstantFoldPass {
                         *instr = IrInstruction::Const {
                             dest: dest.to_string(),
                             value: Literal::Int(product),
+                            ty: ty.clone(),
+                        };
+                    }
+
+                    IrInstruction::Shl { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_shift(dest, lhs, rhs, ty, i64::checked_shl) {
+                            *instr = folded;
+                        }
+                    }
+
+                    IrInstruction::Shr { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_shift(dest, lhs, rhs, ty, i64::checked_shr) {
+                            *instr = folded;
+                        }
+                    }
+
+                    IrInstruction::UShr { dest, lhs, rhs, ty } => {
+                        let logical = |l: i64, r| (l as u64).checked_shr(r).map(|v| v as i64);
+                        if let Some(folded) = fold_shift(dest, lhs, rhs, ty, logical) {
+                            *instr = folded;
+                        }
+                    }
+
+                    IrInstruction::Xor { dest, lhs, rhs, ty } => {
+                        let Some(value) = fold_xor(lhs, rhs) else {
+                            continue;
+                        };
+
+                        *instr = IrInstruction::Const {
+                            dest: dest.to_string(),
+                            value,
+                            ty: ty.clone(),
+                        };
+                    }
+
+                    #[cfg(feature = "float")]
+                    IrInstruction::FAdd { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_float(dest, lhs, rhs, ty, |l, r| l + r) {
+                            *instr = folded;
+                        }
+                    }
+
+                    #[cfg(feature = "float")]
+                    IrInstruction::FSub { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_float(dest, lhs, rhs, ty, |l, r| l - r) {
+                            *instr = folded;
+                        }
+                    }
+
+                    #[cfg(feature = "float")]
+                    IrInstruction::FMul { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_float(dest, lhs, rhs, ty, |l, r| l * r) {
+                            *instr = folded;
+                        }
+                    }
+
+                    #[cfg(feature = "float")]
+                    IrInstruction::FDiv { dest, lhs, rhs, ty } => {
+                        if let Some(folded) = fold_float(dest, lhs, rhs, ty, |l, r| l / r) {
+                            *instr = folded;
+                        }
+                    }
+
+                    // a known condition picks one side, the select becomes a copy
+                    IrInstruction::Select {
+                        dest,
+                        cond,
+                        true_val,
+                        false_val,
+                        ty,
+                    } => {
+                        let Ok(cond) = cond.parse::<bool>() else {
+                            continue;
+                        };
+
+                        let rhs = if cond { true_val } else { false_val };
+                        *instr = IrInstruction::Assign {
+                            lhs: dest.to_string(),
+                            rhs: AssignRhs::from_operand(rhs),
+                            ty: ty.clone(),
                         };
                     }
                     _ => {}
@@ -50,3 +133,55 @@ impl FunctionPass for ConstantFoldPass {
         true
     }
 }
+
+/// Const for a shift with literal operands, None if either isn't one or the
+/// amount is out of range
+fn fold_shift(
+    dest: &str,
+    lhs: &str,
+    rhs: &str,
+    ty: &Option<IrType>,
+    shift: impl Fn(i64, u32) -> Option<i64>,
+) -> Option<IrInstruction> {
+    let left = lhs.parse::<i64>().ok()?;
+    let right = rhs.parse::<u32>().ok()?;
+
+    Some(IrInstruction::Const {
+        dest: dest.to_string(),
+        value: Literal::Int(shift(left, right)?),
+        ty: ty.clone(),
+    })
+}
+
+/// Bitwise on ints, on bools it's true when the operands differ
+fn fold_xor(lhs: &str, rhs: &str) -> Option<Literal> {
+    if let (Ok(l), Ok(r)) = (lhs.parse::<i64>(), rhs.parse::<i64>()) {
+        return Some(Literal::Int(l ^ r));
+    }
+
+    let left = lhs.parse::<bool>().ok()?;
+    let right = rhs.parse::<bool>().ok()?;
+    Some(Literal::Bool(left != right))
+}
+
+/// Const for float arithmetic on literal operands. `inf` and `nan` parse as
+/// floats too but could just as well be names, so only finite values count,
+/// for the operands and for the result
+#[cfg(feature = "float")]
+fn fold_float(
+    dest: &str,
+    lhs: &str,
+    rhs: &str,
+    ty: &Option<IrType>,
+    op: impl Fn(f64, f64) -> f64,
+) -> Option<IrInstruction> {
+    let finite = |x: f64| x.is_finite().then_some(x);
+    let left = lhs.parse::<f64>().ok().and_then(finite)?;
+    let right = rhs.parse::<f64>().ok().and_then(finite)?;
+
+    Some(IrInstruction::Const {
+        dest: dest.to_string(),
+        value: Literal::Float(finite(op(left, right))?),
+        ty: ty.clone(),
+    })
+}
@@ -1,4 +1,5 @@
-use crate::pass_manager::FunctionPass;
+use crate::pass_manager::TransformPass;
+use ir::AssignRhs;
 use ir::IrFunction;
 use ir::IrInstruction;
 use ir::cfg::Literal;
@@ -7,7 +8,7 @@ use std::collections::HashMap;
 /// Intraprocedural Constant Propagation
 pub struct ConstantPropagationPass {}
 
-impl FunctionPass for ConstantPropagationPass {
+impl TransformPass for ConstantPropagationPass {
     fn name(&self) -> &str {
         "ConstantPropagationPass"
     }
@@ -18,13 +19,15 @@ impl FunctionPass for ConstantPropagationPass {
             for instr in blocks.instrs.iter_mut() {
                 match instr {
                     // TODO: Need to add more patterns to match for
-                    IrInstruction::Const { dest, value } => {
+                    IrInstruction::Const { dest, value, .. } => {
                         const_env.insert(dest.clone(), Some(value.clone()));
                     }
 
                     IrInstruction::Assign { rhs, .. } => {
-                        if let Some(Literal::Int(j)) = const_env.get(rhs).cloned().flatten() {
-                            *rhs = j.to_string();
+                        if let AssignRhs::Var(var) = rhs
+                            && let Some(lit) = const_env.get(var).cloned().flatten()
+                        {
+                            *rhs = AssignRhs::Const(lit);
                         }
                     }
 
@@ -54,6 +57,49 @@ impl FunctionPass for ConstantPropagationPass {
                         }
                     }
 
+                    // x && false and x || true are known without x, so both
+                    // short-circuit into a Const that propagates further
+                    IrInstruction::And { dest, lhs, rhs, ty } => {
+                        substitute_bool(&const_env, lhs);
+                        substitute_bool(&const_env, rhs);
+
+                        if let Some(b) = fold_logic(lhs, rhs, false) {
+                            const_env.insert(dest.clone(), Some(Literal::Bool(b)));
+                            *instr = IrInstruction::Const {
+                                dest: dest.clone(),
+                                value: Literal::Bool(b),
+                                ty: ty.clone(),
+                            };
+                        }
+                    }
+
+                    IrInstruction::Or { dest, lhs, rhs, ty } => {
+                        substitute_bool(&const_env, lhs);
+                        substitute_bool(&const_env, rhs);
+
+                        if let Some(b) = fold_logic(lhs, rhs, true) {
+                            const_env.insert(dest.clone(), Some(Literal::Bool(b)));
+                            *instr = IrInstruction::Const {
+                                dest: dest.clone(),
+                                value: Literal::Bool(b),
+                                ty: ty.clone(),
+                            };
+                        }
+                    }
+
+                    IrInstruction::Not { dest, args, ty } => {
+                        substitute_bool(&const_env, args);
+
+                        if let Ok(b) = args.parse::<bool>() {
+                            const_env.insert(dest.clone(), Some(Literal::Bool(!b)));
+                            *instr = IrInstruction::Const {
+                                dest: dest.clone(),
+                                value: Literal::Bool(!b),
+                                ty: ty.clone(),
+                            };
+                        }
+                    }
+
                     IrInstruction::Br { cond, .. } => {
                         if let Some(Literal::Bool(j)) = const_env.get(cond).cloned().flatten() {
                             *cond = j.to_string();
@@ -74,3 +120,25 @@ impl FunctionPass for ConstantPropagationPass {
         true
     }
 }
+
+/// Replaces `name` with its value if it's a known bool
+fn substitute_bool(const_env: &HashMap<String, Option<Literal>>, name: &mut String) {
+    if let Some(Literal::Bool(b)) = const_env.get(name).cloned().flatten() {
+        *name = b.to_string();
+    }
+}
+
+/// Result of an and (`absorbing` = false) or an or (`absorbing` = true), if
+/// the operands decide it: one absorbing literal is enough, otherwise both
+/// have to be literals
+fn fold_logic(lhs: &str, rhs: &str, absorbing: bool) -> Option<bool> {
+    let operands = [lhs.parse::<bool>().ok(), rhs.parse::<bool>().ok()];
+
+    if operands.contains(&Some(absorbing)) {
+        Some(absorbing)
+    } else if operands == [Some(!absorbing); 2] {
+        Some(!absorbing)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,67 @@
+use crate::pass_manager::ModulePass;
+use ir::{IrInstruction, IrModule};
+use std::collections::{HashMap, HashSet};
+
+/// Removes every function that can't be reached through calls from `main` or
+/// one of the `exports`
+///
+/// A module with neither (e.g. a library without an export list) is left
+/// alone, every function in it could still be called from the outside.
+#[derive(Debug, Default)]
+pub struct DeadFunctionElimPass {
+    pub exports: Vec<String>,
+}
+
+impl DeadFunctionElimPass {
+    pub fn new(exports: Vec<String>) -> Self {
+        Self { exports }
+    }
+}
+
+impl ModulePass for DeadFunctionElimPass {
+    fn name(&self) -> &str {
+        "DeadFunctionElimPass"
+    }
+
+    fn run_on_module(&mut self, module: &mut IrModule) -> bool {
+        let graph = call_graph(module);
+
+        let mut worklist: Vec<&str> = std::iter::once("main")
+            .chain(self.exports.iter().map(|e| e.as_str()))
+            .filter(|name| module.get_function(name).is_some())
+            .collect();
+        if worklist.is_empty() {
+            return false;
+        }
+
+        let mut live: HashSet<String> = HashSet::new();
+        while let Some(name) = worklist.pop() {
+            if !live.insert(name.to_string()) {
+                continue;
+            }
+            if let Some(callees) = graph.get(name) {
+                worklist.extend(callees.iter().map(|c| c.as_str()));
+            }
+        }
+
+        let before = module.functions.len();
+        module.retain_functions(|f| live.contains(&f.name));
+        before != module.functions.len()
+    }
+}
+
+/// Maps every function to the functions it calls
+fn call_graph(module: &IrModule) -> HashMap<String, HashSet<String>> {
+    let mut graph: HashMap<String, HashSet<String>> = HashMap::new();
+
+    for func in &module.functions {
+        let callees = graph.entry(func.name.clone()).or_default();
+        for instr in func.blocks.iter().flat_map(|b| b.instrs.iter()) {
+            if let IrInstruction::Call { target_func, .. } = instr {
+                callees.insert(target_func.clone());
+            }
+        }
+    }
+
+    graph
+}
@@ -1,5 +1,5 @@
 use crate::liveness::compute_liveness;
-use crate::pass_manager::FunctionPass;
+use crate::pass_manager::TransformPass;
 use ir::IrFunction;
 use ir::IrInstruction;
 use std::collections::HashSet;
@@ -7,22 +7,23 @@ use std::collections::HashSet;
 /// Intraprocedural Constant Propagation
 pub struct DeadCodeRemovalPass {}
 
-impl FunctionPass for DeadCodeRemovalPass {
+impl TransformPass for DeadCodeRemovalPass {
     fn name(&self) -> &str {
         "DeadCodeRemovalPass"
     }
 
     fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
-        eliminate_deadcode(function);
-        true
+        eliminate_deadcode(function)
     }
 }
 
-fn eliminate_deadcode(func: &mut IrFunction) {
+/// Returns whether any instruction was removed
+fn eliminate_deadcode(func: &mut IrFunction) -> bool {
     // iterate over each block then for each block,
     // iterate over them in reverse
 
     let (live_out, _live_in) = compute_liveness(func);
+    let mut removed = false;
 
     for (b, block) in func.blocks.iter_mut().enumerate() {
         let mut live: HashSet<String> = live_out[b].clone();
@@ -31,8 +32,9 @@ fn eliminate_deadcode(func: &mut IrFunction) {
         for instr in block.instrs.iter().rev() {
             // check to see if a definition is live
             if let Some(d) = instr.defs().first() {
-                // if not live, then skip
-                if !live.contains(d) {
+                // if not live, then skip, unless it does more than define `d`
+                if !live.contains(d) && !instr.has_side_effects() {
+                    removed = true;
                     continue;
                 }
                 // in case we had kept a old definition, we want to remove
@@ -48,4 +50,6 @@ fn eliminate_deadcode(func: &mut IrFunction) {
         new_instrs.reverse();
         block.instrs = new_instrs;
     }
+
+    removed
 }
@@ -0,0 +1,21 @@
+use crate::pass_manager::AnalysisPass;
+use ir::{IrFunction, PerFunctionSSAData};
+
+/// Immediate dominators of the function, blocks that can't be reached from
+/// the entry have none
+pub struct DominatorAnalysis {}
+
+impl AnalysisPass for DominatorAnalysis {
+    type Output = PerFunctionSSAData;
+
+    fn name(&self) -> &str {
+        "DominatorAnalysis"
+    }
+
+    fn run_on_function(&mut self, function: &IrFunction) -> Self::Output {
+        let mut ssa = PerFunctionSSAData::default();
+        ssa.compute_idom(function)
+            .expect("unreachable blocks are skipped, computing idoms can't fail");
+        ssa
+    }
+}
@@ -0,0 +1,227 @@
+use crate::dominators::DominatorAnalysis;
+use crate::pass_manager::{AnalysisCache, TransformPass};
+use crate::ssa_construction::is_ssa;
+use ir::{AssignRhs, BlockID, IrFunction, IrInstruction};
+use std::collections::HashMap;
+
+/// Expression key, the opcode along with the value numbers of its operands
+type ExprKey = (String, Vec<usize>);
+
+/// Global Value Numbering
+///
+/// Walks the blocks in RPO handing out value numbers. An expression that was
+/// already computed by a dominating definition is replaced with a copy of it.
+/// The function is expected to be in SSA form.
+pub struct GVNPass {}
+
+impl TransformPass for GVNPass {
+    fn name(&self) -> &str {
+        "GVNPass"
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        vec!["SSAConstructionPass"]
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        self.run_with_analyses(function, &mut AnalysisCache::default())
+    }
+
+    fn run_with_analyses(
+        &mut self,
+        function: &mut IrFunction,
+        analyses: &mut AnalysisCache,
+    ) -> bool {
+        // value numbers only hold when every name is written exactly once
+        if function.blocks.is_empty() || !is_ssa(function) {
+            return false;
+        }
+
+        // unreachable blocks aren't part of the RPO and never get touched
+        let ssa = analyses.get_or_compute(&mut DominatorAnalysis {}, function);
+
+        let mut numbering = ValueNumbering::default();
+        // every definition of an expression, along with the block it lives in
+        let mut table: HashMap<ExprKey, Vec<(String, BlockID)>> = HashMap::new();
+        let mut changed = false;
+
+        for b in function.rpo() {
+            // redundant phis, and the copies replacing them after the phi group
+            let mut dead_phis: Vec<usize> = Vec::new();
+            let mut phi_copies: Vec<IrInstruction> = Vec::new();
+
+            for (idx, instr) in function.blocks[b].instrs.iter_mut().enumerate() {
+                let Some((dest, ty)) = instr.defs_typed().into_iter().next() else {
+                    continue;
+                };
+
+                // copies just forward the value number of their source
+                if let IrInstruction::Assign { rhs, .. } = instr {
+                    let vn = match rhs {
+                        AssignRhs::Var(var) => numbering.operand(var),
+                        AssignRhs::Const(lit) => numbering.operand(&lit.to_string()),
+                    };
+                    numbering.vn.insert(dest, vn);
+                    continue;
+                }
+
+                let Some(key) = numbering.expression(instr, b) else {
+                    numbering.fresh(&dest);
+                    continue;
+                };
+
+                let leader = table.get(&key).and_then(|defs| {
+                    defs.iter()
+                        .find(|(_, def_block)| ssa.dominates(*def_block, b))
+                        .map(|(name, _)| name.clone())
+                });
+
+                match leader {
+                    Some(leader) => {
+                        let vn = numbering.vn[&leader];
+                        numbering.vn.insert(dest.clone(), vn);
+                        let copy = IrInstruction::Assign {
+                            lhs: dest,
+                            rhs: AssignRhs::Var(leader),
+                            ty: Some(ty),
+                        };
+                        // a copy in the middle of the phis would break them up
+                        if matches!(instr, IrInstruction::Phi { .. }) {
+                            dead_phis.push(idx);
+                            phi_copies.push(copy);
+                        } else {
+                            *instr = copy;
+                        }
+                        changed = true;
+                    }
+                    None => {
+                        numbering.fresh(&dest);
+                        table.entry(key).or_default().push((dest, b));
+                    }
+                }
+            }
+
+            let block = &mut function.blocks[b];
+            for idx in dead_phis.into_iter().rev() {
+                block.instrs.remove(idx);
+            }
+            for copy in phi_copies.into_iter().rev() {
+                block.prepend_instr(copy);
+            }
+        }
+
+        changed
+    }
+}
+
+#[derive(Default)]
+struct ValueNumbering {
+    vn: HashMap<String, usize>,
+    literals: HashMap<String, usize>,
+    next: usize,
+}
+
+impl ValueNumbering {
+    fn fresh(&mut self, name: &str) -> usize {
+        let vn = self.next;
+        self.next += 1;
+        self.vn.insert(name.to_string(), vn);
+        vn
+    }
+
+    /// Value number of an operand, literals share a number with equal literals
+    fn operand(&mut self, name: &str) -> usize {
+        if let Some(&vn) = self.vn.get(name) {
+            return vn;
+        }
+
+        if name.parse::<i64>().is_ok() || name.parse::<bool>().is_ok() {
+            if let Some(&vn) = self.literals.get(name) {
+                return vn;
+            }
+            let vn = self.next;
+            self.next += 1;
+            self.literals.insert(name.to_string(), vn);
+            return vn;
+        }
+
+        // Args or names coming in through a back edge we haven't reached yet
+        self.fresh(name)
+    }
+
+    fn expression(&mut self, instr: &IrInstruction, block: BlockID) -> Option<ExprKey> {
+        let key = match instr {
+            IrInstruction::Add { lhs, rhs, .. }
+            | IrInstruction::Mul { lhs, rhs, .. }
+            | IrInstruction::Eq { lhs, rhs, .. }
+            | IrInstruction::And { lhs, rhs, .. }
+            | IrInstruction::Or { lhs, rhs, .. }
+            | IrInstruction::Xor { lhs, rhs, .. } => {
+                // commutative, order the operands so `a + b` and `b + a` match
+                let mut ops = vec![self.operand(lhs), self.operand(rhs)];
+                ops.sort();
+                (opcode(instr).to_string(), ops)
+            }
+
+            IrInstruction::Sub { lhs, rhs, .. }
+            | IrInstruction::Div { lhs, rhs, .. }
+            | IrInstruction::Shl { lhs, rhs, .. }
+            | IrInstruction::Shr { lhs, rhs, .. }
+            | IrInstruction::UShr { lhs, rhs, .. }
+            | IrInstruction::Lt { lhs, rhs, .. }
+            | IrInstruction::Gt { lhs, rhs, .. }
+            | IrInstruction::Le { lhs, rhs, .. }
+            | IrInstruction::Ge { lhs, rhs, .. } => (
+                opcode(instr).to_string(),
+                vec![self.operand(lhs), self.operand(rhs)],
+            ),
+
+            IrInstruction::Not { args, .. } => ("not".to_string(), vec![self.operand(args)]),
+
+            IrInstruction::Const { value, .. } => {
+                ("const".to_string(), vec![self.operand(&value.to_string())])
+            }
+
+            // Phis are only congruent to phis of the same block whose
+            // sources are pairwise congruent, pred by pred
+            IrInstruction::Phi { sources, .. } => {
+                let mut sources: Vec<&(BlockID, Option<String>)> = sources.iter().collect();
+                sources.sort_by_key(|(p, _)| *p);
+
+                let mut ops = vec![block];
+                for (pred, src) in sources {
+                    match src {
+                        Some(s) => ops.extend([*pred, self.operand(s)]),
+                        None => return None,
+                    }
+                }
+                ("phi".to_string(), ops)
+            }
+
+            _ => return None,
+        };
+
+        Some(key)
+    }
+}
+
+fn opcode(instr: &IrInstruction) -> &'static str {
+    match instr {
+        IrInstruction::Add { .. } => "add",
+        IrInstruction::Mul { .. } => "mul",
+        IrInstruction::Sub { .. } => "sub",
+        IrInstruction::Div { .. } => "div",
+        IrInstruction::Shl { .. } => "shl",
+        IrInstruction::Shr { .. } => "shr",
+        IrInstruction::UShr { .. } => "ushr",
+        IrInstruction::Eq { .. } => "eq",
+        IrInstruction::Lt { .. } => "lt",
+        IrInstruction::Gt { .. } => "gt",
+        IrInstruction::Le { .. } => "le",
+        IrInstruction::Ge { .. } => "ge",
+        IrInstruction::And { .. } => "and",
+        IrInstruction::Or { .. } => "or",
+        IrInstruction::Xor { .. } => "xor",
+        _ => "",
+    }
+}
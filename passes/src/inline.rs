@@ -0,0 +1,254 @@
+use crate::pass_manager::ModulePass;
+use ir::{AssignRhs, BlockID, CallDest, IrFunction, IrInstruction, IrModule};
+use std::collections::{HashMap, HashSet};
+
+/// Inlines calls to functions with fewer than `threshold` instructions
+///
+/// The calling block gets split at the call: everything after it moves into a
+/// continuation block, the arguments are copied into the callee's parameters
+/// and control jumps into a clone of the callee's body. Every `Ret` of the
+/// clone jumps to the continuation, with the returned value copied (or merged
+/// through a phi, for several returns) into the call's dest.
+///
+/// Names and labels of the clone get an `.inl<n>` suffix, `n` being unique for
+/// each call site in the caller, so SSA names never collide.
+#[derive(Debug)]
+pub struct InlinePass {
+    pub threshold: usize,
+}
+
+impl Default for InlinePass {
+    fn default() -> Self {
+        Self { threshold: 16 }
+    }
+}
+
+impl InlinePass {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+}
+
+impl ModulePass for InlinePass {
+    fn name(&self) -> &str {
+        "InlinePass"
+    }
+
+    fn run_on_module(&mut self, module: &mut IrModule) -> bool {
+        let targets: HashSet<&str> = module
+            .functions
+            .iter()
+            .flat_map(|f| f.blocks.iter().flat_map(|b| b.instrs.iter()))
+            .filter_map(|instr| match instr {
+                IrInstruction::Call { target_func, .. } => Some(target_func.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        // callee bodies as they were before anything got inlined into them
+        let callees: HashMap<String, IrFunction> = targets
+            .into_iter()
+            .filter_map(|name| module.get_function(name))
+            .filter(|f| self.is_inlinable(f))
+            .map(|f| (f.name.clone(), f.clone()))
+            .collect();
+
+        let mut changed = false;
+        for caller in module.functions.iter_mut() {
+            changed |= inline_calls(caller, &callees);
+        }
+        changed
+    }
+}
+
+impl InlinePass {
+    fn is_inlinable(&self, func: &IrFunction) -> bool {
+        let size: usize = func.blocks.iter().map(|b| b.instrs.len()).sum();
+
+        // a clone of the entry can't get an extra pred without its phis
+        // growing a source nobody defines
+        !func.blocks.is_empty()
+            && func.blocks[func.entry_block()].preds.is_empty()
+            && size < self.threshold
+    }
+}
+
+/// Inlines every call site of `caller` that targets one of `callees`. Blocks
+/// cloned out of a callee are not scanned again, so recursion stops after one
+/// level
+fn inline_calls(caller: &mut IrFunction, callees: &HashMap<String, IrFunction>) -> bool {
+    let mut cloned: HashSet<BlockID> = HashSet::new();
+    let mut site = 0;
+    let mut changed = false;
+
+    let mut b = 0;
+    while b < caller.blocks.len() {
+        if cloned.contains(&b) {
+            b += 1;
+            continue;
+        }
+
+        let call = caller.blocks[b].instrs.iter().position(|instr| {
+            matches!(instr, IrInstruction::Call { target_func, args, .. }
+                if *target_func != caller.name
+                    && callees.get(target_func).is_some_and(|c| c.args.len() == args.len()))
+        });
+
+        match call {
+            Some(i) => {
+                let first = caller.blocks.len();
+                let callee = match &caller.blocks[b].instrs[i] {
+                    IrInstruction::Call { target_func, .. } => &callees[target_func],
+                    _ => unreachable!(),
+                };
+                // an earlier run may already have used this suffix
+                while !labels_free(caller, b, callee, site) {
+                    site += 1;
+                }
+                inline_call_site(caller, b, i, callee, site);
+                site += 1;
+                changed = true;
+
+                // the continuation is the last block added, it still has to
+                // be scanned for calls
+                cloned.extend(first..caller.blocks.len() - 1);
+            }
+            None => b += 1,
+        }
+    }
+
+    changed
+}
+
+/// Checks that none of the labels inlining at `site` would add are taken yet
+fn labels_free(caller: &IrFunction, b: BlockID, callee: &IrFunction, site: usize) -> bool {
+    let suffix = format!(".inl{}", site);
+    let cont_label = format!("{}.cont{}", caller.blocks[b].label, suffix);
+
+    callee
+        .blocks
+        .iter()
+        .map(|block| format!("{}{}", block.label, suffix))
+        .chain(std::iter::once(cont_label))
+        .all(|label| caller.block_index(&label).is_none())
+}
+
+fn inline_call_site(
+    caller: &mut IrFunction,
+    b: BlockID,
+    i: usize,
+    callee: &IrFunction,
+    site: usize,
+) {
+    let suffix = format!(".inl{}", site);
+
+    let IrInstruction::Call { args, dest, ty, .. } = caller.blocks[b].instrs[i].clone() else {
+        unreachable!("inline_call_site is only called on calls");
+    };
+
+    // names local to the callee, anything else (literals) stays as is
+    let mut locals: HashSet<String> = callee.args.iter().map(|(a, _)| a.clone()).collect();
+    for instr in callee.blocks.iter().flat_map(|b| b.instrs.iter()) {
+        locals.extend(instr.defs().iter().cloned());
+    }
+    let rename = |name: &mut String| {
+        if locals.contains(name) {
+            name.push_str(&suffix);
+        }
+    };
+
+    // Clone the callee's blocks
+    let base = caller.blocks.len();
+    for block in callee.blocks.iter() {
+        let idx = caller
+            .add_block(&format!("{}{}", block.label, suffix))
+            .expect("labels_free checked the label");
+        let clone = &mut caller.blocks[idx];
+
+        clone.preds = block.preds.iter().map(|p| p + base).collect();
+        clone.succs = block.succs.iter().map(|s| s + base).collect();
+        clone.instrs = block.instrs.clone();
+        for instr in clone.instrs.iter_mut() {
+            instr.names_mut().into_iter().for_each(rename);
+            match instr {
+                IrInstruction::Phi { sources, .. } => {
+                    for (p, _) in sources.iter_mut() {
+                        *p += base;
+                    }
+                }
+                IrInstruction::Jmp { label } => label.push_str(&suffix),
+                IrInstruction::Br {
+                    then_lbl, else_lbl, ..
+                } => {
+                    then_lbl.push_str(&suffix);
+                    else_lbl.push_str(&suffix);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Split the calling block, the rest of it runs once the callee returns
+    let cont_label = format!("{}.cont{}", caller.blocks[b].label, suffix);
+    let cont = caller
+        .add_block(&cont_label)
+        .expect("labels_free checked the label");
+    let rest = caller.blocks[b].instrs.split_off(i + 1);
+    caller.blocks[b].instrs.pop();
+    caller.blocks[cont].instrs = rest;
+
+    let succs = std::mem::take(&mut caller.blocks[b].succs);
+    for &s in succs.iter() {
+        caller.blocks[s].replace_pred(b, cont);
+    }
+    caller.blocks[cont].succs = succs;
+
+    // Copy the arguments into the parameters and enter the clone
+    for ((param, param_ty), arg) in callee.args.iter().zip(args) {
+        caller.blocks[b].instrs.push(IrInstruction::Assign {
+            lhs: format!("{}{}", param, suffix),
+            rhs: AssignRhs::from_operand(&arg),
+            ty: Some(param_ty.clone()),
+        });
+    }
+    let entry = base + callee.entry_block();
+    let entry_label = caller.blocks[entry].label.clone();
+    caller.blocks[b]
+        .instrs
+        .push(IrInstruction::Jmp { label: entry_label });
+    caller.add_edge(b, entry);
+
+    // Every return jumps to the continuation
+    let mut returned: Vec<(BlockID, Option<String>)> = Vec::new();
+    for r in base..cont {
+        let Some(IrInstruction::Ret { args }) = caller.blocks[r].terminator().cloned() else {
+            continue;
+        };
+
+        caller.blocks[r].instrs.pop();
+        caller.blocks[r].instrs.push(IrInstruction::Jmp {
+            label: cont_label.clone(),
+        });
+        caller.add_edge(r, cont);
+        returned.push((r, args.into_iter().next()));
+    }
+
+    let CallDest::Named(dest) = dest else {
+        return;
+    };
+    match returned.as_slice() {
+        [] | [(_, None)] => {}
+        [(r, Some(value))] => {
+            caller.blocks[*r].push_instr(IrInstruction::Assign {
+                lhs: dest,
+                rhs: AssignRhs::from_operand(value),
+                ty,
+            });
+        }
+        _ => caller.blocks[cont].push_phi(IrInstruction::Phi {
+            dest,
+            sources: returned,
+            ty,
+        }),
+    }
+}
@@ -1,21 +1,52 @@
 pub mod constant_folding;
 pub mod constant_propagate;
+pub mod dead_function_elim;
 pub mod deadcode_removal;
+pub mod dominators;
+pub mod gvn;
+pub mod inline;
+pub mod licm;
 pub mod liveness;
 pub mod pass_manager;
+pub mod phi_elimination;
+pub mod range_propagation;
+pub mod sccp;
+pub mod ssa_construction;
+pub mod strength_reduction;
+pub mod unreachable_elim;
 pub use constant_folding::ConstantFoldPass;
 pub use constant_propagate::ConstantPropagationPass;
+pub use dead_function_elim::DeadFunctionElimPass;
 pub use deadcode_removal::DeadCodeRemovalPass;
+pub use dominators::DominatorAnalysis;
+pub use gvn::GVNPass;
+pub use inline::InlinePass;
+pub use licm::LICMPass;
 pub use liveness::*;
-pub use pass_manager::FunctionPass;
+pub use pass_manager::AnalysisCache;
+pub use pass_manager::AnalysisPass;
+pub use pass_manager::ModulePass;
 pub use pass_manager::PassManager;
+pub use pass_manager::PassStats;
+pub use pass_manager::TransformPass;
+pub use phi_elimination::PhiEliminationPass;
+pub use range_propagation::RangePropagationPass;
+pub use sccp::SCCPPass;
+pub use ssa_construction::SSAConstructionPass;
+pub use strength_reduction::StrengthReductionPass;
+pub use unreachable_elim::UnreachableBlockElimPass;
 
 // TODO: Need to create a proper test for this crate
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use ir::{IrBasicBlock, IrFunction, IrInstruction, SSAFormation};
+    use ir::cfg::Literal;
+    use ir::{
+        AssignRhs, CallDest, IrBasicBlock, IrFunction, IrInstruction, IrModule, IrType,
+        SSAFormation,
+    };
+    use std::collections::{HashMap, HashSet};
 
     /// Build the 5-block “diamond” CFG:
     ///
@@ -29,38 +60,18 @@ mod tests {
     ///      │
     ///      5
     fn diamond_cfg() -> IrFunction {
-        let block_labels = ["entry", "A", "B", "C", "D", "Exit"];
-
-        let preds = vec![
-            Vec::new(), // 0: entry
-            vec![0],    // 1: A
-            vec![1],    // 2: B
-            vec![1],    // 3: C
-            vec![2, 3], // 4: D (preds are 2 & 3)
-            vec![4],    // 5: exit
-        ];
-
-        let mut blocks = Vec::new();
-        for (i, &label) in block_labels.iter().enumerate() {
-            blocks.push(IrBasicBlock {
-                label: label.to_string(),
-                instrs: Vec::new(),
-                preds: preds[i].clone(),
-                succs: Vec::new(),
-            });
+        let mut func = IrFunction::new("diamond");
+        for label in ["entry", "A", "B", "C", "D", "Exit"] {
+            func.add_block(label).unwrap();
         }
 
-        let mut label_to_idx = std::collections::HashMap::new();
-        for (i, &label) in block_labels.iter().enumerate() {
-            label_to_idx.insert(label.to_string(), i);
-        }
-
-        IrFunction {
-            name: "diamond".to_string(),
-            args: Vec::new(),
-            blocks,
-            label_to_idx,
-        }
+        func.add_edge(0, 1);
+        func.add_edge(1, 2);
+        func.add_edge(1, 3);
+        func.add_edge(2, 4); // D's preds are 2 & 3
+        func.add_edge(3, 4);
+        func.add_edge(4, 5);
+        func
     }
 
     /// Helper function for creating multiple definitions for further testing
@@ -70,12 +81,14 @@ mod tests {
         // by block D (maybe)
         let def_x_b = IrInstruction::Assign {
             lhs: "x".to_string(),
-            rhs: "5".to_string(),
+            rhs: AssignRhs::Const(Literal::Int(5)),
+            ty: None,
         };
 
         let def_x_c = IrInstruction::Assign {
             lhs: "x".to_string(),
-            rhs: "10".to_string(),
+            rhs: AssignRhs::Const(Literal::Int(10)),
+            ty: None,
         };
 
         // index 2 is block B
@@ -108,4 +121,1505 @@ mod tests {
 
         assert_eq!(4, 4);
     }
+
+    #[test]
+    fn liveness_keeps_phi_sources_on_their_edge() {
+        let mut func = diamond_cfg();
+        func.blocks[2].instrs.push(IrInstruction::Assign {
+            lhs: "a".into(),
+            rhs: AssignRhs::Const(Literal::Int(5)),
+            ty: None,
+        });
+        func.blocks[3].instrs.push(IrInstruction::Assign {
+            lhs: "b".into(),
+            rhs: AssignRhs::Const(Literal::Int(10)),
+            ty: None,
+        });
+        func.blocks[4].instrs.push(IrInstruction::Phi {
+            dest: "x".into(),
+            sources: vec![(2, Some("a".into())), (3, Some("b".into()))],
+            ty: None,
+        });
+        func.blocks[5].instrs.push(IrInstruction::Ret {
+            args: vec!["x".into()],
+        });
+
+        let (live_out, live_in) = compute_liveness(&func);
+
+        // `a` only flows out of B and `b` only out of C
+        assert_eq!(live_out[2], HashSet::from(["a".to_string()]));
+        assert_eq!(live_out[3], HashSet::from(["b".to_string()]));
+        // neither is live going into the phi block itself
+        assert!(live_in[4].is_empty());
+        assert_eq!(live_in[5], HashSet::from(["x".to_string()]));
+    }
+
+    #[test]
+    fn block_uses_are_the_names_read_before_any_def() {
+        let mut block = IrBasicBlock {
+            label: "entry".into(),
+            instrs: Vec::new(),
+            preds: Vec::new(),
+            succs: Vec::new(),
+        };
+        let add = |dest: &str, lhs: &str, rhs: &str| IrInstruction::Add {
+            dest: dest.into(),
+            lhs: lhs.into(),
+            rhs: rhs.into(),
+            ty: None,
+        };
+        // `a` is read before this block writes it, `x` reads itself, and `t`
+        // is only read after it's been written
+        block.instrs.push(add("t", "a", "b"));
+        block.instrs.push(add("a", "t", "t"));
+        block.instrs.push(add("x", "x", "a"));
+
+        let (defs, uses) = compute_block_def_use(&block);
+        let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(defs, set(&["t", "a", "x"]));
+        assert_eq!(uses, set(&["a", "b", "x"]));
+    }
+
+    /// entry: c = false; br c then else
+    /// then:  a = 1; jmp join
+    /// else:  b = 2; jmp join
+    /// join:  x = phi(a, b); y = x + 3; ret y
+    fn always_false_branch() -> IrFunction {
+        let mut func = IrFunction::new("branchy");
+        let entry = func.add_block("entry").unwrap();
+        let then_b = func.add_block("then").unwrap();
+        let else_b = func.add_block("else").unwrap();
+        let join = func.add_block("join").unwrap();
+
+        func.add_edge(entry, then_b);
+        func.add_edge(entry, else_b);
+        func.add_edge(then_b, join);
+        func.add_edge(else_b, join);
+
+        let instrs = [
+            (
+                entry,
+                IrInstruction::Const {
+                    dest: "c".into(),
+                    value: Literal::Bool(false),
+                    ty: None,
+                },
+            ),
+            (
+                entry,
+                IrInstruction::Br {
+                    cond: "c".into(),
+                    then_lbl: "then".into(),
+                    else_lbl: "else".into(),
+                },
+            ),
+            (
+                then_b,
+                IrInstruction::Const {
+                    dest: "a".into(),
+                    value: Literal::Int(1),
+                    ty: None,
+                },
+            ),
+            (
+                then_b,
+                IrInstruction::Jmp {
+                    label: "join".into(),
+                },
+            ),
+            (
+                else_b,
+                IrInstruction::Const {
+                    dest: "b".into(),
+                    value: Literal::Int(2),
+                    ty: None,
+                },
+            ),
+            (
+                else_b,
+                IrInstruction::Jmp {
+                    label: "join".into(),
+                },
+            ),
+            (
+                join,
+                IrInstruction::Phi {
+                    dest: "x".into(),
+                    sources: vec![(then_b, Some("a".into())), (else_b, Some("b".into()))],
+                    ty: None,
+                },
+            ),
+            (
+                join,
+                IrInstruction::Add {
+                    dest: "y".into(),
+                    lhs: "x".into(),
+                    rhs: "3".into(),
+                    ty: None,
+                },
+            ),
+            (
+                join,
+                IrInstruction::Ret {
+                    args: vec!["y".into()],
+                },
+            ),
+        ];
+        for (b, instr) in instrs.iter() {
+            func.append_instr(*b, instr);
+        }
+
+        func
+    }
+
+    #[test]
+    fn sccp_resolves_phi_through_dead_branch() {
+        let mut func = always_false_branch();
+        let mut pass = SCCPPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        // the branch is folded into a jump to the live edge
+        assert!(matches!(
+            &func.blocks[0].instrs[1],
+            IrInstruction::Jmp { label } if label == "else"
+        ));
+        assert_eq!(func.blocks[0].succs, vec![2]);
+        assert!(func.blocks[1].preds.is_empty());
+
+        // the phi only sees `b`, so both `x` and `y` become constants
+        assert!(matches!(
+            &func.blocks[3].instrs[0],
+            IrInstruction::Const { dest, value: Literal::Int(2), .. } if dest == "x"
+        ));
+        assert!(matches!(
+            &func.blocks[3].instrs[1],
+            IrInstruction::Const { dest, value: Literal::Int(5), .. } if dest == "y"
+        ));
+    }
+
+    #[test]
+    fn sccp_puts_folded_phis_after_the_phi_group() {
+        let mut func = always_false_branch();
+        func.args = vec![("p".into(), IrType::I64)];
+        func.blocks[3].push_phi(IrInstruction::Phi {
+            dest: "z".into(),
+            sources: vec![(1, Some("a".into())), (2, Some("p".into()))],
+            ty: None,
+        });
+
+        let mut pass = SCCPPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        // `z` only comes from an argument and stays a phi, ahead of the folded `x`
+        assert!(matches!(
+            &func.blocks[3].instrs[0],
+            IrInstruction::Phi { dest, .. } if dest == "z"
+        ));
+        assert!(matches!(
+            &func.blocks[3].instrs[1],
+            IrInstruction::Const { dest, value: Literal::Int(2), .. } if dest == "x"
+        ));
+    }
+
+    #[test]
+    fn sccp_leaves_branches_to_unknown_labels_alone() {
+        let mut func = always_false_branch();
+        if let IrInstruction::Br { else_lbl, .. } = &mut func.blocks[0].instrs[1] {
+            *else_lbl = "nowhere".into();
+        }
+
+        let mut pass = SCCPPass {};
+        pass.run_on_function(&mut func);
+        assert!(matches!(
+            &func.blocks[0].instrs[1],
+            IrInstruction::Br { .. }
+        ));
+        assert_eq!(func.blocks[0].succs, vec![1, 2]);
+    }
+
+    /// entry:  i0 = 0; jmp header
+    /// header: i = phi(i0, i2); c = i < 10; br c body exit
+    /// body:   t = a + b; u = t * 2; i2 = i + u; jmp header
+    /// exit:   ret i
+    fn counted_loop() -> IrFunction {
+        let mut func = IrFunction::new("counted");
+        func.args = vec![("a".into(), IrType::I64), ("b".into(), IrType::I64)];
+        let entry = func.add_block("entry").unwrap();
+        let header = func.add_block("header").unwrap();
+        let body = func.add_block("body").unwrap();
+        let exit = func.add_block("exit").unwrap();
+
+        func.add_edge(entry, header);
+        func.add_edge(header, body);
+        func.add_edge(header, exit);
+        func.add_edge(body, header);
+
+        func.append_instr(
+            entry,
+            &IrInstruction::Const {
+                dest: "i0".into(),
+                value: Literal::Int(0),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            entry,
+            &IrInstruction::Jmp {
+                label: "header".into(),
+            },
+        );
+        func.append_instr(
+            header,
+            &IrInstruction::Phi {
+                dest: "i".into(),
+                sources: vec![(entry, Some("i0".into())), (body, Some("i2".into()))],
+                ty: None,
+            },
+        );
+        func.append_instr(
+            header,
+            &IrInstruction::Lt {
+                dest: "c".into(),
+                lhs: "i".into(),
+                rhs: "10".into(),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            header,
+            &IrInstruction::Br {
+                cond: "c".into(),
+                then_lbl: "body".into(),
+                else_lbl: "exit".into(),
+            },
+        );
+        func.append_instr(
+            body,
+            &IrInstruction::Add {
+                dest: "t".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            body,
+            &IrInstruction::Mul {
+                dest: "u".into(),
+                lhs: "t".into(),
+                rhs: "2".into(),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            body,
+            &IrInstruction::Add {
+                dest: "i2".into(),
+                lhs: "i".into(),
+                rhs: "u".into(),
+                ty: None,
+            },
+        );
+        func.append_instr(
+            body,
+            &IrInstruction::Jmp {
+                label: "header".into(),
+            },
+        );
+        func.append_instr(
+            exit,
+            &IrInstruction::Ret {
+                args: vec!["i".into()],
+            },
+        );
+
+        func
+    }
+
+    #[test]
+    fn licm_hoists_invariants_into_preheader() {
+        let mut func = counted_loop();
+        let mut pass = LICMPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        let preheader = func.block_index(&"header.preheader".to_string()).unwrap();
+        let hoisted: Vec<String> = func.blocks[preheader]
+            .instrs
+            .iter()
+            .flat_map(|i| i.defs().to_vec())
+            .collect();
+        assert_eq!(hoisted, vec!["t".to_string(), "u".to_string()]);
+
+        // `i2` depends on the induction variable and has to stay put
+        assert_eq!(func.blocks[2].instrs.len(), 2);
+
+        assert_eq!(func.blocks[0].succs, vec![preheader]);
+        assert_eq!(func.blocks[1].preds, vec![preheader, 2]);
+        assert!(matches!(
+            func.blocks[0].instrs.last(),
+            Some(IrInstruction::Jmp { label }) if label == "header.preheader"
+        ));
+    }
+
+    #[test]
+    fn licm_only_hoists_divisions_that_cannot_trap_early() {
+        let div = |dest: &str, rhs: &str| IrInstruction::Div {
+            dest: dest.into(),
+            lhs: "a".into(),
+            rhs: rhs.into(),
+            ty: None,
+        };
+        let mut func = counted_loop();
+        // the body doesn't run on the way out through the header
+        func.blocks[2].push_instr(div("q", "b"));
+        func.blocks[2].push_instr(div("h", "2"));
+        func.blocks[2].push_instr(div("z", "0"));
+        // the header runs before every exit
+        func.blocks[1].prepend_instr(div("r", "b"));
+
+        let mut pass = LICMPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        let preheader = func.block_index(&"header.preheader".to_string()).unwrap();
+        let hoisted: Vec<String> = func.blocks[preheader]
+            .instrs
+            .iter()
+            .flat_map(|i| i.defs().to_vec())
+            .collect();
+        assert!(hoisted.contains(&"r".to_string()));
+        assert!(hoisted.contains(&"h".to_string()));
+        assert!(!hoisted.contains(&"q".to_string()));
+        assert!(!hoisted.contains(&"z".to_string()));
+    }
+
+    #[test]
+    fn licm_skips_functions_with_unreachable_blocks() {
+        let mut func = counted_loop();
+        let orphan = func.add_block("orphan").unwrap();
+        func.append_instr(orphan, &IrInstruction::Ret { args: Vec::new() });
+
+        let mut pass = LICMPass {};
+        assert!(!pass.run_on_function(&mut func));
+        assert!(func.block_index(&"header.preheader".to_string()).is_none());
+    }
+
+    /// entry: t1 = a + b; br c B C
+    /// B:     t2 = b + a; m1 = a * b; jmp D
+    /// C:     m2 = a * b; jmp D
+    /// D:     p1 = phi(t2, m2); p2 = phi(t2, m2); ret p2
+    fn redundant_diamond() -> IrFunction {
+        let mut func = IrFunction::new("redundant");
+        func.args = ["a", "b", "c"]
+            .iter()
+            .map(|a| (a.to_string(), IrType::I64))
+            .collect();
+        for label in ["entry", "B", "C", "D"] {
+            func.add_block(label).unwrap();
+        }
+        func.add_edge(0, 1);
+        func.add_edge(0, 2);
+        func.add_edge(1, 3);
+        func.add_edge(2, 3);
+
+        let binop = |dest: &str, lhs: &str, rhs: &str, mul: bool| {
+            let (dest, lhs, rhs) = (dest.to_string(), lhs.to_string(), rhs.to_string());
+            if mul {
+                IrInstruction::Mul {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            } else {
+                IrInstruction::Add {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            }
+        };
+        let phi = |dest: &str| IrInstruction::Phi {
+            dest: dest.to_string(),
+            sources: vec![(1, Some("t2".into())), (2, Some("m2".into()))],
+            ty: None,
+        };
+
+        func.append_instr(0, &binop("t1", "a", "b", false));
+        func.append_instr(
+            0,
+            &IrInstruction::Br {
+                cond: "c".into(),
+                then_lbl: "B".into(),
+                else_lbl: "C".into(),
+            },
+        );
+        func.append_instr(1, &binop("t2", "b", "a", false));
+        func.append_instr(1, &binop("m1", "a", "b", true));
+        func.append_instr(1, &IrInstruction::Jmp { label: "D".into() });
+        func.append_instr(2, &binop("m2", "a", "b", true));
+        func.append_instr(2, &IrInstruction::Jmp { label: "D".into() });
+        func.append_instr(3, &phi("p1"));
+        func.append_instr(3, &phi("p2"));
+        func.append_instr(
+            3,
+            &IrInstruction::Ret {
+                args: vec!["p2".into()],
+            },
+        );
+
+        func
+    }
+
+    #[test]
+    fn gvn_replaces_dominated_redundancies() {
+        let mut func = redundant_diamond();
+        let mut pass = GVNPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        // commutative match, entry dominates B
+        assert!(matches!(
+            &func.blocks[1].instrs[0],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "t2" && *rhs == AssignRhs::Var("t1".into())
+        ));
+
+        // B does not dominate C, so `m2` has to be recomputed
+        assert!(matches!(
+            &func.blocks[2].instrs[0],
+            IrInstruction::Mul { .. }
+        ));
+
+        // congruent phis in the same block, the copy goes after the phi group
+        assert!(matches!(
+            &func.blocks[3].instrs[1],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "p2" && *rhs == AssignRhs::Var("p1".into())
+        ));
+    }
+
+    #[test]
+    fn gvn_keeps_phis_at_the_top_of_the_block() {
+        // p2 repeats p1, p3 is a phi of its own that comes after it
+        let mut func = redundant_diamond();
+        func.blocks[3].instrs.insert(
+            2,
+            IrInstruction::Phi {
+                dest: "p3".into(),
+                sources: vec![(1, Some("m1".into())), (2, Some("m2".into()))],
+                ty: None,
+            },
+        );
+
+        let mut pass = GVNPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        let order: Vec<(&str, bool)> = func.blocks[3]
+            .instrs
+            .iter()
+            .filter_map(|i| match i {
+                IrInstruction::Phi { dest, .. } => Some((dest.as_str(), true)),
+                IrInstruction::Assign { lhs, .. } => Some((lhs.as_str(), false)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(order, vec![("p1", true), ("p3", true), ("p2", false)]);
+    }
+
+    #[test]
+    fn gvn_leaves_unreachable_blocks_alone() {
+        let mut func = redundant_diamond();
+        let orphan = func.add_block("orphan").unwrap();
+        func.append_instr(
+            orphan,
+            &IrInstruction::Add {
+                dest: "t3".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+        );
+
+        let mut pass = GVNPass {};
+        assert!(pass.run_on_function(&mut func));
+        assert!(matches!(
+            &func.blocks[1].instrs[0],
+            IrInstruction::Assign { .. }
+        ));
+        assert!(matches!(
+            &func.blocks[orphan].instrs[0],
+            IrInstruction::Add { .. }
+        ));
+    }
+
+    #[test]
+    fn ssa_construction_reports_whether_anything_was_renamed() {
+        let mut func = IrFunction::new("nothing");
+        let entry = func.add_block("entry").unwrap();
+        func.append_instr(entry, &IrInstruction::Ret { args: Vec::new() });
+
+        let mut pass = SSAConstructionPass {};
+        assert!(!pass.run_on_function(&mut func));
+
+        let mut func = diamond_cfg();
+        create_def_sites(&mut func).unwrap();
+        assert!(pass.run_on_function(&mut func));
+
+        // already in SSA form, a second run leaves it alone
+        let before = format!("{:?}", func.blocks);
+        assert!(!pass.run_on_function(&mut func));
+        assert_eq!(format!("{:?}", func.blocks), before);
+    }
+
+    #[test]
+    fn ssa_construction_skips_unreachable_blocks() {
+        let mut func = diamond_cfg();
+        create_def_sites(&mut func).unwrap();
+        func.add_block("orphan").unwrap();
+
+        assert!(SSAConstructionPass {}.run_on_function(&mut func));
+        assert!(matches!(
+            &func.blocks[4].instrs[0],
+            IrInstruction::Phi { dest, .. } if dest != "x"
+        ));
+    }
+
+    #[test]
+    fn dead_code_removal_keeps_side_effects() {
+        let mut func = IrFunction::new("effects");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Add {
+                dest: "unused".into(),
+                lhs: "a".into(),
+                rhs: "1".into(),
+                ty: None,
+            },
+            // nobody reads `r`, but the call still has to happen
+            IrInstruction::Call {
+                target_func: "tick".into(),
+                args: Vec::new(),
+                dest: CallDest::Named("r".into()),
+                ty: Some(IrType::I64),
+            },
+            IrInstruction::Print {
+                values: vec!["a".into()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        assert!(!func.blocks[entry].instrs[0].has_side_effects());
+        assert!(
+            func.blocks[entry].instrs[1..]
+                .iter()
+                .all(|i| i.has_side_effects())
+        );
+
+        assert!(DeadCodeRemovalPass {}.run_on_function(&mut func));
+        let kept: Vec<bool> = func.blocks[entry]
+            .instrs
+            .iter()
+            .map(|i| matches!(i, IrInstruction::Call { .. } | IrInstruction::Print { .. }))
+            .collect();
+        assert_eq!(kept, vec![true, true, false]);
+
+        // nothing left to remove
+        assert!(!DeadCodeRemovalPass {}.run_on_function(&mut func));
+    }
+
+    #[test]
+    fn phi_elimination_copies_on_every_incoming_edge() {
+        let mut func = counted_loop();
+        let mut pass = PhiEliminationPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        let no_phis = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.instrs.iter())
+            .all(|i| !matches!(i, IrInstruction::Phi { .. }));
+        assert!(no_phis);
+
+        let is_copy = |instr: &IrInstruction, to: &str, from: &str| {
+            matches!(
+                instr,
+                IrInstruction::Assign { lhs, rhs: AssignRhs::Var(var), .. } if lhs == to && var == from
+            )
+        };
+        assert!(is_copy(&func.blocks[1].instrs[0], "i", "i.phi"));
+        // right before the jumps into the header
+        let entry = &func.blocks[0].instrs;
+        assert!(is_copy(&entry[entry.len() - 2], "i.phi", "i0"));
+        let body = &func.blocks[2].instrs;
+        assert!(is_copy(&body[body.len() - 2], "i.phi", "i2"));
+
+        assert!(!pass.run_on_function(&mut func));
+    }
+
+    #[test]
+    fn phi_elimination_splits_critical_edges() {
+        // entry: br c join other
+        // other: jmp join
+        // join:  x = phi(1, 2); ret x
+        let mut func = IrFunction::new("critical");
+        func.args = vec![("c".into(), IrType::Bool)];
+        let entry = func.add_block("entry").unwrap();
+        let other = func.add_block("other").unwrap();
+        let join = func.add_block("join").unwrap();
+        func.add_edge(entry, join);
+        func.add_edge(entry, other);
+        func.add_edge(other, join);
+        func.blocks[entry].instrs = vec![IrInstruction::Br {
+            cond: "c".into(),
+            then_lbl: "join".into(),
+            else_lbl: "other".into(),
+        }];
+        func.blocks[other].instrs = vec![IrInstruction::Jmp {
+            label: "join".into(),
+        }];
+        func.blocks[join].instrs = vec![
+            IrInstruction::Phi {
+                dest: "x".into(),
+                sources: vec![(entry, Some("1".into())), (other, Some("2".into()))],
+                ty: None,
+            },
+            IrInstruction::Ret {
+                args: vec!["x".into()],
+            },
+        ];
+
+        assert!(PhiEliminationPass {}.run_on_function(&mut func));
+
+        // the copy for the entry -> join edge can't go in entry, the other
+        // successor would see it too
+        assert_eq!(func.blocks[entry].instrs.len(), 1);
+        let split = func.block_index(&"entry.join.split".to_string()).unwrap();
+        assert!(matches!(
+            &func.blocks[split].instrs[0],
+            IrInstruction::Assign { lhs, rhs: AssignRhs::Const(Literal::Int(1)), .. } if lhs == "x.phi"
+        ));
+        assert!(matches!(
+            &func.blocks[other].instrs[0],
+            IrInstruction::Assign { lhs, rhs: AssignRhs::Const(Literal::Int(2)), .. } if lhs == "x.phi"
+        ));
+    }
+
+    #[test]
+    fn unreachable_blocks_are_removed() {
+        // entry jumps straight to join, `dead` still points at join
+        let mut func = IrFunction::new("dead_block");
+        let entry = func.add_block("entry").unwrap();
+        let dead = func.add_block("dead").unwrap();
+        let join = func.add_block("join").unwrap();
+        func.add_edge(entry, join);
+        func.add_edge(dead, join);
+
+        func.append_instr(
+            join,
+            &IrInstruction::Phi {
+                dest: "x".into(),
+                sources: vec![(entry, Some("e".into())), (dead, Some("d".into()))],
+                ty: None,
+            },
+        );
+
+        let mut pass = UnreachableBlockElimPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        assert_eq!(func.blocks.len(), 2);
+        assert_eq!(func.block_index(&"join".to_string()), Some(1));
+        assert_eq!(func.block_index(&"dead".to_string()), None);
+        assert_eq!(func.blocks[0].succs, vec![1]);
+        assert_eq!(func.blocks[1].preds, vec![0]);
+        assert!(matches!(
+            &func.blocks[1].instrs[0],
+            IrInstruction::Phi { sources, .. } if sources == &vec![(0, Some("e".to_string()))]
+        ));
+
+        // nothing left to remove
+        assert!(!pass.run_on_function(&mut func));
+    }
+
+    #[test]
+    fn strength_reduction_turns_pow2_into_shifts() {
+        let mut func = IrFunction::new("shifty");
+        let entry = func.add_block("entry").unwrap();
+        let binop = |dest: &str, lhs: &str, rhs: &str, div: bool| {
+            let (dest, lhs, rhs) = (dest.to_string(), lhs.to_string(), rhs.to_string());
+            if div {
+                IrInstruction::Div {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            } else {
+                IrInstruction::Mul {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            }
+        };
+        func.append_instr(entry, &binop("a", "x", "8", false));
+        func.append_instr(entry, &binop("b", "16", "x", false));
+        func.append_instr(entry, &binop("c", "x", "4", true));
+        func.append_instr(entry, &binop("d", "x", "6", false));
+        func.append_instr(entry, &binop("e", "x", "-4", true));
+
+        let mut pass = StrengthReductionPass {};
+        assert!(pass.run_on_function(&mut func));
+
+        let instrs = &func.blocks[entry].instrs;
+        assert!(matches!(
+            &instrs[0],
+            IrInstruction::Shl { lhs, rhs, .. } if lhs == "x" && rhs == "3"
+        ));
+        assert!(matches!(
+            &instrs[1],
+            IrInstruction::Shl { lhs, rhs, .. } if lhs == "x" && rhs == "4"
+        ));
+        // the divide needs the bias for negative x, the last shift defines c
+        assert!(matches!(
+            &instrs[5],
+            IrInstruction::Shr { dest, rhs, .. } if dest == "c" && rhs == "2"
+        ));
+        assert!(matches!(&instrs[6], IrInstruction::Mul { .. }));
+        assert!(matches!(&instrs[7], IrInstruction::Div { .. }));
+    }
+
+    #[test]
+    fn strength_reduced_division_truncates_negative_dividends() {
+        let mut func = IrFunction::new("div");
+        let entry = func.add_block("entry").unwrap();
+        func.append_instr(
+            entry,
+            &IrInstruction::Div {
+                dest: "q".to_string(),
+                lhs: "x".to_string(),
+                rhs: "4".to_string(),
+                ty: None,
+            },
+        );
+
+        let mut pass = StrengthReductionPass {};
+        assert!(pass.run_on_function(&mut func));
+        let instrs = &func.blocks[entry].instrs;
+        assert!(
+            instrs
+                .iter()
+                .all(|i| !matches!(i, IrInstruction::Div { .. }))
+        );
+
+        // run the shifts by hand and compare against a real divide
+        for x in [-9i64, -8, -7, -4, -1, 0, 1, 7, 8, i64::MIN, i64::MAX] {
+            let mut env = HashMap::from([("x".to_string(), x)]);
+            for instr in instrs {
+                let val = |o: &String| env.get(o).copied().unwrap_or_else(|| o.parse().unwrap());
+                let value = match instr {
+                    IrInstruction::Shr { lhs, rhs, .. } => val(lhs) >> val(rhs),
+                    IrInstruction::UShr { lhs, rhs, .. } => ((val(lhs) as u64) >> val(rhs)) as i64,
+                    IrInstruction::Add { lhs, rhs, .. } => val(lhs).wrapping_add(val(rhs)),
+                    other => panic!("unexpected {:?}", other),
+                };
+                env.insert(instr.defs()[0].clone(), value);
+            }
+            assert_eq!(env["q"], x / 4, "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn constant_fold_picks_the_side_of_a_known_select() {
+        let mut func = IrFunction::new("pick");
+        let entry = func.add_block("entry").unwrap();
+        let select = |dest: &str, cond: &str| IrInstruction::Select {
+            dest: dest.to_string(),
+            cond: cond.to_string(),
+            true_val: "a".to_string(),
+            false_val: "b".to_string(),
+            ty: None,
+        };
+        func.append_instr(entry, &select("x", "true"));
+        func.append_instr(entry, &select("y", "false"));
+        func.append_instr(entry, &select("z", "c"));
+
+        let mut pass = ConstantFoldPass {};
+        pass.run_on_function(&mut func);
+
+        let instrs = &func.blocks[entry].instrs;
+        assert!(matches!(
+            &instrs[0],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "x" && *rhs == AssignRhs::Var("a".into())
+        ));
+        assert!(matches!(
+            &instrs[1],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "y" && *rhs == AssignRhs::Var("b".into())
+        ));
+        assert!(matches!(&instrs[2], IrInstruction::Select { .. }));
+        assert_eq!(instrs[2].uses(), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn constant_fold_evaluates_shifts() {
+        let mut func = IrFunction::new("shifts");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Shl {
+                dest: "a".to_string(),
+                lhs: "3".to_string(),
+                rhs: "2".to_string(),
+                ty: None,
+            },
+            IrInstruction::Shr {
+                dest: "b".to_string(),
+                lhs: "-8".to_string(),
+                rhs: "1".to_string(),
+                ty: None,
+            },
+            IrInstruction::UShr {
+                dest: "c".to_string(),
+                lhs: "-8".to_string(),
+                rhs: "60".to_string(),
+                ty: None,
+            },
+            // out of range amounts are left for the hardware to deal with
+            IrInstruction::Shl {
+                dest: "d".to_string(),
+                lhs: "1".to_string(),
+                rhs: "64".to_string(),
+                ty: None,
+            },
+        ];
+
+        let mut pass = ConstantFoldPass {};
+        pass.run_on_function(&mut func);
+
+        let folded: Vec<Option<i64>> = func.blocks[entry]
+            .instrs
+            .iter()
+            .map(|instr| match instr {
+                IrInstruction::Const {
+                    value: Literal::Int(i),
+                    ..
+                } => Some(*i),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(folded, vec![Some(12), Some(-4), Some(15), None]);
+    }
+
+    #[test]
+    fn constant_fold_evaluates_xor() {
+        let xor = |dest: &str, lhs: &str, rhs: &str| IrInstruction::Xor {
+            dest: dest.to_string(),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+            ty: None,
+        };
+        let mut func = IrFunction::new("xors");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            xor("a", "12", "10"),
+            xor("b", "true", "false"),
+            xor("c", "true", "true"),
+            xor("d", "x", "1"),
+        ];
+
+        let mut pass = ConstantFoldPass {};
+        pass.run_on_function(&mut func);
+
+        let folded: Vec<Option<Literal>> = func.blocks[entry]
+            .instrs
+            .iter()
+            .map(|instr| match instr {
+                IrInstruction::Const { value, .. } => Some(value.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            folded,
+            vec![
+                Some(Literal::Int(6)),
+                Some(Literal::Bool(true)),
+                Some(Literal::Bool(false)),
+                None
+            ]
+        );
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn constant_fold_evaluates_finite_float_arithmetic() {
+        let fdiv = |dest: &str, lhs: &str, rhs: &str| IrInstruction::FDiv {
+            dest: dest.to_string(),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+            ty: None,
+        };
+        let mut func = IrFunction::new("floats");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            fdiv("a", "3", "2"),
+            // the result isn't finite
+            fdiv("b", "1", "0"),
+            // `inf` could be a variable
+            fdiv("c", "inf", "2"),
+        ];
+
+        let mut pass = ConstantFoldPass {};
+        pass.run_on_function(&mut func);
+
+        let instrs = &func.blocks[entry].instrs;
+        assert!(matches!(
+            instrs[0],
+            IrInstruction::Const {
+                value: Literal::Float(1.5),
+                ..
+            }
+        ));
+        assert!(matches!(instrs[1], IrInstruction::FDiv { .. }));
+        assert!(matches!(instrs[2], IrInstruction::FDiv { .. }));
+    }
+
+    #[test]
+    fn constant_propagation_folds_known_bools() {
+        let logic = |dest: &str, lhs: &str, rhs: &str, and: bool| {
+            let (dest, lhs, rhs) = (dest.to_string(), lhs.to_string(), rhs.to_string());
+            if and {
+                IrInstruction::And {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            } else {
+                IrInstruction::Or {
+                    dest,
+                    lhs,
+                    rhs,
+                    ty: None,
+                }
+            }
+        };
+
+        let mut func = IrFunction::new("bools");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Const {
+                dest: "t".to_string(),
+                value: Literal::Bool(true),
+                ty: None,
+            },
+            IrInstruction::Not {
+                dest: "n".to_string(),
+                args: "t".to_string(),
+                ty: None,
+            },
+            // `n` is known false by now, x && false doesn't depend on x
+            logic("a", "x", "n", true),
+            logic("o", "x", "t", false),
+            // neither side known, nothing to do
+            logic("u", "x", "y", true),
+            IrInstruction::Ret {
+                args: vec!["a".to_string()],
+            },
+        ];
+
+        let mut pass = ConstantPropagationPass {};
+        pass.run_on_function(&mut func);
+
+        let instrs = &func.blocks[entry].instrs;
+        let known = |i: usize| match &instrs[i] {
+            IrInstruction::Const {
+                value: Literal::Bool(b),
+                ..
+            } => Some(*b),
+            _ => None,
+        };
+        assert_eq!(known(1), Some(false));
+        assert_eq!(known(2), Some(false));
+        assert_eq!(known(3), Some(true));
+        assert_eq!(known(4), None);
+        assert_eq!(instrs[4].uses(), vec!["x", "y"]);
+    }
+
+    /// Helper function for building a module out of `functions`, in order
+    fn module_of(functions: Vec<IrFunction>) -> IrModule {
+        let mut module = IrModule::default();
+        for func in functions {
+            module.add_function(func);
+        }
+        module
+    }
+
+    #[test]
+    fn range_propagation_folds_branches_on_bounded_values() {
+        // x is 3 or 7 depending on `p`, so x + 100 > 200 can never hold even
+        // though x isn't a constant
+        let mut func = IrFunction::new("ranges");
+        for label in ["entry", "A", "B", "C", "big", "small"] {
+            func.add_block(label).unwrap();
+        }
+        func.add_edge(0, 1);
+        func.add_edge(0, 2);
+        func.add_edge(1, 3);
+        func.add_edge(2, 3);
+        func.add_edge(3, 4);
+        func.add_edge(3, 5);
+
+        let br = |cond: &str, then_lbl: &str, else_lbl: &str| IrInstruction::Br {
+            cond: cond.into(),
+            then_lbl: then_lbl.into(),
+            else_lbl: else_lbl.into(),
+        };
+        let jmp = |label: &str| IrInstruction::Jmp {
+            label: label.into(),
+        };
+        func.blocks[0].instrs = vec![br("p", "A", "B")];
+        func.blocks[1].instrs = vec![jmp("C")];
+        func.blocks[2].instrs = vec![jmp("C")];
+        func.blocks[3].instrs = vec![
+            IrInstruction::Phi {
+                dest: "x".into(),
+                sources: vec![(1, Some("3".into())), (2, Some("7".into()))],
+                ty: None,
+            },
+            IrInstruction::Add {
+                dest: "y".into(),
+                lhs: "x".into(),
+                rhs: "100".into(),
+                ty: None,
+            },
+            IrInstruction::Gt {
+                dest: "c".into(),
+                lhs: "y".into(),
+                rhs: "200".into(),
+                ty: None,
+            },
+            br("c", "big", "small"),
+        ];
+        func.blocks[4].instrs = vec![IrInstruction::Ret { args: Vec::new() }];
+        func.blocks[5].instrs = vec![IrInstruction::Ret { args: Vec::new() }];
+
+        assert!(RangePropagationPass {}.run_on_function(&mut func));
+
+        assert!(matches!(
+            func.blocks[3].terminator(),
+            Some(IrInstruction::Jmp { label }) if label == "small"
+        ));
+        assert_eq!(func.blocks[3].succs, vec![5]);
+        assert!(func.blocks[4].preds.is_empty());
+
+        // nothing is known about `p`
+        assert!(matches!(
+            func.blocks[0].terminator(),
+            Some(IrInstruction::Br { .. })
+        ));
+        assert!(!RangePropagationPass {}.run_on_function(&mut func));
+    }
+
+    /// Drops every function named `unused`, and records the order passes ran in
+    struct DropUnused {
+        log: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    }
+
+    impl ModulePass for DropUnused {
+        fn name(&self) -> &str {
+            "DropUnused"
+        }
+
+        fn run_on_module(&mut self, module: &mut IrModule) -> bool {
+            self.log.borrow_mut().push(self.name().to_string());
+            let before = module.functions.len();
+            module.retain_functions(|f| f.name != "unused");
+            before != module.functions.len()
+        }
+    }
+
+    struct CountFunctions {
+        log: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    }
+
+    impl TransformPass for CountFunctions {
+        fn name(&self) -> &str {
+            "CountFunctions"
+        }
+
+        fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+            self.log.borrow_mut().push(function.name.clone());
+            true
+        }
+    }
+
+    #[test]
+    fn module_passes_run_before_function_passes() {
+        let mut module = module_of(vec![IrFunction::new("main"), IrFunction::new("unused")]);
+
+        let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut pm = PassManager::new();
+        pm.add_pass(CountFunctions { log: log.clone() });
+        pm.add_module_pass(DropUnused { log: log.clone() });
+        pm.run(&mut module).unwrap();
+
+        assert_eq!(module.functions.len(), 1);
+        assert_eq!(*log.borrow(), vec!["DropUnused", "main"]);
+    }
+
+    /// Never changes anything
+    struct Idle {}
+
+    impl TransformPass for Idle {
+        fn name(&self) -> &str {
+            "Idle"
+        }
+
+        fn run_on_function(&mut self, _function: &mut IrFunction) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn pass_manager_reports_stats_for_every_pass() {
+        let mut module = module_of(vec![IrFunction::new("main"), IrFunction::new("helper")]);
+
+        let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut pm = PassManager::new();
+        pm.add_pass(Idle {});
+        pm.add_pass(CountFunctions { log: log.clone() });
+        pm.add_module_pass(DropUnused { log: log.clone() });
+        let stats = pm.run(&mut module).unwrap();
+
+        let summary: Vec<(&str, bool)> =
+            stats.iter().map(|s| (s.name.as_str(), s.changed)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("DropUnused", false),
+                ("Idle", false),
+                ("CountFunctions", true)
+            ]
+        );
+        // a pass that changes nothing doesn't stop the ones after it
+        assert_eq!(*log.borrow(), vec!["DropUnused", "main", "helper"]);
+    }
+
+    /// Block count of a function, counting how often it really gets computed
+    struct CountBlocks {
+        runs: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl AnalysisPass for CountBlocks {
+        type Output = usize;
+
+        fn name(&self) -> &str {
+            "CountBlocks"
+        }
+
+        fn run_on_function(&mut self, function: &IrFunction) -> usize {
+            self.runs.set(self.runs.get() + 1);
+            function.blocks.len()
+        }
+    }
+
+    #[test]
+    fn analyses_are_cached_until_a_transform_changes_the_function() {
+        let func = |name: &str| {
+            let mut func = IrFunction::new(name);
+            func.add_block("entry").unwrap();
+            func
+        };
+        let mut module = module_of(vec![func("main"), func("helper")]);
+
+        let runs = std::rc::Rc::new(std::cell::Cell::new(0));
+        let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut pm = PassManager::new();
+        pm.add_analysis(CountBlocks { runs: runs.clone() });
+        pm.add_pass(Idle {});
+        pm.add_analysis(CountBlocks { runs: runs.clone() });
+        pm.add_analysis(LivenessAnalysis {});
+        pm.add_pass(CountFunctions { log: log.clone() });
+        pm.add_analysis(CountBlocks { runs: runs.clone() });
+        let stats = pm.run(&mut module).unwrap();
+
+        // once before `CountFunctions` and once after, for both functions
+        assert_eq!(runs.get(), 4);
+        // analyses never report a change
+        assert!(!stats[0].changed && stats[4].changed);
+        assert_eq!(pm.analysis::<usize>("main", "CountBlocks"), Some(&1));
+        assert_eq!(pm.analysis::<bool>("main", "CountBlocks"), None);
+
+        // liveness was thrown away when `CountFunctions` changed the function
+        type Liveness = (Vec<HashSet<String>>, Vec<HashSet<String>>);
+        assert_eq!(pm.analysis::<Liveness>("main", "LivenessAnalysis"), None);
+
+        let mut pm = PassManager::new();
+        pm.add_analysis(LivenessAnalysis {});
+        pm.run(&mut module).unwrap();
+        assert_eq!(
+            pm.analysis::<Liveness>("helper", "LivenessAnalysis"),
+            Some(&compute_liveness(&module.functions[1]))
+        );
+    }
+
+    /// Reads the block count through the cache it gets handed
+    struct ReadsBlockCount {
+        runs: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl TransformPass for ReadsBlockCount {
+        fn name(&self) -> &str {
+            "ReadsBlockCount"
+        }
+
+        fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+            self.run_with_analyses(function, &mut AnalysisCache::default())
+        }
+
+        fn run_with_analyses(
+            &mut self,
+            function: &mut IrFunction,
+            analyses: &mut AnalysisCache,
+        ) -> bool {
+            let mut count = CountBlocks {
+                runs: self.runs.clone(),
+            };
+            assert_eq!(*analyses.get_or_compute(&mut count, function), 1);
+            false
+        }
+    }
+
+    #[test]
+    fn transforms_reuse_cached_analyses() {
+        let mut func = IrFunction::new("main");
+        func.add_block("entry").unwrap();
+        let mut module = module_of(vec![func]);
+
+        let runs = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut pm = PassManager::new();
+        pm.add_analysis(CountBlocks { runs: runs.clone() });
+        pm.add_pass(ReadsBlockCount { runs: runs.clone() });
+        pm.add_pass(ReadsBlockCount { runs: runs.clone() });
+        pm.run(&mut module).unwrap();
+        assert_eq!(runs.get(), 1);
+
+        // without a pipeline the pass computes the analysis itself
+        ReadsBlockCount { runs: runs.clone() }.run_on_function(&mut module.functions[0]);
+        assert_eq!(runs.get(), 2);
+    }
+
+    /// Claims no block of the function has a dominator
+    struct NoDominators {}
+
+    impl AnalysisPass for NoDominators {
+        type Output = ir::PerFunctionSSAData;
+
+        fn name(&self) -> &str {
+            "DominatorAnalysis"
+        }
+
+        fn run_on_function(&mut self, _function: &IrFunction) -> Self::Output {
+            ir::PerFunctionSSAData::default()
+        }
+    }
+
+    #[test]
+    fn licm_and_gvn_take_dominators_from_the_cache() {
+        let mut analyses = AnalysisCache::default();
+        let mut func = counted_loop();
+        assert!(
+            analyses
+                .get_or_compute(&mut NoDominators {}, &func)
+                .idom
+                .is_empty()
+        );
+
+        // both would change their function if they computed dominators again
+        assert!(!LICMPass {}.run_with_analyses(&mut func, &mut analyses));
+        let mut func = redundant_diamond();
+        GVNPass {}.run_with_analyses(&mut func, &mut analyses);
+        assert!(matches!(
+            &func.blocks[1].instrs[0],
+            IrInstruction::Add { .. }
+        ));
+
+        assert!(GVNPass {}.run_with_analyses(&mut func, &mut AnalysisCache::default()));
+    }
+
+    #[test]
+    fn dead_functions_are_dropped_from_the_module() {
+        let call = |target: &str| IrInstruction::Call {
+            target_func: target.to_string(),
+            args: Vec::new(),
+            dest: CallDest::Void,
+            ty: None,
+        };
+        let func = |name: &str, callees: &[&str]| {
+            let mut func = IrFunction::new(name);
+            let entry = func.add_block("entry").unwrap();
+            for c in callees {
+                func.append_instr(entry, &call(c));
+            }
+            func
+        };
+
+        let mut module = module_of(vec![
+            func("main", &["helper"]),
+            func("helper", &["helper", "leaf"]),
+            func("leaf", &[]),
+            func("orphan", &["leaf"]),
+            func("api", &[]),
+        ]);
+
+        let mut pass = DeadFunctionElimPass::new(vec!["api".to_string()]);
+        assert!(pass.run_on_module(&mut module));
+
+        let names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "helper", "leaf", "api"]);
+
+        // without main or exports, nothing is known to be dead
+        let mut library = module_of(vec![func("orphan", &[])]);
+        assert!(!DeadFunctionElimPass::default().run_on_module(&mut library));
+        assert_eq!(library.functions.len(), 1);
+    }
+
+    #[test]
+    fn inline_splits_the_caller_around_the_callee_body() {
+        // add1(a): t = a + 1; ret t
+        let mut add1 = IrFunction::new("add1");
+        add1.args = vec![("a".into(), IrType::I64)];
+        add1.ret_ty = IrType::I64;
+        let entry = add1.add_block("entry").unwrap();
+        add1.append_instr(
+            entry,
+            &IrInstruction::Add {
+                dest: "t".into(),
+                lhs: "a".into(),
+                rhs: "1".into(),
+                ty: None,
+            },
+        );
+        add1.append_instr(
+            entry,
+            &IrInstruction::Ret {
+                args: vec!["t".into()],
+            },
+        );
+
+        // main: y = add1(5); print y; ret
+        let mut main = IrFunction::new("main");
+        let entry = main.add_block("entry").unwrap();
+        main.append_instr(
+            entry,
+            &IrInstruction::Call {
+                target_func: "add1".into(),
+                args: vec!["5".into()],
+                dest: CallDest::Named("y".into()),
+                ty: None,
+            },
+        );
+        main.append_instr(
+            entry,
+            &IrInstruction::Print {
+                values: vec!["y".into()],
+            },
+        );
+        main.append_instr(entry, &IrInstruction::Ret { args: Vec::new() });
+
+        let mut module = module_of(vec![main, add1]);
+        assert!(InlinePass::default().run_on_module(&mut module));
+
+        let main = &module.functions[0];
+        let labels: Vec<&str> = main.blocks.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, vec!["entry", "entry.inl0", "entry.cont.inl0"]);
+        assert_eq!(main.blocks[0].succs, vec![1]);
+        assert_eq!(main.blocks[1].succs, vec![2]);
+        assert_eq!(main.blocks[2].preds, vec![1]);
+
+        assert!(matches!(
+            &main.blocks[0].instrs[0],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "a.inl0" && *rhs == AssignRhs::Const(Literal::Int(5))
+        ));
+        assert!(matches!(
+            &main.blocks[1].instrs[0],
+            IrInstruction::Add { dest, lhs, rhs, .. }
+                if dest == "t.inl0" && lhs == "a.inl0" && rhs == "1"
+        ));
+        assert!(matches!(
+            &main.blocks[1].instrs[1],
+            IrInstruction::Assign { lhs, rhs, .. } if lhs == "y" && *rhs == AssignRhs::Var("t.inl0".into())
+        ));
+        assert!(matches!(
+            main.blocks[1].terminator(),
+            Some(IrInstruction::Jmp { label }) if label == "entry.cont.inl0"
+        ));
+        assert!(matches!(
+            &main.blocks[2].instrs[0],
+            IrInstruction::Print { .. }
+        ));
+
+        // the callee itself is left alone
+        assert_eq!(module.functions[1].blocks.len(), 1);
+    }
+
+    #[test]
+    fn pass_manager_inserts_missing_prerequisites() {
+        let mut pm = PassManager::new();
+        pm.add_pass(LICMPass {});
+        pm.schedule().unwrap();
+        assert_eq!(pm.pass_names(), vec!["SSAConstructionPass", "LICMPass"]);
+
+        // an already registered prerequisite is pulled forward, not duplicated
+        let mut pm = PassManager::new();
+        pm.add_pass(GVNPass {});
+        pm.add_pass(SSAConstructionPass {});
+        pm.add_pass(SCCPPass {});
+        pm.schedule().unwrap();
+        assert_eq!(
+            pm.pass_names(),
+            vec!["SSAConstructionPass", "GVNPass", "SCCPPass"]
+        );
+    }
+
+    /// Does nothing, only there to be scheduled
+    struct Requires {
+        name: &'static str,
+        deps: Vec<&'static str>,
+    }
+
+    impl TransformPass for Requires {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn run_on_function(&mut self, _function: &mut IrFunction) -> bool {
+            false
+        }
+
+        fn requires(&self) -> Vec<&'static str> {
+            self.deps.clone()
+        }
+    }
+
+    #[test]
+    fn pass_manager_reports_scheduling_errors() {
+        let mut pm = PassManager::new();
+        pm.add_pass(Requires {
+            name: "A",
+            deps: vec!["B"],
+        });
+        pm.add_pass(Requires {
+            name: "B",
+            deps: vec!["A"],
+        });
+        let err = pm.schedule().unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+
+        let mut pm = PassManager::new();
+        pm.add_pass(Requires {
+            name: "A",
+            deps: vec!["Missing"],
+        });
+        let mut module = module_of(vec![IrFunction::new("main")]);
+        let err = pm.run(&mut module).unwrap_err();
+        assert_eq!(err.to_string(), "A requires unknown pass Missing");
+    }
 }
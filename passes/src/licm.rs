@@ -0,0 +1,287 @@
+use crate::dominators::DominatorAnalysis;
+use crate::pass_manager::{AnalysisCache, TransformPass};
+use ir::{
+    BlockID, IrFunction, IrInstruction, NaturalLoop, PerFunctionSSAData, find_natural_loops,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Loop-Invariant Code Motion
+///
+/// Hoists side-effect free instructions whose operands never change inside a
+/// loop into a freshly created preheader block, so they only get computed once.
+pub struct LICMPass {}
+
+impl TransformPass for LICMPass {
+    fn name(&self) -> &str {
+        "LICMPass"
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        vec!["SSAConstructionPass"]
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        self.run_with_analyses(function, &mut AnalysisCache::default())
+    }
+
+    fn run_with_analyses(
+        &mut self,
+        function: &mut IrFunction,
+        analyses: &mut AnalysisCache,
+    ) -> bool {
+        if function.blocks.is_empty() {
+            return false;
+        }
+
+        let ssa = analyses.get_or_compute(&mut DominatorAnalysis {}, function);
+        // Loop bodies are walked through preds, an unreachable block feeding
+        // into a loop would end up inside it. Leave those to UnreachableBlockElimPass.
+        if (0..function.blocks.len()).any(|b| !ssa.idom.contains_key(&b)) {
+            return false;
+        }
+
+        let mut loops = find_natural_loops(function, ssa);
+        // innermost loops first, so hoisted code can keep bubbling outwards
+        loops.sort_by_key(|l| l.body.len());
+
+        let mut changed = false;
+        for i in 0..loops.len() {
+            let hoisted = invariant_instrs(function, &loops[i], ssa);
+            if hoisted.is_empty() {
+                continue;
+            }
+
+            let Some(preheader) = insert_preheader(function, &loops[i]) else {
+                continue;
+            };
+
+            // Move the invariant instructions, they are already in dependency order
+            for (b, idx) in hoisted.iter() {
+                let instr = function.blocks[*b].instrs[*idx].clone();
+                function.blocks[preheader].push_instr(instr);
+            }
+            let mut removed: Vec<(BlockID, usize)> = hoisted;
+            removed.sort_by(|a, b| b.cmp(a));
+            for (b, idx) in removed {
+                function.blocks[b].instrs.remove(idx);
+            }
+
+            // The new block now lives inside every loop enclosing this one
+            let header = loops[i].header;
+            for outer in loops.iter_mut().skip(i + 1) {
+                if outer.body.contains(&header) {
+                    outer.body.push(preheader);
+                }
+            }
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+/// Side-effect free instructions we are allowed to move
+fn is_candidate(instr: &IrInstruction) -> bool {
+    matches!(
+        instr,
+        IrInstruction::Add { .. }
+            | IrInstruction::Mul { .. }
+            | IrInstruction::Sub { .. }
+            | IrInstruction::Div { .. }
+            | IrInstruction::Const { .. }
+            | IrInstruction::Assign { .. }
+    )
+}
+
+/// A division traps on a zero divisor, so it may only run in the preheader when
+/// the loop runs it on every way out anyway, or when the divisor can't be zero
+fn div_may_move(
+    instr: &IrInstruction,
+    b: BlockID,
+    exits: &[BlockID],
+    ssa: &PerFunctionSSAData,
+) -> bool {
+    let IrInstruction::Div { rhs, .. } = instr else {
+        return true;
+    };
+
+    if rhs.parse::<i64>().is_ok_and(|d| d != 0) {
+        return true;
+    }
+    exits.iter().all(|&e| ssa.dominates(b, e))
+}
+
+/// Returns the (block, instr index) of every loop-invariant instruction, in an
+/// order where definitions come before their uses
+fn invariant_instrs(
+    func: &IrFunction,
+    lp: &NaturalLoop,
+    ssa: &PerFunctionSSAData,
+) -> Vec<(BlockID, usize)> {
+    // how many times a name gets written, anything written twice can't move
+    let mut def_count: HashMap<&String, usize> = HashMap::new();
+    for block in &func.blocks {
+        for instr in &block.instrs {
+            for d in instr.defs() {
+                *def_count.entry(d).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut loop_defs: HashSet<&String> = HashSet::new();
+    for &b in &lp.body {
+        for instr in &func.blocks[b].instrs {
+            loop_defs.extend(instr.defs());
+        }
+    }
+
+    // blocks the loop can be left from
+    let exits: Vec<BlockID> = lp
+        .body
+        .iter()
+        .copied()
+        .filter(|&b| func.blocks[b].succs.iter().any(|s| !lp.body.contains(s)))
+        .collect();
+
+    let mut invariant_defs: HashSet<String> = HashSet::new();
+    let mut found: Vec<(BlockID, usize)> = Vec::new();
+
+    // fix-point, an instruction may become invariant once its operands are
+    loop {
+        let mut changed = false;
+
+        for &b in &lp.body {
+            for (idx, instr) in func.blocks[b].instrs.iter().enumerate() {
+                if !is_candidate(instr) || found.contains(&(b, idx)) {
+                    continue;
+                }
+                if !div_may_move(instr, b, &exits, ssa) {
+                    continue;
+                }
+
+                let Some(dest) = instr.defs().first() else {
+                    continue;
+                };
+                if def_count.get(dest).copied().unwrap_or(0) != 1 {
+                    continue;
+                }
+
+                let invariant = instr
+                    .uses()
+                    .iter()
+                    .all(|u| !loop_defs.contains(u) || invariant_defs.contains(u));
+
+                if invariant {
+                    invariant_defs.insert(dest.clone());
+                    found.push((b, idx));
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    found
+}
+
+/// Creates a preheader for `lp`, routing every edge that enters the loop from
+/// outside through it. Returns `None` when the loop has no entry from outside,
+/// or when its preheader label is already taken.
+fn insert_preheader(func: &mut IrFunction, lp: &NaturalLoop) -> Option<BlockID> {
+    let header = lp.header;
+    let header_lbl = func.blocks[header].label.clone();
+
+    let outside: Vec<BlockID> = func.blocks[header]
+        .preds
+        .iter()
+        .copied()
+        .filter(|p| !lp.body.contains(p))
+        .collect();
+    if outside.is_empty() {
+        return None;
+    }
+
+    let preheader_lbl = format!("{}.preheader", header_lbl);
+    // somebody already took the label, leave the loop alone
+    let preheader = func.add_block(&preheader_lbl).ok()?;
+
+    // Header phis collapse the outside sources into a single one coming from the
+    // preheader. With several outside preds the merge needs its own phi.
+    let mut preheader_phis: Vec<IrInstruction> = Vec::new();
+    for instr in func.blocks[header].instrs.iter_mut() {
+        if let IrInstruction::Phi { dest, sources, ty } = instr {
+            let Some(first) = sources.iter().position(|(p, _)| outside.contains(p)) else {
+                continue;
+            };
+            let incoming: Vec<(BlockID, Option<String>)> = sources
+                .iter()
+                .filter(|(p, _)| outside.contains(p))
+                .cloned()
+                .collect();
+
+            let merged = if incoming.len() == 1 {
+                incoming[0].1.clone()
+            } else {
+                let ph_dest = format!("{}.ph", dest);
+                preheader_phis.push(IrInstruction::Phi {
+                    dest: ph_dest.clone(),
+                    sources: incoming,
+                    ty: ty.clone(),
+                });
+                Some(ph_dest)
+            };
+
+            sources.retain(|(p, _)| !outside.contains(p));
+            sources.insert(first, (preheader, merged));
+        }
+    }
+
+    {
+        let preds = &mut func.blocks[header].preds;
+        let first = preds.iter().position(|p| outside.contains(p)).unwrap();
+        preds[first] = preheader;
+        preds.retain(|p| !outside.contains(p));
+    }
+
+    for &p in &outside {
+        for s in func.blocks[p].succs.iter_mut() {
+            if *s == header {
+                *s = preheader;
+            }
+        }
+
+        match func.blocks[p].instrs.last_mut() {
+            Some(IrInstruction::Jmp { label }) => {
+                *label = preheader_lbl.clone();
+            }
+            Some(IrInstruction::Br {
+                then_lbl, else_lbl, ..
+            }) => {
+                if *then_lbl == header_lbl {
+                    *then_lbl = preheader_lbl.clone();
+                }
+                if *else_lbl == header_lbl {
+                    *else_lbl = preheader_lbl.clone();
+                }
+            }
+            // fell through into the header, which is no longer the next block
+            _ => {
+                func.blocks[p].instrs.push(IrInstruction::Jmp {
+                    label: preheader_lbl.clone(),
+                });
+            }
+        }
+    }
+
+    func.blocks[preheader].instrs = preheader_phis;
+    func.blocks[preheader]
+        .instrs
+        .push(IrInstruction::Jmp { label: header_lbl });
+    func.blocks[preheader].preds = outside;
+    func.blocks[preheader].succs = vec![header];
+
+    Some(preheader)
+}
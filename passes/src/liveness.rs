@@ -1,6 +1,23 @@
-use ir::{IrBasicBlock, IrFunction};
+use crate::pass_manager::AnalysisPass;
+use ir::{BlockID, IrBasicBlock, IrFunction, IrInstruction};
 use std::collections::HashSet;
 
+/// `compute_liveness` as a pipeline analysis, the output is the same
+/// (live_out, live_in) pair
+pub struct LivenessAnalysis {}
+
+impl AnalysisPass for LivenessAnalysis {
+    type Output = (Vec<HashSet<String>>, Vec<HashSet<String>>);
+
+    fn name(&self) -> &str {
+        "LivenessAnalysis"
+    }
+
+    fn run_on_function(&mut self, function: &IrFunction) -> Self::Output {
+        compute_liveness(function)
+    }
+}
+
 /// Helps with determining which value or variable is alives through out the function
 pub fn compute_liveness(func: &IrFunction) -> (Vec<HashSet<String>>, Vec<HashSet<String>>) {
     let n = func.blocks.len();
@@ -16,9 +33,12 @@ pub fn compute_liveness(func: &IrFunction) -> (Vec<HashSet<String>>, Vec<HashSet
         defs[i] = d;
     }
 
-    // LiveOut Formula: LiveOut[1] = LiveIn[2]
+    // LiveOut Formula: LiveOut[1] = LiveIn[2] ∪ PhiUse[2, 1]
     // LiveIn Formula:  LiveIn[2] = Use[2] ∪ ( LiveOut[2]  / Def[2] )
     //
+    // A phi source is only live on the edge it comes in from, so it gets
+    // added to the LiveOut of that pred instead of the LiveIn of the phi block
+    //
     // Fix-pointed iteration (backwards)
     loop {
         let mut changed = false;
@@ -30,6 +50,8 @@ pub fn compute_liveness(func: &IrFunction) -> (Vec<HashSet<String>>, Vec<HashSet
             live_out[b].clear();
             for &s in &func.blocks[b].succs {
                 live_out[b].extend(live_in[s].iter().cloned());
+
+                live_out[b].extend(compute_block_phi_uses(&func.blocks[s], b));
             }
 
             // (LiveOut[b] / Def[b])
@@ -60,22 +82,44 @@ pub fn compute_liveness(func: &IrFunction) -> (Vec<HashSet<String>>, Vec<HashSet
 }
 
 /// Returns the set of defintions & uses for each variable in a block
+///
+/// An instruction reads its operands before it writes its dest, so uses are
+/// checked against the defs of earlier instructions only: in `x = x + 1` the
+/// old `x` still flows into the block
 pub fn compute_block_def_use(block: &IrBasicBlock) -> (HashSet<String>, HashSet<String>) {
     let mut defs = HashSet::new();
     let mut uses = HashSet::new();
 
     for instr in block.instrs.iter() {
+        // phi sources are live on the incoming edges, see `compute_block_phi_uses`
+        if !matches!(instr, IrInstruction::Phi { .. }) {
+            // Anything that is used before you define it
+            for u in instr.uses() {
+                if !defs.contains(&u) {
+                    uses.insert(u);
+                }
+            }
+        }
+
         for def in instr.defs() {
             defs.insert(def.clone());
         }
+    }
+
+    (defs, uses)
+}
+
+/// Returns the phi sources of `block` flowing in from `pred`
+pub fn compute_block_phi_uses(block: &IrBasicBlock, pred: BlockID) -> HashSet<String> {
+    let mut uses = HashSet::new();
 
-        // Anything that is used before you define it
-        for u in instr.uses() {
-            if !defs.contains(&u) {
-                uses.insert(u.clone());
+    for instr in block.instrs.iter() {
+        if let IrInstruction::Phi { sources, .. } = instr {
+            for (_, src) in sources.iter().filter(|(p, _)| *p == pred) {
+                uses.extend(src.clone());
             }
         }
     }
 
-    (defs, uses)
+    uses
 }
@@ -1,40 +1,326 @@
+use crate::{
+    ConstantFoldPass, ConstantPropagationPass, DeadCodeRemovalPass, GVNPass, LICMPass,
+    PhiEliminationPass, RangePropagationPass, SCCPPass, SSAConstructionPass,
+    StrengthReductionPass, UnreachableBlockElimPass,
+};
+use anyhow::{Result, bail};
 use ir::IrFunction;
 use ir::IrModule;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 /// This trait will be inherited by optimizations or transformations of
 /// on functions within the Module scope
-pub trait FunctionPass {
+pub trait TransformPass {
     fn name(&self) -> &str;
 
     fn run_on_function(&mut self, function: &mut IrFunction) -> bool;
+
+    /// `run_on_function` with the analyses the `PassManager` has cached for
+    /// `function`. Passes that lean on an analysis override this one instead.
+    fn run_with_analyses(
+        &mut self,
+        function: &mut IrFunction,
+        _analyses: &mut AnalysisCache,
+    ) -> bool {
+        self.run_on_function(function)
+    }
+
+    /// Names of the passes that have to run before this one. The `PassManager`
+    /// inserts any of them that are missing from the pipeline.
+    fn requires(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+/// A pass that only reads the function and hands back what it found. The
+/// `PassManager` caches the result until a transform changes the function
+pub trait AnalysisPass {
+    type Output: 'static;
+
+    fn name(&self) -> &str;
+
+    fn run_on_function(&mut self, function: &IrFunction) -> Self::Output;
+}
+
+/// Analysis results of a single function, keyed by analysis name
+#[derive(Default)]
+pub struct AnalysisCache {
+    results: HashMap<String, Box<dyn Any>>,
+}
+
+impl AnalysisCache {
+    /// Result of `analysis`, only run on `function` when it isn't cached yet
+    pub fn get_or_compute<A: AnalysisPass>(
+        &mut self,
+        analysis: &mut A,
+        function: &IrFunction,
+    ) -> &A::Output {
+        self.results
+            .entry(analysis.name().to_string())
+            .or_insert_with(|| Box::new(analysis.run_on_function(function)))
+            .downcast_ref()
+            .expect("two analyses with the same name but different outputs")
+    }
+
+    /// Cached result of the analysis named `analysis`, `None` if there is none
+    /// or `T` isn't its output type
+    pub fn get<T: 'static>(&self, analysis: &str) -> Option<&T> {
+        self.results.get(analysis)?.downcast_ref()
+    }
+}
+
+/// Object safe side of `AnalysisPass`, so analyses with different outputs
+/// can share the pipeline
+trait ErasedAnalysis {
+    fn name(&self) -> &str;
+
+    fn run_on_function(&mut self, function: &IrFunction) -> Box<dyn Any>;
+}
+
+impl<A: AnalysisPass> ErasedAnalysis for A {
+    fn name(&self) -> &str {
+        AnalysisPass::name(self)
+    }
+
+    fn run_on_function(&mut self, function: &IrFunction) -> Box<dyn Any> {
+        Box::new(AnalysisPass::run_on_function(self, function))
+    }
+}
+
+/// A slot in the function pipeline
+enum PipelinePass {
+    Analysis(Box<dyn ErasedAnalysis>),
+    Transform(Box<dyn TransformPass>),
+}
+
+impl PipelinePass {
+    fn name(&self) -> &str {
+        match self {
+            PipelinePass::Analysis(pass) => pass.name(),
+            PipelinePass::Transform(pass) => pass.name(),
+        }
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        match self {
+            PipelinePass::Analysis(_) => Vec::new(),
+            PipelinePass::Transform(pass) => pass.requires(),
+        }
+    }
+}
+
+/// Same idea as `TransformPass`, but the pass gets to see the whole module at
+/// once. Needed for interprocedural work (inlining, call graphs, ...)
+pub trait ModulePass {
+    fn name(&self) -> &str;
+
+    fn run_on_module(&mut self, module: &mut IrModule) -> bool;
+}
+
+/// What a pass did during `PassManager::run`. Function passes get a single
+/// entry, with the time summed over every function and `changed` set if any
+/// of them changed
+#[derive(Debug, Clone)]
+pub struct PassStats {
+    pub name: String,
+    pub elapsed: Duration,
+    pub changed: bool,
 }
 
 #[derive(Default)]
 pub struct PassManager {
-    passes: Vec<Box<dyn FunctionPass>>,
+    module_passes: Vec<Box<dyn ModulePass>>,
+    passes: Vec<PipelinePass>,
+    /// Analysis results of every function, dropped whenever a transform
+    /// changes that function
+    analysis_cache: HashMap<String, AnalysisCache>,
+    /// Print the stats of every run to stderr
+    pub verbose: bool,
 }
 
 impl PassManager {
     pub fn new() -> PassManager {
-        PassManager { passes: Vec::new() }
+        PassManager {
+            module_passes: Vec::new(),
+            passes: Vec::new(),
+            analysis_cache: HashMap::new(),
+            verbose: false,
+        }
     }
 
-    pub fn run(&mut self, module: &mut IrModule) {
+    /// Runs the pipeline over `module`, returns the stats of every pass in the
+    /// order they ran. Every pass runs on every function, a pass that changes
+    /// nothing no longer stops the ones after it from running.
+    ///
+    /// Fails when the function passes can't be scheduled, see `schedule`
+    pub fn run(&mut self, module: &mut IrModule) -> Result<Vec<PassStats>> {
+        self.schedule()?;
+        self.analysis_cache.clear();
+        let mut stats: Vec<PassStats> = Vec::new();
+
+        // module passes may add or remove functions, so they go first
+        for pass in self.module_passes.iter_mut() {
+            let start = Instant::now();
+            let changed = pass.run_on_module(module);
+            stats.push(PassStats {
+                name: pass.name().to_string(),
+                elapsed: start.elapsed(),
+                changed,
+            });
+        }
+
+        let first = stats.len();
+        stats.extend(self.passes.iter().map(|pass| PassStats {
+            name: pass.name().to_string(),
+            elapsed: Duration::ZERO,
+            changed: false,
+        }));
+
         // loop throught each function in the module and run the pass
         for func in module.functions.iter_mut() {
             // loop there each of the element in the passes vector
-            for pass in self.passes.iter_mut() {
-                let changed = pass.run_on_function(func);
-                if !changed {
-                    // TODO: find a better way of dealing with this
-                    // maybe add an erroring system?
-                    break;
+            for (pass, stat) in self.passes.iter_mut().zip(&mut stats[first..]) {
+                let start = Instant::now();
+                match pass {
+                    PipelinePass::Analysis(analysis) => {
+                        self.analysis_cache
+                            .entry(func.name.clone())
+                            .or_default()
+                            .results
+                            .entry(analysis.name().to_string())
+                            .or_insert_with(|| analysis.run_on_function(func));
+                    }
+                    PipelinePass::Transform(transform) => {
+                        let analyses = self.analysis_cache.entry(func.name.clone()).or_default();
+                        if transform.run_with_analyses(func, analyses) {
+                            stat.changed = true;
+                            self.analysis_cache.remove(&func.name);
+                        }
+                    }
                 }
+                stat.elapsed += start.elapsed();
+            }
+        }
+
+        if self.verbose {
+            print_stats(&stats);
+        }
+        Ok(stats)
+    }
+
+    pub fn add_pass<P: TransformPass + 'static>(&mut self, pass: P) {
+        self.passes.push(PipelinePass::Transform(Box::new(pass)));
+    }
+
+    pub fn add_analysis<A: AnalysisPass + 'static>(&mut self, pass: A) {
+        self.passes.push(PipelinePass::Analysis(Box::new(pass)));
+    }
+
+    /// Result of the analysis named `analysis` on `function` from the last
+    /// `run`. `None` if it was never computed, was invalidated by a later
+    /// transform, or `T` isn't its output type
+    pub fn analysis<T: 'static>(&self, function: &str, analysis: &str) -> Option<&T> {
+        self.analysis_cache.get(function)?.get(analysis)
+    }
+
+    /// Names of the function passes and analyses, in the order they will run
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Orders the function passes so every pass runs after the passes it
+    /// `requires`, creating any prerequisite that was never registered.
+    /// Otherwise the registration order is kept.
+    ///
+    /// Fails on a dependency cycle, or on a prerequisite that isn't registered
+    /// and isn't a known pass either. The pipeline is left empty then.
+    pub fn schedule(&mut self) -> Result<()> {
+        let mut pending: Vec<Option<PipelinePass>> = std::mem::take(&mut self.passes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut ordered: Vec<PipelinePass> = Vec::new();
+        let mut visiting: HashSet<String> = HashSet::new();
+
+        for i in 0..pending.len() {
+            if let Some(pass) = pending[i].take() {
+                schedule_pass(pass, &mut pending, &mut ordered, &mut visiting)?;
             }
         }
+
+        self.passes = ordered;
+        Ok(())
+    }
+
+    pub fn add_module_pass<P: ModulePass + 'static>(&mut self, pass: P) {
+        self.module_passes.push(Box::new(pass));
     }
+}
+
+fn print_stats(stats: &[PassStats]) {
+    eprintln!("{:<28} {:>12}  changed", "pass", "time");
+    for stat in stats {
+        let elapsed = format!("{:?}", stat.elapsed);
+        eprintln!("{:<28} {:>12}  {}", stat.name, elapsed, stat.changed);
+    }
+}
 
-    pub fn add_pass<P: FunctionPass + 'static>(&mut self, pass: P) {
-        self.passes.push(Box::new(pass));
+/// Depth-first topological sort, prerequisites get emitted before `pass`
+fn schedule_pass(
+    pass: PipelinePass,
+    pending: &mut [Option<PipelinePass>],
+    ordered: &mut Vec<PipelinePass>,
+    visiting: &mut HashSet<String>,
+) -> Result<()> {
+    let name = pass.name().to_string();
+    if !visiting.insert(name.clone()) {
+        bail!("pass dependency cycle through {}", name);
     }
+
+    for dep in pass.requires() {
+        if ordered.iter().any(|p| p.name() == dep) {
+            continue;
+        }
+        // still waiting on its own prerequisites further up
+        if visiting.contains(dep) {
+            bail!("pass dependency cycle through {}", dep);
+        }
+
+        // registered later on, pull it forward
+        let registered = pending
+            .iter_mut()
+            .find(|p| p.as_ref().is_some_and(|p| p.name() == dep))
+            .and_then(|p| p.take());
+
+        let dep_pass = match registered.or_else(|| pass_by_name(dep).map(PipelinePass::Transform)) {
+            Some(p) => p,
+            None => bail!("{} requires unknown pass {}", name, dep),
+        };
+        schedule_pass(dep_pass, pending, ordered, visiting)?;
+    }
+
+    visiting.remove(&name);
+    ordered.push(pass);
+    Ok(())
+}
+
+/// Creates a pass from its name, used to fill in missing prerequisites
+fn pass_by_name(name: &str) -> Option<Box<dyn TransformPass>> {
+    let pass: Box<dyn TransformPass> = match name {
+        "SSAConstructionPass" => Box::new(SSAConstructionPass {}),
+        "ConstantFoldPass" => Box::new(ConstantFoldPass {}),
+        "ConstantPropagationPass" => Box::new(ConstantPropagationPass {}),
+        "DeadCodeRemovalPass" => Box::new(DeadCodeRemovalPass {}),
+        "SCCPPass" => Box::new(SCCPPass {}),
+        "GVNPass" => Box::new(GVNPass {}),
+        "LICMPass" => Box::new(LICMPass {}),
+        "PhiEliminationPass" => Box::new(PhiEliminationPass {}),
+        "RangePropagationPass" => Box::new(RangePropagationPass {}),
+        "StrengthReductionPass" => Box::new(StrengthReductionPass {}),
+        "UnreachableBlockElimPass" => Box::new(UnreachableBlockElimPass {}),
+        _ => return None,
+    };
+    Some(pass)
 }
@@ -0,0 +1,68 @@
+use crate::pass_manager::TransformPass;
+use ir::{AssignRhs, IrFunction, IrInstruction, split_critical_edges};
+
+/// Takes the function out of SSA form, instruction selection has no phis
+///
+/// Critical edges get split first, so every copy meant for an edge has a block
+/// that only runs on that edge. Each phi `d` then becomes a copy into `d.phi`
+/// at the end of every predecessor and `d = d.phi` at the top of its block.
+/// Going through `d.phi` keeps the phis of a block reading their sources all
+/// at once, even when one of them feeds another (a swap).
+pub struct PhiEliminationPass {}
+
+impl TransformPass for PhiEliminationPass {
+    fn name(&self) -> &str {
+        "PhiEliminationPass"
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        let has_phis = function
+            .blocks
+            .iter()
+            .any(|b| matches!(b.instrs.first(), Some(IrInstruction::Phi { .. })));
+        if !has_phis {
+            return false;
+        }
+
+        split_critical_edges(function);
+
+        for b in 0..function.blocks.len() {
+            let phi_count = function.blocks[b]
+                .instrs
+                .iter()
+                .take_while(|i| matches!(i, IrInstruction::Phi { .. }))
+                .count();
+            let phis: Vec<IrInstruction> = function.blocks[b].instrs.drain(..phi_count).collect();
+
+            let mut copies: Vec<IrInstruction> = Vec::new();
+            for phi in phis {
+                let IrInstruction::Phi { dest, sources, ty } = phi else {
+                    unreachable!();
+                };
+                let tmp = format!("{}.phi", dest);
+
+                for (pred, src) in sources {
+                    // undefined along this edge, nothing to copy
+                    let Some(src) = src else {
+                        continue;
+                    };
+                    function.blocks[pred].push_instr(IrInstruction::Assign {
+                        lhs: tmp.clone(),
+                        rhs: AssignRhs::from_operand(&src),
+                        ty: ty.clone(),
+                    });
+                }
+
+                copies.push(IrInstruction::Assign {
+                    lhs: dest,
+                    rhs: AssignRhs::Var(tmp),
+                    ty,
+                });
+            }
+
+            function.blocks[b].instrs.splice(0..0, copies);
+        }
+
+        true
+    }
+}
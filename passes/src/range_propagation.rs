@@ -0,0 +1,187 @@
+use crate::pass_manager::TransformPass;
+use crate::sccp::remove_edge;
+use ir::cfg::Literal;
+use ir::{AssignRhs, IrFunction, IrInstruction};
+use std::collections::HashMap;
+
+/// Inclusive bounds of a value, bools are `0..=1`
+type Range = (i64, i64);
+
+const TRUE: Range = (1, 1);
+const FALSE: Range = (0, 0);
+const BOOL: Range = (0, 1);
+
+/// Value Range Propagation
+///
+/// Walks the blocks in RPO keeping the range every name is known to fall in.
+/// A branch whose condition can only be true (or only false) becomes a jump,
+/// even if the condition isn't a single constant. Only names written exactly
+/// once are tracked, and phis fed through a back edge are left unknown.
+pub struct RangePropagationPass {}
+
+impl TransformPass for RangePropagationPass {
+    fn name(&self) -> &str {
+        "RangePropagationPass"
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        vec!["ConstantPropagationPass"]
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        let mut def_count: HashMap<&String, usize> = HashMap::new();
+        for instr in function.blocks.iter().flat_map(|b| b.instrs.iter()) {
+            for d in instr.defs() {
+                *def_count.entry(d).or_default() += 1;
+            }
+        }
+
+        let mut ranges: HashMap<String, Range> = HashMap::new();
+        for b in function.rpo() {
+            for instr in function.blocks[b].instrs.iter() {
+                let Some(dest) = instr.defs().first() else {
+                    continue;
+                };
+                if def_count[dest] != 1 {
+                    continue;
+                }
+                if let Some(range) = evaluate(instr, &ranges) {
+                    ranges.insert(dest.clone(), range);
+                }
+            }
+        }
+
+        // (from, to) edges that are dropped when a branch gets folded
+        let mut dead_edges = Vec::new();
+        for b in 0..function.blocks.len() {
+            let Some(IrInstruction::Br {
+                cond,
+                then_lbl,
+                else_lbl,
+            }) = function.blocks[b].terminator()
+            else {
+                continue;
+            };
+
+            let (live, dead) = match operand(cond, &ranges) {
+                Some(TRUE) => (then_lbl.clone(), else_lbl.clone()),
+                Some(FALSE) => (else_lbl.clone(), then_lbl.clone()),
+                _ => continue,
+            };
+
+            let live_idx = function.block_index(&live).unwrap();
+            let dead_idx = function.block_index(&dead).unwrap();
+            if live_idx != dead_idx {
+                dead_edges.push((b, dead_idx));
+            }
+            *function.blocks[b].instrs.last_mut().unwrap() = IrInstruction::Jmp { label: live };
+        }
+
+        let changed = !dead_edges.is_empty();
+        for (from, to) in dead_edges {
+            remove_edge(function, from, to);
+        }
+        changed
+    }
+}
+
+/// Range of the value `instr` defines, `None` when nothing is known about it
+fn evaluate(instr: &IrInstruction, ranges: &HashMap<String, Range>) -> Option<Range> {
+    match instr {
+        IrInstruction::Const { value, .. } => literal(value),
+
+        IrInstruction::Assign { rhs, .. } => match rhs {
+            AssignRhs::Var(var) => operand(var, ranges),
+            AssignRhs::Const(lit) => literal(lit),
+        },
+
+        IrInstruction::Add { lhs, rhs, .. } => {
+            let (l, r) = (operand(lhs, ranges)?, operand(rhs, ranges)?);
+            Some((l.0.checked_add(r.0)?, l.1.checked_add(r.1)?))
+        }
+
+        IrInstruction::Sub { lhs, rhs, .. } => {
+            let (l, r) = (operand(lhs, ranges)?, operand(rhs, ranges)?);
+            Some((l.0.checked_sub(r.1)?, l.1.checked_sub(r.0)?))
+        }
+
+        // x < y is settled once the ranges don't overlap the wrong way
+        IrInstruction::Lt { lhs, rhs, .. } => compare(lhs, rhs, ranges),
+        IrInstruction::Gt { lhs, rhs, .. } => compare(rhs, lhs, ranges),
+        IrInstruction::Le { lhs, rhs, .. } => compare(rhs, lhs, ranges).map(negate),
+        IrInstruction::Ge { lhs, rhs, .. } => compare(lhs, rhs, ranges).map(negate),
+
+        IrInstruction::Eq { lhs, rhs, .. } => {
+            let (l, r) = (operand(lhs, ranges)?, operand(rhs, ranges)?);
+            if l.1 < r.0 || r.1 < l.0 {
+                Some(FALSE)
+            } else if l.0 == l.1 && l == r {
+                Some(TRUE)
+            } else {
+                Some(BOOL)
+            }
+        }
+
+        IrInstruction::Not { args, .. } => operand(args, ranges).map(negate),
+
+        // the union of every source, a source we haven't seen yet (back edge)
+        // could be anything
+        IrInstruction::Phi { sources, .. } => sources
+            .iter()
+            .map(|(_, src)| operand(src.as_deref()?, ranges))
+            .reduce(|acc, r| Some(union(acc?, r?)))?,
+
+        IrInstruction::Select {
+            true_val,
+            false_val,
+            ..
+        } => Some(union(
+            operand(true_val, ranges)?,
+            operand(false_val, ranges)?,
+        )),
+
+        _ => None,
+    }
+}
+
+/// Bool range of `lhs < rhs`
+fn compare(lhs: &str, rhs: &str, ranges: &HashMap<String, Range>) -> Option<Range> {
+    let (l, r) = (operand(lhs, ranges)?, operand(rhs, ranges)?);
+    if l.1 < r.0 {
+        Some(TRUE)
+    } else if l.0 >= r.1 {
+        Some(FALSE)
+    } else {
+        Some(BOOL)
+    }
+}
+
+fn negate(range: Range) -> Range {
+    match range {
+        TRUE => FALSE,
+        FALSE => TRUE,
+        _ => BOOL,
+    }
+}
+
+fn union(a: Range, b: Range) -> Range {
+    (a.0.min(b.0), a.1.max(b.1))
+}
+
+fn operand(name: &str, ranges: &HashMap<String, Range>) -> Option<Range> {
+    if let Ok(i) = name.parse::<i64>() {
+        return Some((i, i));
+    }
+    if let Ok(b) = name.parse::<bool>() {
+        return Some(if b { TRUE } else { FALSE });
+    }
+    ranges.get(name).copied()
+}
+
+fn literal(lit: &Literal) -> Option<Range> {
+    match lit {
+        Literal::Int(i) => Some((*i, *i)),
+        Literal::Bool(b) => Some(if *b { TRUE } else { FALSE }),
+        _ => None,
+    }
+}
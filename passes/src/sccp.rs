@@ -0,0 +1,405 @@
+use crate::pass_manager::TransformPass;
+use ir::cfg::Literal;
+use ir::{AssignRhs, BlockID, IrFunction, IrInstruction};
+use std::collections::{HashMap, HashSet};
+
+/// Value lattice used by SCCP
+///
+///   Top          -> nothing is known yet (optimistic)
+///   Const(lit)   -> proven to always hold `lit`
+///   Bottom       -> overdefined, not a constant
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lattice {
+    Top,
+    Const(Literal),
+    Bottom,
+}
+
+impl Lattice {
+    /// Lattice meet, values can only ever move downwards: Top -> Const -> Bottom
+    fn meet(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Top, x) | (x, Lattice::Top) => x.clone(),
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a.clone()),
+            _ => Lattice::Bottom,
+        }
+    }
+}
+
+/// Sparse Conditional Constant Propagation (Wegman & Zadeck)
+///
+/// Unlike `ConstantPropagationPass`, this only evaluates blocks that are proven
+/// reachable, so a phi fed by an always-false branch resolves to the single
+/// value coming from the live edge.
+pub struct SCCPPass {}
+
+impl TransformPass for SCCPPass {
+    fn name(&self) -> &str {
+        "SCCPPass"
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        vec!["SSAConstructionPass"]
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        if function.blocks.is_empty() {
+            return false;
+        }
+
+        let mut solver = Solver::new(function);
+        solver.solve(function);
+        solver.rewrite(function)
+    }
+}
+
+struct Solver {
+    values: HashMap<String, Lattice>,
+    // every instruction that reads a given name, as (block, instr index)
+    users: HashMap<String, Vec<(BlockID, usize)>>,
+    executable_blocks: HashSet<BlockID>,
+    executable_edges: HashSet<(BlockID, BlockID)>,
+    cfg_worklist: Vec<(BlockID, BlockID)>,
+    ssa_worklist: Vec<String>,
+}
+
+impl Solver {
+    fn new(func: &IrFunction) -> Self {
+        let mut values = HashMap::new();
+        let mut users: HashMap<String, Vec<(BlockID, usize)>> = HashMap::new();
+
+        for (b, block) in func.blocks.iter().enumerate() {
+            for (i, instr) in block.instrs.iter().enumerate() {
+                for d in instr.defs() {
+                    values.insert(d.clone(), Lattice::Top);
+                }
+                for u in instr.uses() {
+                    users.entry(u).or_default().push((b, i));
+                }
+            }
+        }
+
+        Self {
+            values,
+            users,
+            executable_blocks: HashSet::new(),
+            executable_edges: HashSet::new(),
+            cfg_worklist: Vec::new(),
+            ssa_worklist: Vec::new(),
+        }
+    }
+
+    fn solve(&mut self, func: &IrFunction) {
+        let entry = func.entry_block();
+        self.executable_blocks.insert(entry);
+        self.visit_block(func, entry);
+
+        loop {
+            if let Some((from, to)) = self.cfg_worklist.pop() {
+                if !self.executable_edges.insert((from, to)) {
+                    continue;
+                }
+
+                if self.executable_blocks.insert(to) {
+                    self.visit_block(func, to);
+                } else {
+                    // block was already visited, only the phis can observe the new edge
+                    for (i, instr) in func.blocks[to].instrs.iter().enumerate() {
+                        if let IrInstruction::Phi { .. } = instr {
+                            self.visit_instr(func, to, i);
+                        }
+                    }
+                }
+            } else if let Some(name) = self.ssa_worklist.pop() {
+                let users = self.users.get(&name).cloned().unwrap_or_default();
+                for (b, i) in users {
+                    if self.executable_blocks.contains(&b) {
+                        self.visit_instr(func, b, i);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn visit_block(&mut self, func: &IrFunction, b: BlockID) {
+        for i in 0..func.blocks[b].instrs.len() {
+            self.visit_instr(func, b, i);
+        }
+
+        // Anything other than a branch unconditionally flows into all of its successors
+        if !matches!(func.blocks[b].terminator(), Some(IrInstruction::Br { .. })) {
+            for &s in &func.blocks[b].succs {
+                self.cfg_worklist.push((b, s));
+            }
+        }
+    }
+
+    fn visit_instr(&mut self, func: &IrFunction, b: BlockID, i: usize) {
+        let block = &func.blocks[b];
+
+        match &block.instrs[i] {
+            IrInstruction::Phi { dest, sources, .. } => {
+                let mut value = Lattice::Top;
+                for (pred, src) in sources {
+                    if !self.executable_edges.contains(&(*pred, b)) {
+                        continue;
+                    }
+                    if let Some(s) = src {
+                        value = value.meet(&self.operand(s));
+                    }
+                }
+                self.update(dest, value);
+            }
+
+            IrInstruction::Br {
+                cond,
+                then_lbl,
+                else_lbl,
+            } => {
+                let targets = func.block_index(then_lbl).zip(func.block_index(else_lbl));
+                let Some((then_idx, else_idx)) = targets else {
+                    // can't tell the labels apart, so follow every edge out
+                    for &s in &block.succs {
+                        self.cfg_worklist.push((b, s));
+                    }
+                    return;
+                };
+
+                match self.operand(cond) {
+                    Lattice::Top => {}
+                    Lattice::Const(Literal::Bool(true)) => self.cfg_worklist.push((b, then_idx)),
+                    Lattice::Const(Literal::Bool(false)) => self.cfg_worklist.push((b, else_idx)),
+                    _ => {
+                        self.cfg_worklist.push((b, then_idx));
+                        self.cfg_worklist.push((b, else_idx));
+                    }
+                }
+            }
+
+            instr => {
+                if let Some(dest) = instr.defs().first() {
+                    let value = self.evaluate(instr);
+                    self.update(dest, value);
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, instr: &IrInstruction) -> Lattice {
+        match instr {
+            IrInstruction::Const { value, .. } => Lattice::Const(value.clone()),
+            IrInstruction::Assign { rhs, .. } => match rhs {
+                AssignRhs::Var(var) => self.operand(var),
+                AssignRhs::Const(lit) => Lattice::Const(lit.clone()),
+            },
+
+            IrInstruction::Not { args, .. } => match self.operand(args) {
+                Lattice::Const(Literal::Bool(b)) => Lattice::Const(Literal::Bool(!b)),
+                Lattice::Top => Lattice::Top,
+                _ => Lattice::Bottom,
+            },
+
+            IrInstruction::Add { lhs, rhs, .. }
+            | IrInstruction::Sub { lhs, rhs, .. }
+            | IrInstruction::Mul { lhs, rhs, .. }
+            | IrInstruction::Div { lhs, rhs, .. }
+            | IrInstruction::Shl { lhs, rhs, .. }
+            | IrInstruction::Shr { lhs, rhs, .. }
+            | IrInstruction::UShr { lhs, rhs, .. }
+            | IrInstruction::Eq { lhs, rhs, .. }
+            | IrInstruction::Lt { lhs, rhs, .. }
+            | IrInstruction::Gt { lhs, rhs, .. }
+            | IrInstruction::Le { lhs, rhs, .. }
+            | IrInstruction::Ge { lhs, rhs, .. }
+            | IrInstruction::And { lhs, rhs, .. }
+            | IrInstruction::Or { lhs, rhs, .. }
+            | IrInstruction::Xor { lhs, rhs, .. } => match (self.operand(lhs), self.operand(rhs)) {
+                (Lattice::Bottom, _) | (_, Lattice::Bottom) => Lattice::Bottom,
+                (Lattice::Const(l), Lattice::Const(r)) => match fold(instr, &l, &r) {
+                    Some(lit) => Lattice::Const(lit),
+                    None => Lattice::Bottom,
+                },
+                _ => Lattice::Top,
+            },
+
+            IrInstruction::Select {
+                cond,
+                true_val,
+                false_val,
+                ..
+            } => match self.operand(cond) {
+                Lattice::Const(Literal::Bool(true)) => self.operand(true_val),
+                Lattice::Const(Literal::Bool(false)) => self.operand(false_val),
+                Lattice::Top => Lattice::Top,
+                _ => self.operand(true_val).meet(&self.operand(false_val)),
+            },
+
+            // Calls (and anything we don't understand) are never constant
+            _ => Lattice::Bottom,
+        }
+    }
+
+    /// Lattice value of an operand, which is either a literal or a variable name
+    fn operand(&self, name: &str) -> Lattice {
+        if let Ok(i) = name.parse::<i64>() {
+            return Lattice::Const(Literal::Int(i));
+        }
+        if let Ok(b) = name.parse::<bool>() {
+            return Lattice::Const(Literal::Bool(b));
+        }
+
+        // Names without a definition in this function (args) are unknown
+        self.values.get(name).cloned().unwrap_or(Lattice::Bottom)
+    }
+
+    fn update(&mut self, dest: &str, value: Lattice) {
+        let old = self.values.get(dest).cloned().unwrap_or(Lattice::Top);
+        let new = old.meet(&value);
+
+        if new != old {
+            self.values.insert(dest.to_string(), new);
+            self.ssa_worklist.push(dest.to_string());
+        }
+    }
+
+    /// Replace every definition proven constant with a `Const`, and fold branches
+    /// on constant conditions into jumps
+    fn rewrite(&self, func: &mut IrFunction) -> bool {
+        let mut changed = false;
+        // (from, to) edges that are dropped when a branch gets folded
+        let mut dead_edges: Vec<(BlockID, BlockID)> = Vec::new();
+
+        for b in 0..func.blocks.len() {
+            // Unreachable blocks are left alone
+            if !self.executable_blocks.contains(&b) {
+                continue;
+            }
+
+            if let Some((live, dead_idx)) = self.folded_branch(func, b) {
+                if let Some(dead_idx) = dead_idx {
+                    dead_edges.push((b, dead_idx));
+                }
+                *func.blocks[b].instrs.last_mut().unwrap() = IrInstruction::Jmp { label: live };
+                changed = true;
+            }
+
+            // constant phis, and the `Const`s replacing them after the phi group
+            let mut dead_phis: Vec<usize> = Vec::new();
+            let mut phi_consts: Vec<IrInstruction> = Vec::new();
+
+            for (idx, instr) in func.blocks[b].instrs.iter_mut().enumerate() {
+                if let IrInstruction::Const { .. } = instr {
+                    continue;
+                }
+
+                let Some((dest, ty)) = instr.defs_typed().into_iter().next() else {
+                    continue;
+                };
+
+                if let Some(Lattice::Const(value)) = self.values.get(&dest) {
+                    let folded = IrInstruction::Const {
+                        dest,
+                        value: value.clone(),
+                        ty: Some(ty),
+                    };
+                    if matches!(instr, IrInstruction::Phi { .. }) {
+                        dead_phis.push(idx);
+                        phi_consts.push(folded);
+                    } else {
+                        *instr = folded;
+                    }
+                    changed = true;
+                }
+            }
+
+            let block = &mut func.blocks[b];
+            for idx in dead_phis.into_iter().rev() {
+                block.instrs.remove(idx);
+            }
+            for folded in phi_consts.into_iter().rev() {
+                block.prepend_instr(folded);
+            }
+        }
+
+        for (from, to) in dead_edges {
+            remove_edge(func, from, to);
+        }
+
+        changed
+    }
+
+    /// The label a branch on a constant condition always takes, along with the
+    /// block it no longer flows into (`None` when both labels are the same).
+    /// Branches to labels that don't resolve to a block are left alone.
+    fn folded_branch(&self, func: &IrFunction, b: BlockID) -> Option<(String, Option<BlockID>)> {
+        let Some(IrInstruction::Br {
+            cond,
+            then_lbl,
+            else_lbl,
+        }) = func.blocks[b].terminator()
+        else {
+            return None;
+        };
+        let Lattice::Const(Literal::Bool(taken)) = self.operand(cond) else {
+            return None;
+        };
+
+        let (live, dead) = if taken {
+            (then_lbl, else_lbl)
+        } else {
+            (else_lbl, then_lbl)
+        };
+        let live_idx = func.block_index(live)?;
+        let dead_idx = func.block_index(dead)?;
+        Some((live.clone(), (live_idx != dead_idx).then_some(dead_idx)))
+    }
+}
+
+/// Folds a binary instruction over two constant operands
+fn fold(instr: &IrInstruction, lhs: &Literal, rhs: &Literal) -> Option<Literal> {
+    match (lhs, rhs) {
+        (Literal::Int(l), Literal::Int(r)) => match instr {
+            IrInstruction::Add { .. } => l.checked_add(*r).map(Literal::Int),
+            IrInstruction::Sub { .. } => l.checked_sub(*r).map(Literal::Int),
+            IrInstruction::Mul { .. } => l.checked_mul(*r).map(Literal::Int),
+            IrInstruction::Div { .. } => l.checked_div(*r).map(Literal::Int),
+            IrInstruction::Shl { .. } => u32::try_from(*r)
+                .ok()
+                .and_then(|r| l.checked_shl(r))
+                .map(Literal::Int),
+            IrInstruction::Shr { .. } => u32::try_from(*r)
+                .ok()
+                .and_then(|r| l.checked_shr(r))
+                .map(Literal::Int),
+            IrInstruction::UShr { .. } => u32::try_from(*r)
+                .ok()
+                .and_then(|r| (*l as u64).checked_shr(r))
+                .map(|v| Literal::Int(v as i64)),
+            IrInstruction::Eq { .. } => Some(Literal::Bool(l == r)),
+            IrInstruction::Lt { .. } => Some(Literal::Bool(l < r)),
+            IrInstruction::Gt { .. } => Some(Literal::Bool(l > r)),
+            IrInstruction::Le { .. } => Some(Literal::Bool(l <= r)),
+            IrInstruction::Ge { .. } => Some(Literal::Bool(l >= r)),
+            IrInstruction::Xor { .. } => Some(Literal::Int(l ^ r)),
+            _ => None,
+        },
+
+        (Literal::Bool(l), Literal::Bool(r)) => match instr {
+            IrInstruction::And { .. } => Some(Literal::Bool(*l && *r)),
+            IrInstruction::Or { .. } => Some(Literal::Bool(*l || *r)),
+            IrInstruction::Xor { .. } => Some(Literal::Bool(l != r)),
+            IrInstruction::Eq { .. } => Some(Literal::Bool(l == r)),
+            _ => None,
+        },
+
+        _ => None,
+    }
+}
+
+/// Drop the CFG edge `from -> to`, along with the phi source that belonged to it
+pub(crate) fn remove_edge(func: &mut IrFunction, from: BlockID, to: BlockID) {
+    func.blocks[from].succs.retain(|&s| s != to);
+    func.blocks[to].remove_pred(from);
+}
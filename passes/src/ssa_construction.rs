@@ -0,0 +1,40 @@
+use crate::pass_manager::TransformPass;
+use ir::IrFunction;
+use ir::SSAFormation;
+use log::error;
+use std::collections::HashSet;
+
+/// Wraps `SSAFormation` so SSA construction can be scheduled like any other
+/// pass, and be required by the passes that expect SSA form
+pub struct SSAConstructionPass {}
+
+impl TransformPass for SSAConstructionPass {
+    fn name(&self) -> &str {
+        "SSAConstructionPass"
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        // renaming gives every definition a fresh name, a function that is
+        // already in SSA form (or has no definitions at all) comes out as it went in
+        if function.blocks.is_empty() || is_ssa(function) {
+            return false;
+        }
+
+        if let Err(err) = SSAFormation::new(std::slice::from_mut(function)) {
+            error!("SSA construction failed for {}: {:#}", function.name, err);
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Every name is written exactly once
+pub(crate) fn is_ssa(func: &IrFunction) -> bool {
+    let mut seen: HashSet<&String> = HashSet::new();
+    func.blocks
+        .iter()
+        .flat_map(|b| b.instrs.iter())
+        .flat_map(|i| i.defs())
+        .all(|d| seen.insert(d))
+}
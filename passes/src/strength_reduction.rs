@@ -0,0 +1,112 @@
+use crate::pass_manager::TransformPass;
+use ir::AssignRhs;
+use ir::IrFunction;
+use ir::IrInstruction;
+use ir::IrType;
+
+/// Replaces multiplies and divides by a power-of-two constant with shifts
+pub struct StrengthReductionPass {}
+
+impl TransformPass for StrengthReductionPass {
+    fn name(&self) -> &str {
+        "StrengthReductionPass"
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        let mut changed = false;
+
+        for blocks in function.blocks.iter_mut() {
+            let old_instrs = std::mem::take(&mut blocks.instrs);
+            for instr in old_instrs {
+                match &instr {
+                    IrInstruction::Mul { dest, lhs, rhs, ty } => {
+                        // x * 2^k => x << k, either side may hold the constant
+                        let (value, shift) = if let Some(k) = log2(rhs) {
+                            (lhs.clone(), k)
+                        } else if let Some(k) = log2(lhs) {
+                            (rhs.clone(), k)
+                        } else {
+                            blocks.instrs.push(instr);
+                            continue;
+                        };
+
+                        blocks.instrs.push(IrInstruction::Shl {
+                            dest: dest.clone(),
+                            lhs: value,
+                            rhs: shift.to_string(),
+                            ty: ty.clone(),
+                        });
+                        changed = true;
+                    }
+
+                    IrInstruction::Div { dest, lhs, rhs, ty } => {
+                        let Some(k) = log2(rhs) else {
+                            blocks.instrs.push(instr);
+                            continue;
+                        };
+
+                        blocks.instrs.extend(div_by_pow2(dest, lhs, k, ty));
+                        changed = true;
+                    }
+                    _ => blocks.instrs.push(instr),
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+/// x / 2^k as shifts. `sra` rounds towards -inf while `div` truncates, so a
+/// negative dividend first gets 2^k - 1 added,
+/// `dest = (x + ((x >> 63) >>> (64 - k))) >> k`
+fn div_by_pow2(dest: &str, x: &str, k: u32, ty: &Option<IrType>) -> Vec<IrInstruction> {
+    if k == 0 {
+        return vec![IrInstruction::Assign {
+            lhs: dest.to_string(),
+            rhs: AssignRhs::from_operand(x),
+            ty: ty.clone(),
+        }];
+    }
+
+    let sign = format!("{}.sign", dest);
+    let bias = format!("{}.bias", dest);
+    let biased = format!("{}.biased", dest);
+    vec![
+        // all ones for a negative x, 0 otherwise
+        IrInstruction::Shr {
+            dest: sign.clone(),
+            lhs: x.to_string(),
+            rhs: "63".to_string(),
+            ty: ty.clone(),
+        },
+        IrInstruction::UShr {
+            dest: bias.clone(),
+            lhs: sign,
+            rhs: (64 - k).to_string(),
+            ty: ty.clone(),
+        },
+        IrInstruction::Add {
+            dest: biased.clone(),
+            lhs: x.to_string(),
+            rhs: bias,
+            ty: ty.clone(),
+        },
+        IrInstruction::Shr {
+            dest: dest.to_string(),
+            lhs: biased,
+            rhs: k.to_string(),
+            ty: ty.clone(),
+        },
+    ]
+}
+
+/// If `operand` is a positive power-of-two constant, returns its exponent
+fn log2(operand: &str) -> Option<u32> {
+    let value = operand.parse::<i64>().ok()?;
+    if value > 0 && value.count_ones() == 1 {
+        Some(value.trailing_zeros())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,90 @@
+use crate::pass_manager::TransformPass;
+use ir::{BlockID, IrFunction, IrInstruction};
+use std::collections::HashMap;
+
+/// Removes every block that can't be reached from the entry block
+pub struct UnreachableBlockElimPass {}
+
+impl TransformPass for UnreachableBlockElimPass {
+    fn name(&self) -> &str {
+        "UnreachableBlockElimPass"
+    }
+
+    fn run_on_function(&mut self, function: &mut IrFunction) -> bool {
+        if function.blocks.is_empty() {
+            return false;
+        }
+
+        let reachable = reachable_blocks(function);
+        if reachable.iter().all(|&r| r) {
+            return false;
+        }
+
+        // Drop the dead preds first, along with their phi sources
+        for b in 0..function.blocks.len() {
+            if !reachable[b] {
+                continue;
+            }
+
+            let block = &mut function.blocks[b];
+            let dead: Vec<BlockID> = block
+                .preds
+                .iter()
+                .copied()
+                .filter(|&p| !reachable[p])
+                .collect();
+            for p in dead {
+                block.remove_pred(p);
+            }
+        }
+
+        // old block index -> new block index
+        let mut remap: HashMap<BlockID, BlockID> = HashMap::new();
+        for (old, _) in reachable.iter().enumerate().filter(|&(_, &r)| r) {
+            remap.insert(old, remap.len());
+        }
+
+        let old_blocks = std::mem::take(&mut function.blocks);
+        function.blocks = old_blocks
+            .into_iter()
+            .enumerate()
+            .filter(|(old, _)| reachable[*old])
+            .map(|(_, block)| block)
+            .collect();
+
+        function.label_to_idx.clear();
+        for (idx, block) in function.blocks.iter_mut().enumerate() {
+            block.preds = block.preds.iter().map(|p| remap[p]).collect();
+            block.succs = block.succs.iter().map(|s| remap[s]).collect();
+            for instr in block.instrs.iter_mut() {
+                if let IrInstruction::Phi { sources, .. } = instr {
+                    for (p, _) in sources.iter_mut() {
+                        *p = remap[p];
+                    }
+                }
+            }
+            function.label_to_idx.insert(block.label.clone(), idx);
+        }
+
+        true
+    }
+}
+
+/// BFS over the successors, starting from the entry block
+fn reachable_blocks(func: &IrFunction) -> Vec<bool> {
+    let mut reachable = vec![false; func.blocks.len()];
+    let entry = func.entry_block();
+    let mut worklist = vec![entry];
+    reachable[entry] = true;
+
+    while let Some(b) = worklist.pop() {
+        for &s in &func.blocks[b].succs {
+            if !reachable[s] {
+                reachable[s] = true;
+                worklist.push(s);
+            }
+        }
+    }
+
+    reachable
+}
@@ -0,0 +1,93 @@
+use crate::machine_ir::{MachineFunc, MachineInstr, VReg};
+use crate::register_alloc::{ALL_REGS, build_interference_graph};
+use std::collections::{HashMap, HashSet};
+
+/// Register coalescing
+///
+/// Merges the source and dest of a `mv` when they don't interfere, so both end
+/// up in the same register and the move goes away. Uses the Briggs criterion
+/// to stay conservative: a merge never turns a colorable graph uncolorable.
+///
+/// Only virtual registers get merged. Pre-colored registers are left to the
+/// allocator, calls clobber them in ways the interference graph can't see yet.
+#[derive(Debug, Default)]
+pub struct CoalescingPass {}
+
+impl CoalescingPass {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Returns true when at least one move got removed
+    pub fn run(&mut self, mf: &mut MachineFunc) -> bool {
+        let mut changed = false;
+
+        // every merge changes the graph, so rebuild it after each one
+        while let Some((keep, merged)) = find_candidate(mf) {
+            for block in mf.blocks.iter_mut() {
+                for instr in block.instrs.iter_mut() {
+                    for reg in instr.regs_mut() {
+                        if *reg == merged {
+                            *reg = keep;
+                        }
+                    }
+                }
+
+                block
+                    .instrs
+                    .retain(|i| !matches!(i, MachineInstr::Mv { rd, rs1 } if rd == rs1));
+            }
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+/// First `mv` whose operands can be merged, as (register kept, register merged)
+fn find_candidate(mf: &MachineFunc) -> Option<(VReg, VReg)> {
+    let graph = build_interference_graph(mf);
+
+    for block in mf.blocks.iter() {
+        for instr in block.instrs.iter() {
+            let MachineInstr::Mv { rd, rs1 } = instr else {
+                continue;
+            };
+
+            if rd == rs1 || !is_virtual(*rd) || !is_virtual(*rs1) {
+                continue;
+            }
+
+            let interferes = graph.get(rd).is_some_and(|adj| adj.contains(rs1));
+            if !interferes && briggs(&graph, *rd, *rs1) {
+                return Some((*rd, *rs1));
+            }
+        }
+    }
+
+    None
+}
+
+/// The merged node has fewer than K neighbours of significant degree
+fn briggs(graph: &HashMap<VReg, HashSet<VReg>>, u: VReg, v: VReg) -> bool {
+    let k = ALL_REGS.len();
+    let empty = HashSet::new();
+
+    let neighbours: HashSet<&VReg> = graph
+        .get(&u)
+        .unwrap_or(&empty)
+        .iter()
+        .chain(graph.get(&v).unwrap_or(&empty).iter())
+        .collect();
+
+    let significant = neighbours
+        .iter()
+        .filter(|n| !is_virtual(***n) || graph[**n].len() >= k)
+        .count();
+
+    significant < k
+}
+
+fn is_virtual(v: VReg) -> bool {
+    matches!(v, VReg::Virtual(_))
+}
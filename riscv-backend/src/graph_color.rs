@@ -0,0 +1,443 @@
+use crate::machine_ir::{MachineFunc, MachineInstr, VReg};
+use crate::register_alloc::{ALL_REGS, build_interference_graph};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Graph coloring register allocation (Chaitin-Briggs, with the iterated
+/// coalescing from George & Appel)
+///
+/// Can be used in place of `LinearScan::run`. Physical registers showing up in
+/// the function are pre-colored nodes, so hard constraints like the calling
+/// convention are respected.
+#[derive(Debug, Default)]
+pub struct GraphColoringAlloc {
+    /// Virtual registers that didn't get a color, by function name
+    pub spilled: HashMap<String, Vec<VReg>>,
+}
+
+impl GraphColoringAlloc {
+    pub fn new() -> Self {
+        Self {
+            spilled: HashMap::new(),
+        }
+    }
+
+    pub fn run(&mut self, funcs: &[MachineFunc]) -> HashMap<String, HashMap<VReg, VReg>> {
+        let mut func_by_colors = HashMap::new();
+        for func in funcs.iter() {
+            func_by_colors.insert(func.name.clone(), self.allocate(func));
+        }
+
+        func_by_colors
+    }
+
+    /// Maps every virtual register of `mf` to a physical one. Whatever can't be
+    /// colored is left out of the map and recorded in `spilled`.
+    pub fn allocate(&mut self, mf: &MachineFunc) -> HashMap<VReg, VReg> {
+        let mut state = ColoringState::new(ALL_REGS);
+        state.build(mf);
+        state.make_worklist();
+
+        loop {
+            if !state.simplify_worklist.is_empty() {
+                state.simplify();
+            } else if !state.worklist_moves.is_empty() {
+                state.coalesce();
+            } else if !state.freeze_worklist.is_empty() {
+                state.freeze();
+            } else if !state.spill_worklist.is_empty() {
+                state.select_spill();
+            } else {
+                break;
+            }
+        }
+
+        state.assign_colors();
+
+        self.spilled
+            .insert(mf.name.clone(), state.spilled_nodes.clone());
+
+        state
+            .color
+            .into_iter()
+            .filter(|(v, _)| matches!(v, VReg::Virtual(_)))
+            .collect()
+    }
+}
+
+/// Work state of a single allocation, names follow Appel's "Modern Compiler
+/// Implementation" so the algorithm can be checked against the book
+struct ColoringState {
+    colors: Vec<VReg>,
+    k: usize,
+
+    precolored: HashSet<VReg>,
+    // virtual registers, in order of appearance
+    initial: Vec<VReg>,
+
+    simplify_worklist: Vec<VReg>,
+    freeze_worklist: Vec<VReg>,
+    spill_worklist: Vec<VReg>,
+    spilled_nodes: Vec<VReg>,
+    coalesced_nodes: HashSet<VReg>,
+    select_stack: Vec<VReg>,
+
+    // every `mv` as (rd, rs1), the sets below hold indices into it
+    moves: Vec<(VReg, VReg)>,
+    coalesced_moves: BTreeSet<usize>,
+    constrained_moves: BTreeSet<usize>,
+    frozen_moves: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    active_moves: BTreeSet<usize>,
+
+    adj_set: HashSet<(VReg, VReg)>,
+    adj_list: HashMap<VReg, Vec<VReg>>,
+    degree: HashMap<VReg, usize>,
+    move_list: HashMap<VReg, Vec<usize>>,
+    alias: HashMap<VReg, VReg>,
+    color: HashMap<VReg, VReg>,
+}
+
+impl ColoringState {
+    fn new(colors: &[VReg]) -> Self {
+        Self {
+            colors: colors.to_vec(),
+            k: colors.len(),
+            precolored: HashSet::new(),
+            initial: Vec::new(),
+            simplify_worklist: Vec::new(),
+            freeze_worklist: Vec::new(),
+            spill_worklist: Vec::new(),
+            spilled_nodes: Vec::new(),
+            coalesced_nodes: HashSet::new(),
+            select_stack: Vec::new(),
+            moves: Vec::new(),
+            coalesced_moves: BTreeSet::new(),
+            constrained_moves: BTreeSet::new(),
+            frozen_moves: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            active_moves: BTreeSet::new(),
+            adj_set: HashSet::new(),
+            adj_list: HashMap::new(),
+            degree: HashMap::new(),
+            move_list: HashMap::new(),
+            alias: HashMap::new(),
+            color: HashMap::new(),
+        }
+    }
+
+    fn add_node(&mut self, v: VReg) {
+        if matches!(v, VReg::Virtual(_)) {
+            if let Entry::Vacant(e) = self.degree.entry(v) {
+                e.insert(0);
+                self.initial.push(v);
+            }
+        } else if self.precolored.insert(v) {
+            // pre-colored nodes can never be simplified or spilled
+            self.degree.insert(v, usize::MAX / 2);
+            self.color.insert(v, v);
+        }
+    }
+
+    fn build(&mut self, mf: &MachineFunc) {
+        let graph = build_interference_graph(mf);
+
+        // every node has to exist before edges get added, pre-colored ones
+        // keep no adjacency list of their own
+        for block in mf.blocks.iter() {
+            for instr in block.instrs.iter() {
+                for v in instr.defs().into_iter().chain(instr.uses()) {
+                    self.add_node(v);
+                }
+
+                if let MachineInstr::Mv { rd, rs1 } = instr {
+                    let m = self.moves.len();
+                    self.moves.push((*rd, *rs1));
+                    self.move_list.entry(*rd).or_default().push(m);
+                    if rs1 != rd {
+                        self.move_list.entry(*rs1).or_default().push(m);
+                    }
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+
+        let mut nodes: Vec<&VReg> = graph.keys().collect();
+        nodes.sort_by_key(|v| sort_key(v));
+        for &u in nodes {
+            let mut neighbours: Vec<VReg> = graph[&u].iter().copied().collect();
+            neighbours.sort_by_key(sort_key);
+            for v in neighbours {
+                self.add_edge(u, v);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, u: VReg, v: VReg) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+
+        if !self.precolored.contains(&u) {
+            self.adj_list.entry(u).or_default().push(v);
+            *self.degree.entry(u).or_insert(0) += 1;
+        }
+        if !self.precolored.contains(&v) {
+            self.adj_list.entry(v).or_default().push(u);
+            *self.degree.entry(v).or_insert(0) += 1;
+        }
+    }
+
+    fn make_worklist(&mut self) {
+        for n in std::mem::take(&mut self.initial) {
+            if self.degree[&n] >= self.k {
+                self.spill_worklist.push(n);
+            } else if self.move_related(n) {
+                self.freeze_worklist.push(n);
+            } else {
+                self.simplify_worklist.push(n);
+            }
+        }
+    }
+
+    fn adjacent(&self, n: VReg) -> Vec<VReg> {
+        self.adj_list
+            .get(&n)
+            .map(|adj| {
+                adj.iter()
+                    .copied()
+                    .filter(|v| !self.select_stack.contains(v) && !self.coalesced_nodes.contains(v))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn node_moves(&self, n: VReg) -> Vec<usize> {
+        self.move_list
+            .get(&n)
+            .map(|ms| {
+                ms.iter()
+                    .copied()
+                    .filter(|m| self.active_moves.contains(m) || self.worklist_moves.contains(m))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn move_related(&self, n: VReg) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    fn simplify(&mut self) {
+        let n = self.simplify_worklist.pop().unwrap();
+        self.select_stack.push(n);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: VReg) {
+        let d = self.degree[&m];
+        self.degree.insert(m, d - 1);
+
+        if d == self.k {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+
+            self.spill_worklist.retain(|&v| v != m);
+            if self.move_related(m) {
+                self.freeze_worklist.push(m);
+            } else {
+                self.simplify_worklist.push(m);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[VReg]) {
+        for &n in nodes {
+            for m in self.node_moves(n) {
+                if self.active_moves.remove(&m) {
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn coalesce(&mut self) {
+        let m = *self.worklist_moves.iter().next().unwrap();
+        self.worklist_moves.remove(&m);
+
+        let x = self.get_alias(self.moves[m].0);
+        let y = self.get_alias(self.moves[m].1);
+        let (u, v) = if self.precolored.contains(&y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+
+        if u == v {
+            self.coalesced_moves.insert(m);
+            self.add_worklist(u);
+        } else if self.precolored.contains(&v) || self.adj_set.contains(&(u, v)) {
+            self.constrained_moves.insert(m);
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if self.can_coalesce(u, v) {
+            self.coalesced_moves.insert(m);
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.active_moves.insert(m);
+        }
+    }
+
+    /// George's test when `u` is pre-colored, Briggs' otherwise
+    fn can_coalesce(&self, u: VReg, v: VReg) -> bool {
+        if self.precolored.contains(&u) {
+            return self.adjacent(v).iter().all(|&t| self.ok(t, u));
+        }
+
+        let mut nodes = self.adjacent(u);
+        for t in self.adjacent(v) {
+            if !nodes.contains(&t) {
+                nodes.push(t);
+            }
+        }
+        self.conservative(&nodes)
+    }
+
+    fn ok(&self, t: VReg, r: VReg) -> bool {
+        self.degree[&t] < self.k || self.precolored.contains(&t) || self.adj_set.contains(&(t, r))
+    }
+
+    fn conservative(&self, nodes: &[VReg]) -> bool {
+        let significant = nodes.iter().filter(|n| self.degree[n] >= self.k).count();
+        significant < self.k
+    }
+
+    fn add_worklist(&mut self, u: VReg) {
+        if !self.precolored.contains(&u) && !self.move_related(u) && self.degree[&u] < self.k {
+            self.freeze_worklist.retain(|&v| v != u);
+            self.simplify_worklist.push(u);
+        }
+    }
+
+    fn get_alias(&self, n: VReg) -> VReg {
+        let mut n = n;
+        while self.coalesced_nodes.contains(&n) {
+            n = self.alias[&n];
+        }
+        n
+    }
+
+    fn combine(&mut self, u: VReg, v: VReg) {
+        if self.freeze_worklist.contains(&v) {
+            self.freeze_worklist.retain(|&n| n != v);
+        } else {
+            self.spill_worklist.retain(|&n| n != v);
+        }
+
+        self.coalesced_nodes.insert(v);
+        self.alias.insert(v, u);
+
+        let v_moves = self.move_list.get(&v).cloned().unwrap_or_default();
+        self.move_list.entry(u).or_default().extend(v_moves);
+        self.enable_moves(&[v]);
+
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+
+        if self.degree[&u] >= self.k && self.freeze_worklist.contains(&u) {
+            self.freeze_worklist.retain(|&n| n != u);
+            self.spill_worklist.push(u);
+        }
+    }
+
+    fn freeze(&mut self) {
+        let u = self.freeze_worklist.pop().unwrap();
+        self.simplify_worklist.push(u);
+        self.freeze_moves(u);
+    }
+
+    fn freeze_moves(&mut self, u: VReg) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) {
+                self.get_alias(x)
+            } else {
+                self.get_alias(y)
+            };
+
+            self.active_moves.remove(&m);
+            self.worklist_moves.remove(&m);
+            self.frozen_moves.insert(m);
+
+            if !self.precolored.contains(&v)
+                && self.node_moves(v).is_empty()
+                && self.degree[&v] < self.k
+                && self.freeze_worklist.contains(&v)
+            {
+                self.freeze_worklist.retain(|&n| n != v);
+                self.simplify_worklist.push(v);
+            }
+        }
+    }
+
+    /// Spill heuristic: the node with the most neighbours frees up the most
+    fn select_spill(&mut self) {
+        let (idx, _) = self
+            .spill_worklist
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, n)| self.degree[n])
+            .unwrap();
+        let m = self.spill_worklist.remove(idx);
+
+        self.simplify_worklist.push(m);
+        self.freeze_moves(m);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(n) = self.select_stack.pop() {
+            let mut ok_colors = self.colors.clone();
+
+            for &w in self.adj_list.get(&n).into_iter().flatten() {
+                if let Some(c) = self.color.get(&self.get_alias(w)) {
+                    ok_colors.retain(|ok| ok != c);
+                }
+            }
+
+            match ok_colors.first() {
+                Some(&c) => {
+                    self.color.insert(n, c);
+                }
+                None => self.spilled_nodes.push(n),
+            }
+        }
+
+        let mut coalesced: Vec<VReg> = self.coalesced_nodes.iter().copied().collect();
+        coalesced.sort_by_key(sort_key);
+        for n in coalesced {
+            match self.color.get(&self.get_alias(n)).copied() {
+                Some(c) => {
+                    self.color.insert(n, c);
+                }
+                None => self.spilled_nodes.push(n),
+            }
+        }
+    }
+}
+
+/// Keeps the iteration order (and so the allocation) stable between runs
+fn sort_key(v: &VReg) -> (bool, i32, String) {
+    match v {
+        VReg::Virtual(n) => (true, *n, String::new()),
+        _ => (false, 0, v.name()),
+    }
+}
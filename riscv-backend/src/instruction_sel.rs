@@ -1,111 +1,430 @@
-use crate::machine_ir::{MachineBlock, MachineFunc, MachineInstr, VReg};
-use ir::{IrFunction, IrInstruction};
+use crate::machine_ir::{MachineFunc, MachineInstr, VReg, compute_preds};
+use ir::{AssignRhs, CallDest, IrFunction, IrInstruction};
 use ir::cfg::Literal;
 use std::collections::HashMap;
 
+/// Registers the first 8 arguments of a call get passed in
+const ARG_REGS: [VReg; 8] = [
+    VReg::A0,
+    VReg::A1,
+    VReg::A2,
+    VReg::A3,
+    VReg::A4,
+    VReg::A5,
+    VReg::A6,
+    VReg::A7,
+];
+
 pub fn select_instructions(func: &IrFunction) -> MachineFunc {
     let mut machine_func: MachineFunc = MachineFunc::new(func);
 
     let mut vreg_mapping: HashMap<String, VReg> = HashMap::new();
     let mut next_vreg = 0;
+    // literals get a fresh register every time, `operand_reg` loads them
     let mut allocate_reg = |name: &String| {
-        *vreg_mapping.entry(name.clone()).or_insert_with(|| {
+        let mut fresh = || {
             let r = VReg::Virtual(next_vreg);
             next_vreg += 1;
             r
-        })
+        };
+        if operand_imm(name).is_some() {
+            return fresh();
+        }
+        *vreg_mapping.entry(name.clone()).or_insert_with(fresh)
     };
 
+    // how many times each value gets read, a comparison feeding nothing but
+    // the branch right after it can be fused into that branch
+    let mut use_counts: HashMap<String, usize> = HashMap::new();
+    for instr in func.blocks.iter().flat_map(|b| b.instrs.iter()) {
+        for u in instr.uses() {
+            *use_counts.entry(u).or_insert(0) += 1;
+        }
+    }
+
+    // Incoming arguments, the first 8 come in a0..a7 and the rest sit on the
+    // caller's stack, right above our frame (s0 points at its base)
+    let mut arg_moves = Vec::new();
+    for (i, (arg, _)) in func.args.iter().enumerate() {
+        let rd = allocate_reg(arg);
+        machine_func.args.push(rd);
+
+        match ARG_REGS.get(i) {
+            Some(&a_reg) => arg_moves.push(MachineInstr::Mv { rd, rs1: a_reg }),
+            None => arg_moves.push(MachineInstr::Ld {
+                rd,
+                offset: ((i - 8) * 8) as i32,
+                base: VReg::S0,
+            }),
+        }
+    }
+
     for block in func.blocks.iter() {
-        let mut machine_block: MachineBlock = MachineBlock {
-            name: block.label.clone(),
-            instrs: Vec::new(),
-            succs: block.succs.to_vec(),
-        };
+        let idx = machine_func.add_block(&block.label);
+        let machine_block = &mut machine_func.blocks[idx];
+        machine_block.succs = block.succs.to_vec();
+        let mut fused_branch = false;
+
+        for (i, instr) in block.instrs.iter().enumerate() {
+            if let Some(IrInstruction::Br {
+                cond,
+                then_lbl,
+                else_lbl,
+            }) = block.instrs.get(i + 1)
+                && instr.defs().first() == Some(cond)
+                && use_counts.get(cond) == Some(&1)
+                && let Some(branch) = compare_and_branch(
+                    instr,
+                    then_lbl,
+                    &mut machine_block.instrs,
+                    &mut allocate_reg,
+                )
+            {
+                machine_block.instrs.push(branch);
+                machine_block.instrs.push(MachineInstr::Jmp {
+                    label: else_lbl.to_string(),
+                });
+                fused_branch = true;
+                continue;
+            }
 
-        for instr in block.instrs.iter() {
             match instr {
-                IrInstruction::Const { dest, value } => {
+                IrInstruction::Const { dest, value, .. } => {
                     let rd = allocate_reg(dest);
-                    let imm = match value {
-                        Literal::Int(i) => *i,
-                        Literal::Bool(i) => *i as i64,
-                    };
-                    machine_block.instrs.push(MachineInstr::Li { rd, imm });
+                    let load = load_literal(rd, value, &func.name, &mut machine_func.strings);
+                    machine_block.instrs.push(load);
                 }
 
-                IrInstruction::Assign { lhs, rhs } => {
+                IrInstruction::Assign { lhs, rhs, .. } => {
                     let rd = allocate_reg(lhs);
-                    let rs1 = allocate_reg(rhs);
-                    machine_block.instrs.push(MachineInstr::Mv { rd, rs1 });
+                    match rhs {
+                        AssignRhs::Var(var) => {
+                            let rs1 = allocate_reg(var);
+                            machine_block.instrs.push(MachineInstr::Mv { rd, rs1 });
+                        }
+                        AssignRhs::Const(lit) => {
+                            let load = load_literal(rd, lit, &func.name, &mut machine_func.strings);
+                            machine_block.instrs.push(load);
+                        }
+                    }
+                }
+
+                IrInstruction::Add { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+
+                    // addition commutes, the constant can sit on either side
+                    match (small_imm(lhs), small_imm(rhs)) {
+                        (_, Some(imm)) => {
+                            let rs1 =
+                                operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Addi { rd, rs1, imm });
+                        }
+                        (Some(imm), None) => {
+                            let rs1 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Addi { rd, rs1, imm });
+                        }
+                        (None, None) => {
+                            let rs1 =
+                                operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Add { rd, rs1, rs2 });
+                        }
+                    }
                 }
 
-                IrInstruction::Add { dest, lhs, rhs } => {
+                IrInstruction::Mul { dest, lhs, rhs, .. } => {
                     let rd = allocate_reg(dest);
-                    let rs1 = allocate_reg(lhs);
-                    let rs2 = allocate_reg(rhs);
+
+                    // x * 2^k  ->  x << k
+                    match (pow2_shift(lhs), pow2_shift(rhs)) {
+                        (_, Some(imm)) => {
+                            let rs1 =
+                                operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Slli { rd, rs1, imm });
+                        }
+                        (Some(imm), None) => {
+                            let rs1 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Slli { rd, rs1, imm });
+                        }
+                        (None, None) => {
+                            let rs1 =
+                                operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Mul { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::Sub { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
 
                     machine_block
                         .instrs
-                        .push(MachineInstr::Add { rd, rs1, rs2 });
+                        .push(MachineInstr::Sub { rd, rs1, rs2 });
                 }
 
-                IrInstruction::Mul { dest, lhs, rhs } => {
+                IrInstruction::Div { dest, lhs, rhs, .. } => {
                     let rd = allocate_reg(dest);
-                    let rs1 = allocate_reg(lhs);
-                    let rs2 = allocate_reg(rhs);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
 
                     machine_block
                         .instrs
-                        .push(MachineInstr::Mul { rd, rs1, rs2 });
+                        .push(MachineInstr::Div { rd, rs1, rs2 });
+                }
+
+                // booleans are 0/1, so `not` is just a compare against zero
+                IrInstruction::Not { dest, args, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(args, &mut machine_block.instrs, &mut allocate_reg);
+
+                    machine_block.instrs.push(MachineInstr::Seqz { rd, rs1 });
                 }
 
-                IrInstruction::Sub { dest, lhs, rhs } => {
+                // rd = true_val, then overwritten with false_val when cond is 0
+                IrInstruction::Select {
+                    dest,
+                    cond,
+                    true_val,
+                    false_val,
+                    ..
+                } => {
                     let rd = allocate_reg(dest);
-                    let rs1 = allocate_reg(lhs);
-                    let rs2 = allocate_reg(rhs);
+                    let cond = operand_reg(cond, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs_true =
+                        operand_reg(true_val, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs_false =
+                        operand_reg(false_val, &mut machine_block.instrs, &mut allocate_reg);
 
                     machine_block
                         .instrs
-                        .push(MachineInstr::Sub { rd, rs1, rs2 });
+                        .push(MachineInstr::Mv { rd, rs1: rs_true });
+                    machine_block.instrs.push(MachineInstr::MvEqz {
+                        rd,
+                        cond,
+                        rs1: rs_false,
+                    });
+                }
+
+                IrInstruction::And { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match small_imm(rhs) {
+                        Some(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Andi { rd, rs1, imm }),
+                        None => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::And { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::Or { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match small_imm(rhs) {
+                        Some(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Ori { rd, rs1, imm }),
+                        None => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block.instrs.push(MachineInstr::Or { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::Xor { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match small_imm(rhs) {
+                        Some(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Xori { rd, rs1, imm }),
+                        None => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Xor { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::Shl { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match rhs.parse::<i64>() {
+                        Ok(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Slli { rd, rs1, imm }),
+                        Err(_) => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Sll { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::Shr { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match rhs.parse::<i64>() {
+                        Ok(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Srai { rd, rs1, imm }),
+                        Err(_) => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Sra { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                IrInstruction::UShr { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match rhs.parse::<i64>() {
+                        Ok(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Srli { rd, rs1, imm }),
+                        Err(_) => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Srl { rd, rs1, rs2 });
+                        }
+                    }
                 }
 
-                IrInstruction::Div { dest, lhs, rhs } => {
+                // a == b  ->  sub t, a, b; seqz rd, t
+                IrInstruction::Eq { dest, lhs, rhs, .. } => {
                     let rd = allocate_reg(dest);
-                    let rs1 = allocate_reg(lhs);
-                    let rs2 = allocate_reg(rhs);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let diff = allocate_reg(&format!("{}.cmp", dest));
 
                     machine_block
                         .instrs
-                        .push(MachineInstr::Div { rd, rs1, rs2 });
+                        .push(MachineInstr::Sub { rd: diff, rs1, rs2 });
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Seqz { rd, rs1: diff });
+                }
+
+                IrInstruction::Lt { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    match small_imm(rhs) {
+                        Some(imm) => machine_block
+                            .instrs
+                            .push(MachineInstr::Slti { rd, rs1, imm }),
+                        None => {
+                            let rs2 =
+                                operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                            machine_block
+                                .instrs
+                                .push(MachineInstr::Slt { rd, rs1, rs2 });
+                        }
+                    }
+                }
+
+                // a > b  ->  b < a
+                IrInstruction::Gt { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Slt { rd, rs1, rs2 });
+                }
+
+                // a <= b  ->  !(b < a)
+                IrInstruction::Le { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let gt = allocate_reg(&format!("{}.cmp", dest));
+
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Slt { rd: gt, rs1, rs2 });
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Seqz { rd, rs1: gt });
+                }
+
+                // a >= b  ->  !(a < b)
+                IrInstruction::Ge { dest, lhs, rhs, .. } => {
+                    let rd = allocate_reg(dest);
+                    let rs1 = operand_reg(lhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let rs2 = operand_reg(rhs, &mut machine_block.instrs, &mut allocate_reg);
+                    let lt = allocate_reg(&format!("{}.cmp", dest));
+
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Slt { rd: lt, rs1, rs2 });
+                    machine_block
+                        .instrs
+                        .push(MachineInstr::Seqz { rd, rs1: lt });
                 }
 
                 IrInstruction::Call {
                     dest,
                     target_func,
                     args,
+                    ..
                 } => {
+                    let stack_bytes = args.len().saturating_sub(ARG_REGS.len()) * 8;
+                    machine_func.outgoing_arg_bytes =
+                        machine_func.outgoing_arg_bytes.max(stack_bytes);
+
                     for (i, arg) in args.iter().enumerate() {
-                        let src_reg = allocate_reg(arg);
-                        if i < 8 {
-                            let a_reg = match i {
-                                0 => VReg::A0,
-                                1 => VReg::A1,
-                                2 => VReg::A2,
-                                3 => VReg::A3,
-                                4 => VReg::A4,
-                                5 => VReg::A5,
-                                6 => VReg::A6,
-                                7 => VReg::A7,
-                                _ => unreachable!(),
-                            };
+                        let src_reg =
+                            operand_reg(arg, &mut machine_block.instrs, &mut allocate_reg);
+                        if let Some(&a_reg) = ARG_REGS.get(i) {
                             machine_block.instrs.push(MachineInstr::Mv {
                                 rd: a_reg,
                                 rs1: src_reg,
                             });
                         } else {
                             let offset = ((i - 8) * 8) as i32;
-                            machine_block.instrs.push(MachineInstr::Sw {
+                            machine_block.instrs.push(MachineInstr::Sd {
                                 offset,
                                 base: VReg::SP,
                                 rs1: src_reg,
@@ -118,7 +437,7 @@ pub fn select_instructions(func: &IrFunction) -> MachineFunc {
                         label: target_func.to_string(),
                     });
 
-                    if let Some(d) = dest {
+                    if let CallDest::Named(d) = dest {
                         let return_value = allocate_reg(d);
                         // A0 is the returh value
                         machine_block.instrs.push(MachineInstr::Mv {
@@ -133,7 +452,12 @@ pub fn select_instructions(func: &IrFunction) -> MachineFunc {
                     then_lbl,
                     else_lbl,
                 } => {
-                    let rs1 = allocate_reg(cond);
+                    // already lowered along with the comparison before it
+                    if fused_branch {
+                        continue;
+                    }
+
+                    let rs1 = operand_reg(cond, &mut machine_block.instrs, &mut allocate_reg);
 
                     // if rs1 = 0
                     // goto else_lbl
@@ -157,17 +481,168 @@ pub fn select_instructions(func: &IrFunction) -> MachineFunc {
                 IrInstruction::Ret { args } => {
                     let mut rd = None;
 
-                    if args.is_empty() {
-                        rd = Some(allocate_reg(&args[0]));
+                    // the return value is passed back through a0, keeping it
+                    // on the `Ret` marks a0 live up to the return
+                    if !args.is_empty() {
+                        let rs1 =
+                            operand_reg(&args[0], &mut machine_block.instrs, &mut allocate_reg);
+                        machine_block
+                            .instrs
+                            .push(MachineInstr::Mv { rd: VReg::A0, rs1 });
+                        rd = Some(VReg::A0);
                     }
 
                     machine_block.instrs.push(MachineInstr::Ret { rd });
                 }
 
-                _ => {}
+                // Phis have to be turned into copies before we get here
+                IrInstruction::Phi { dest, .. } => {
+                    debug_assert!(false, "phi for `{}` reached instruction selection", dest);
+                }
+
+                // one runtime call per value, so each of them is its own call
+                // site and the values printed later stay live across it
+                IrInstruction::Print { values } => {
+                    for value in values {
+                        let arg = operand_reg(value, &mut machine_block.instrs, &mut allocate_reg);
+                        machine_block.instrs.push(MachineInstr::Print { arg });
+                    }
+                    machine_block.instrs.push(MachineInstr::PrintNewline);
+                }
+
+                #[cfg(feature = "float")]
+                IrInstruction::FAdd { .. }
+                | IrInstruction::FSub { .. }
+                | IrInstruction::FMul { .. }
+                | IrInstruction::FDiv { .. } => {
+                    todo!("float instructions need the RV64D extension")
+                }
             }
         }
-        machine_func.blocks.push(machine_block.clone());
     }
+
+    if let Some(entry) = machine_func.blocks.get_mut(func.entry_block()) {
+        entry.instrs.splice(0..0, arg_moves);
+    }
+    compute_preds(&mut machine_func);
+
     machine_func
 }
+
+/// Branch to `label` when the comparison `instr` holds, without materializing
+/// its result. Literal operands get loaded into `instrs` first. Returns `None`
+/// for anything that isn't a comparison.
+fn compare_and_branch(
+    instr: &IrInstruction,
+    label: &str,
+    instrs: &mut Vec<MachineInstr>,
+    allocate_reg: &mut impl FnMut(&String) -> VReg,
+) -> Option<MachineInstr> {
+    // a > b  ->  b < a,  a <= b  ->  b >= a
+    let (lhs, rhs) = match instr {
+        IrInstruction::Eq { lhs, rhs, .. }
+        | IrInstruction::Lt { lhs, rhs, .. }
+        | IrInstruction::Ge { lhs, rhs, .. } => (lhs, rhs),
+        IrInstruction::Gt { lhs, rhs, .. } | IrInstruction::Le { lhs, rhs, .. } => (rhs, lhs),
+        _ => return None,
+    };
+    let rs1 = operand_reg(lhs, instrs, allocate_reg);
+    let rs2 = operand_reg(rhs, instrs, allocate_reg);
+    let label = label.to_string();
+
+    let branch = match instr {
+        IrInstruction::Eq { .. } => MachineInstr::Beq { rs1, rs2, label },
+        IrInstruction::Lt { .. } | IrInstruction::Gt { .. } => {
+            MachineInstr::Blt { rs1, rs2, label }
+        }
+        _ => MachineInstr::Bge { rs1, rs2, label },
+    };
+
+    Some(branch)
+}
+
+/// Loads `value` into `rd`. A string becomes a `.rodata` entry of the function
+/// and `rd` gets its address
+fn load_literal(
+    rd: VReg,
+    value: &Literal,
+    func_name: &str,
+    strings: &mut Vec<(String, String)>,
+) -> MachineInstr {
+    match value {
+        Literal::Str(s) => {
+            let label = format!("{}.str{}", func_name, strings.len());
+            strings.push((label.clone(), s.clone()));
+            MachineInstr::La { rd, label }
+        }
+        _ => load_imm(rd, literal_imm(value)),
+    }
+}
+
+/// Value `li` loads for a literal
+fn literal_imm(value: &Literal) -> i64 {
+    match value {
+        Literal::Int(i) => *i,
+        Literal::Bool(b) => *b as i64,
+        Literal::Str(_) => unreachable!("strings are loaded by address"),
+        #[cfg(feature = "float")]
+        Literal::Float(_) => todo!("float constants need the RV64D extension"),
+    }
+}
+
+/// Register holding `operand`. Literals don't live anywhere, so they get
+/// loaded right before the instruction reading them, except 0 which is `zero`
+fn operand_reg(
+    operand: &String,
+    instrs: &mut Vec<MachineInstr>,
+    allocate_reg: &mut impl FnMut(&String) -> VReg,
+) -> VReg {
+    match operand_imm(operand) {
+        Some(0) => VReg::Zero,
+        Some(imm) => {
+            let rd = allocate_reg(operand);
+            instrs.push(load_imm(rd, imm));
+            rd
+        }
+        None => allocate_reg(operand),
+    }
+}
+
+/// Value of an operand that is an int or bool literal
+fn operand_imm(operand: &str) -> Option<i64> {
+    operand
+        .parse::<i64>()
+        .ok()
+        .or_else(|| operand.parse::<bool>().ok().map(i64::from))
+}
+
+/// `addi rd, zero, imm` when the constant fits in 12 bits, `li` otherwise so
+/// the assembler can expand it to `lui`+`addi`
+fn load_imm(rd: VReg, imm: i64) -> MachineInstr {
+    if (-2048..2048).contains(&imm) {
+        MachineInstr::Addi {
+            rd,
+            rs1: VReg::Zero,
+            imm,
+        }
+    } else {
+        MachineInstr::Li { rd, imm }
+    }
+}
+
+/// The operand as an immediate, if it is a constant that fits the 12 signed
+/// bits of an I-type instruction
+fn small_imm(operand: &str) -> Option<i64> {
+    operand
+        .parse::<i64>()
+        .ok()
+        .filter(|imm| (-2048..2048).contains(imm))
+}
+
+/// Shift amount `k` when the operand is the constant 2^k (k > 0)
+fn pow2_shift(operand: &str) -> Option<i64> {
+    match operand.parse::<i64>() {
+        Ok(n) if n > 1 && n.count_ones() == 1 => Some(n.trailing_zeros() as i64),
+        _ => None,
+    }
+}
@@ -1,23 +1,1308 @@
+pub mod coalesce;
+pub mod graph_color;
 pub mod instruction_sel;
 pub mod machine_ir;
+pub mod peephole;
 pub mod register_alloc;
 pub mod riscv_emission;
 
+pub use coalesce::CoalescingPass;
+pub use graph_color::GraphColoringAlloc;
 pub use instruction_sel::select_instructions;
 //pub use machine_ir::MachineBlock;
 //pub use machine_ir::MachineFunc;
 //pub use machine_ir::MachineInstr;
 pub use machine_ir::*;
+pub use peephole::PeepholePass;
 pub use register_alloc::*;
-pub use riscv_emission::emit_riscv;
+pub use riscv_emission::{emit_data_section, emit_riscv};
 
 // some change
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ir::{IrFunction, IrInstruction, IrType};
+    use std::collections::HashMap;
 
     #[test]
     fn it_works() {
         assert_eq!(4, 4);
     }
+
+    #[test]
+    fn virtual_regs_print_their_number() {
+        assert_eq!(VReg::Virtual(42).name(), "%42");
+        assert_eq!(VReg::A0.name(), "a0");
+    }
+
+    #[test]
+    fn interference_graph_links_values_live_together() {
+        let v = VReg::Virtual;
+        let mf = MachineFunc {
+            name: "f".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li { rd: v(0), imm: 1 },
+                    MachineInstr::Li { rd: v(1), imm: 2 },
+                    MachineInstr::Add {
+                        rd: v(2),
+                        rs1: v(0),
+                        rs2: v(1),
+                    },
+                    MachineInstr::Mv {
+                        rd: VReg::A0,
+                        rs1: v(2),
+                    },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        let graph = build_interference_graph(&mf);
+
+        // v0 and v1 are both live at the add
+        assert!(graph[&v(0)].contains(&v(1)));
+        assert!(graph[&v(1)].contains(&v(0)));
+        // the add's result only starts living once its operands are dead
+        assert!(graph[&v(2)].is_empty());
+        // pre-colored registers are part of the graph
+        assert!(graph.contains_key(&VReg::A0));
+    }
+
+    #[test]
+    fn coalescing_keeps_moves_between_interfering_regs() {
+        let v = VReg::Virtual;
+        let mut mf = MachineFunc {
+            name: "f".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li { rd: v(0), imm: 1 },
+                    MachineInstr::Mv {
+                        rd: v(1),
+                        rs1: v(0),
+                    },
+                    MachineInstr::Add {
+                        rd: v(2),
+                        rs1: v(1),
+                        rs2: v(1),
+                    },
+                    MachineInstr::Mv {
+                        rd: v(3),
+                        rs1: v(2),
+                    },
+                    // v2 gets overwritten while v3 is live, so they interfere
+                    MachineInstr::Addi {
+                        rd: v(2),
+                        rs1: v(2),
+                        imm: 1,
+                    },
+                    MachineInstr::Add {
+                        rd: VReg::A0,
+                        rs1: v(3),
+                        rs2: v(2),
+                    },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        assert!(CoalescingPass::new().run(&mut mf));
+
+        let instrs = &mf.blocks[0].instrs;
+        let moves = instrs
+            .iter()
+            .filter(|i| matches!(i, MachineInstr::Mv { .. }))
+            .count();
+        assert_eq!(moves, 1);
+        assert!(matches!(
+            instrs[1],
+            MachineInstr::Add { rs1, rs2, .. } if rs1 == v(1) && rs2 == v(1)
+        ));
+    }
+
+    #[test]
+    fn spill_code_reloads_uses_and_stores_defs() {
+        let v = VReg::Virtual;
+        let mut mf = MachineFunc {
+            name: "f".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li { rd: v(0), imm: 1 },
+                    MachineInstr::Add {
+                        rd: v(1),
+                        rs1: v(0),
+                        rs2: v(0),
+                    },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        let mut intervals = LinearScan::new().build_intervals(&mf);
+        intervals.get_mut(&v(0)).unwrap().mark_spilled = true;
+        let intervals = intervals
+            .into_iter()
+            .map(|(vreg, iv)| {
+                let live = LiveIntervals {
+                    vreg,
+                    start: iv.start,
+                    end: iv.end,
+                    phy_reg: iv.phy_reg,
+                    mark_spilled: iv.mark_spilled,
+                    crosses_call: iv.crosses_call,
+                };
+                (vreg, live)
+            })
+            .collect();
+
+        let mut slots = HashMap::new();
+        assert!(insert_spill_code(&mut mf, &intervals, &mut slots));
+        assert_eq!(slots[&v(0)], 0);
+
+        let instrs = &mf.blocks[0].instrs;
+        assert_eq!(instrs.len(), 4);
+        assert!(matches!(instrs[0], MachineInstr::Li { rd, .. } if rd == v(2)));
+        assert!(matches!(
+            instrs[1],
+            MachineInstr::Sd { rs1, offset: 0, base: VReg::SP } if rs1 == v(2)
+        ));
+        assert!(matches!(
+            instrs[2],
+            MachineInstr::Ld { rd, offset: 0, base: VReg::SP } if rd == v(3)
+        ));
+        assert!(matches!(
+            instrs[3],
+            MachineInstr::Add { rs1, rs2, .. } if rs1 == v(3) && rs2 == v(3)
+        ));
+    }
+
+    #[test]
+    fn loads_define_rd_and_read_base() {
+        let v = VReg::Virtual;
+        let lw = MachineInstr::Lw {
+            rd: v(0),
+            offset: 4,
+            base: VReg::SP,
+        };
+        let ld = MachineInstr::Ld {
+            rd: v(1),
+            offset: 8,
+            base: v(0),
+        };
+
+        assert_eq!(lw.defs(), vec![v(0)]);
+        assert_eq!(lw.uses(), vec![VReg::SP]);
+        assert_eq!(ld.defs(), vec![v(1)]);
+        assert_eq!(ld.uses(), vec![v(0)]);
+    }
+
+    #[test]
+    fn comparisons_lower_to_slt_sequences() {
+        let mut func = IrFunction::new("cmp");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Le {
+                dest: "le".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::Lt {
+                dest: "lt".into(),
+                lhs: "a".into(),
+                rhs: "10".into(),
+                ty: None,
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert_eq!(instrs.len(), 3);
+        // a <= b is lowered as !(b < a)
+        assert!(matches!(
+            (&instrs[0], &instrs[1]),
+            (MachineInstr::Slt { rd, .. }, MachineInstr::Seqz { rs1, .. }) if rd == rs1
+        ));
+        assert!(matches!(instrs[2], MachineInstr::Slti { imm: 10, .. }));
+    }
+
+    #[test]
+    fn logical_ops_lower_to_bitwise_instrs() {
+        let mut func = IrFunction::new("bits");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::And {
+                dest: "x".into(),
+                lhs: "a".into(),
+                rhs: "1".into(),
+                ty: None,
+            },
+            IrInstruction::Or {
+                dest: "y".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::Not {
+                dest: "z".into(),
+                args: "y".into(),
+                ty: None,
+            },
+            IrInstruction::Xor {
+                dest: "w".into(),
+                lhs: "x".into(),
+                rhs: "y".into(),
+                ty: None,
+            },
+            IrInstruction::Xor {
+                dest: "v".into(),
+                lhs: "w".into(),
+                rhs: "-1".into(),
+                ty: None,
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert!(matches!(instrs[0], MachineInstr::Andi { imm: 1, .. }));
+        assert!(matches!(instrs[1], MachineInstr::Or { .. }));
+        assert!(matches!(instrs[2], MachineInstr::Seqz { .. }));
+        assert!(matches!(instrs[3], MachineInstr::Xor { .. }));
+        assert!(matches!(instrs[4], MachineInstr::Xori { imm: -1, .. }));
+    }
+
+    #[test]
+    fn shifts_pick_arithmetic_or_logical_instrs() {
+        let mut func = IrFunction::new("shifts");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Shr {
+                dest: "x".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::UShr {
+                dest: "y".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::UShr {
+                dest: "z".into(),
+                lhs: "a".into(),
+                rhs: "3".into(),
+                ty: None,
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert!(matches!(instrs[0], MachineInstr::Sra { .. }));
+        assert!(matches!(instrs[1], MachineInstr::Srl { .. }));
+        assert!(matches!(instrs[2], MachineInstr::Srli { imm: 3, .. }));
+    }
+
+    #[test]
+    fn small_constants_fold_into_immediates() {
+        let mut func = IrFunction::new("imm");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Add {
+                dest: "x".into(),
+                lhs: "5".into(),
+                rhs: "a".into(),
+                ty: None,
+            },
+            IrInstruction::Add {
+                dest: "y".into(),
+                lhs: "a".into(),
+                rhs: "4096".into(),
+                ty: None,
+            },
+            IrInstruction::Mul {
+                dest: "z".into(),
+                lhs: "a".into(),
+                rhs: "8".into(),
+                ty: None,
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert!(matches!(instrs[0], MachineInstr::Addi { imm: 5, .. }));
+        // out of the 12-bit range, gets loaded and stays a register add
+        let MachineInstr::Li { rd, imm: 4096 } = instrs[1] else {
+            panic!("expected 4096 to be loaded, got {:?}", instrs[1]);
+        };
+        assert!(matches!(instrs[2], MachineInstr::Add { rs2, .. } if rs2 == rd));
+        assert!(matches!(instrs[3], MachineInstr::Slli { imm: 3, .. }));
+    }
+
+    #[test]
+    fn literal_operands_are_loaded_before_use() {
+        let mut func = IrFunction::new("lit");
+        func.args = vec![("a".to_string(), IrType::I64)];
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Gt {
+                dest: "c".into(),
+                lhs: "a".into(),
+                rhs: "7".into(),
+                ty: None,
+            },
+            IrInstruction::Eq {
+                dest: "d".into(),
+                lhs: "c".into(),
+                rhs: "true".into(),
+                ty: None,
+            },
+            IrInstruction::Ret {
+                args: vec!["0".into()],
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        // 7 is loaded into the register the comparison reads
+        let MachineInstr::Addi {
+            rd: seven,
+            rs1: VReg::Zero,
+            imm: 7,
+        } = instrs[1]
+        else {
+            panic!("expected 7 to be loaded, got {:?}", instrs[1]);
+        };
+        assert!(matches!(instrs[2], MachineInstr::Slt { rs1, .. } if rs1 == seven));
+
+        // so is `true`, as 1
+        let MachineInstr::Addi {
+            rd: one, imm: 1, ..
+        } = instrs[3]
+        else {
+            panic!("expected true to be loaded, got {:?}", instrs[3]);
+        };
+        assert!(matches!(instrs[4], MachineInstr::Sub { rs2, .. } if rs2 == one));
+
+        // 0 is just the zero register
+        assert!(instrs.contains(&MachineInstr::Mv {
+            rd: VReg::A0,
+            rs1: VReg::Zero,
+        }));
+    }
+
+    #[test]
+    fn assigns_copy_variables_and_load_literals() {
+        let mut func = IrFunction::new("assign");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Assign {
+                lhs: "x".into(),
+                rhs: ir::AssignRhs::Var("a".into()),
+                ty: None,
+            },
+            IrInstruction::Assign {
+                lhs: "y".into(),
+                rhs: ir::AssignRhs::Const(ir::cfg::Literal::Bool(true)),
+                ty: None,
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert!(matches!(instrs[0], MachineInstr::Mv { .. }));
+        assert!(matches!(
+            instrs[1],
+            MachineInstr::Addi {
+                rs1: VReg::Zero,
+                imm: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn only_constants_past_12_bits_need_li() {
+        let mut func = IrFunction::new("consts");
+        let entry = func.add_block("entry").unwrap();
+        for (dest, n) in [("a", 2047), ("b", -2048), ("c", 2048), ("d", -2049)] {
+            func.append_instr(
+                entry,
+                &IrInstruction::Const {
+                    dest: dest.into(),
+                    value: ir::cfg::Literal::Int(n),
+                    ty: None,
+                },
+            );
+        }
+
+        let mf = select_instructions(&func);
+        let imms: Vec<(bool, i64)> = mf.blocks[0]
+            .instrs
+            .iter()
+            .map(|instr| match instr {
+                MachineInstr::Addi {
+                    rs1: VReg::Zero,
+                    imm,
+                    ..
+                } => (false, *imm),
+                MachineInstr::Li { imm, .. } => (true, *imm),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            imms,
+            vec![(false, 2047), (false, -2048), (true, 2048), (true, -2049)]
+        );
+
+        // x0 never competes for a register
+        let intervals = LinearScan::new().build_intervals(&mf);
+        assert!(!intervals.contains_key(&VReg::Zero));
+    }
+
+    #[test]
+    fn compare_feeding_only_a_branch_is_fused() {
+        let mut func = IrFunction::new("fuse");
+        let entry = func.add_block("entry").unwrap();
+        let then = func.add_block("then").unwrap();
+        let els = func.add_block("else").unwrap();
+        func.blocks[entry].succs = vec![then, els];
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Gt {
+                dest: "c".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::Br {
+                cond: "c".into(),
+                then_lbl: "then".into(),
+                else_lbl: "else".into(),
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[entry].instrs;
+
+        assert_eq!(instrs.len(), 2);
+        assert!(matches!(&instrs[0], MachineInstr::Blt { label, .. } if label == "then"));
+        assert!(matches!(&instrs[1], MachineInstr::Jmp { label } if label == "else"));
+    }
+
+    #[test]
+    fn fused_compare_loads_its_literal_operands() {
+        let mut func = IrFunction::new("fuse_lit");
+        let entry = func.add_block("entry").unwrap();
+        let then = func.add_block("then").unwrap();
+        let els = func.add_block("else").unwrap();
+        func.blocks[entry].succs = vec![then, els];
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Ge {
+                dest: "c".into(),
+                lhs: "a".into(),
+                rhs: "100".into(),
+                ty: None,
+            },
+            IrInstruction::Br {
+                cond: "c".into(),
+                then_lbl: "then".into(),
+                else_lbl: "else".into(),
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[entry].instrs;
+
+        assert_eq!(instrs.len(), 3);
+        let MachineInstr::Addi {
+            rd: hundred,
+            rs1: VReg::Zero,
+            imm: 100,
+        } = instrs[0]
+        else {
+            panic!("expected 100 to be loaded, got {:?}", instrs[0]);
+        };
+        assert!(matches!(
+            &instrs[1],
+            MachineInstr::Bge { rs2, label, .. } if *rs2 == hundred && label == "then"
+        ));
+    }
+
+    #[test]
+    fn emit_riscv_writes_into_any_writer() {
+        let v = VReg::Virtual;
+        let mf = MachineFunc {
+            name: "main".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li { rd: v(0), imm: 7 },
+                    MachineInstr::Mv {
+                        rd: VReg::A0,
+                        rs1: v(0),
+                    },
+                    MachineInstr::Ret { rd: None },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.starts_with(".section .text\n"));
+        assert!(asm.contains(".globl main\n"));
+        assert!(asm.contains("\nmain:\n"));
+        assert!(asm.contains("  li "));
+        assert!(asm.trim_end().ends_with("ret"));
+    }
+
+    #[test]
+    fn ret_moves_its_value_into_a0() {
+        let mut func = IrFunction::new("ret");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Const {
+                dest: "x".into(),
+                value: ir::cfg::Literal::Int(3),
+                ty: None,
+            },
+            IrInstruction::Ret {
+                args: vec!["x".into()],
+            },
+        ];
+
+        let mut mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+        assert!(matches!(instrs[1], MachineInstr::Mv { rd: VReg::A0, .. }));
+        assert!(matches!(
+            instrs[2],
+            MachineInstr::Ret { rd: Some(VReg::A0) }
+        ));
+
+        // a hand-built `Ret` with a value still ends up in a0
+        mf.blocks[0].instrs = vec![
+            MachineInstr::Li {
+                rd: VReg::Virtual(0),
+                imm: 3,
+            },
+            MachineInstr::Ret {
+                rd: Some(VReg::Virtual(0)),
+            },
+        ];
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.contains("  mv a0, "));
+        assert!(!asm.contains("ret "));
+    }
+
+    #[test]
+    fn incoming_args_are_copied_out_of_arg_registers() {
+        let mut func = IrFunction::new("many_args");
+        func.args = (0..9).map(|i| (format!("p{}", i), IrType::I64)).collect();
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![IrInstruction::Ret {
+            args: vec!["p8".into()],
+        }];
+
+        let mf = select_instructions(&func);
+        let instrs = &mf.blocks[0].instrs;
+
+        assert_eq!(mf.args.len(), 9);
+        assert!(matches!(
+            instrs[0],
+            MachineInstr::Mv { rd, rs1: VReg::A0 } if rd == mf.args[0]
+        ));
+        assert!(matches!(
+            instrs[7],
+            MachineInstr::Mv { rd, rs1: VReg::A7 } if rd == mf.args[7]
+        ));
+        // the 9th argument comes from the caller's stack
+        assert!(matches!(
+            instrs[8],
+            MachineInstr::Ld { rd, offset: 0, base: VReg::S0 } if rd == mf.args[8]
+        ));
+    }
+
+    #[test]
+    fn machine_liveness_stretches_intervals_around_loops() {
+        let v = VReg::Virtual;
+        let block = |name: &str, instrs, succs| MachineBlock {
+            name: name.to_string(),
+            instrs,
+            succs,
+            preds: Vec::new(),
+        };
+        let mf = MachineFunc {
+            name: "loop".to_string(),
+            blocks: vec![
+                block(
+                    "entry",
+                    vec![MachineInstr::Li { rd: v(0), imm: 1 }],
+                    vec![1],
+                ),
+                block(
+                    "body",
+                    vec![
+                        MachineInstr::Li { rd: v(1), imm: 2 },
+                        MachineInstr::Add {
+                            rd: v(2),
+                            rs1: v(0),
+                            rs2: v(1),
+                        },
+                        MachineInstr::Beqz {
+                            rs1: v(2),
+                            label: "body".to_string(),
+                        },
+                    ],
+                    vec![1, 2],
+                ),
+                block("exit", vec![MachineInstr::Ret { rd: None }], Vec::new()),
+            ],
+            ..Default::default()
+        };
+
+        let mut mf = mf;
+        compute_preds(&mut mf);
+        assert_eq!(mf.blocks[1].preds, vec![0, 1]);
+        assert_eq!(mf.blocks[2].preds, vec![1]);
+
+        let (live_out, live_in) = compute_machine_liveness(&mf);
+        assert!(live_in[1].contains(&v(0)));
+        assert!(live_out[1].contains(&v(0)));
+        assert!(!live_in[1].contains(&v(1)));
+
+        // v0 is read again on the next trip around, so it has to survive
+        // until the end of the loop body and not just its last use
+        let intervals = LinearScan::new().build_intervals(&mf);
+        assert_eq!(intervals[&v(0)].start, 0);
+        assert_eq!(intervals[&v(0)].end, 3);
+    }
+
+    #[test]
+    fn intervals_cover_blocks_laid_out_after_the_last_use() {
+        let v = VReg::Virtual;
+        let block = |name: &str, instrs, succs| MachineBlock {
+            name: name.to_string(),
+            instrs,
+            succs,
+            preds: Vec::new(),
+        };
+        // entry -> detour -> exit, but exit sits before detour in the layout
+        let mut mf = MachineFunc {
+            name: "detour".to_string(),
+            blocks: vec![
+                block(
+                    "entry",
+                    vec![
+                        MachineInstr::Li { rd: v(0), imm: 1 },
+                        MachineInstr::Jmp {
+                            label: "detour".to_string(),
+                        },
+                    ],
+                    vec![2],
+                ),
+                block(
+                    "exit",
+                    vec![
+                        MachineInstr::Mv {
+                            rd: VReg::A0,
+                            rs1: v(0),
+                        },
+                        MachineInstr::Ret { rd: None },
+                    ],
+                    Vec::new(),
+                ),
+                block(
+                    "detour",
+                    vec![
+                        MachineInstr::Li { rd: v(1), imm: 2 },
+                        MachineInstr::Print { arg: v(1) },
+                        MachineInstr::Jmp {
+                            label: "exit".to_string(),
+                        },
+                    ],
+                    vec![1],
+                ),
+            ],
+            ..Default::default()
+        };
+        compute_preds(&mut mf);
+
+        // v0's last use is at position 2, but it's still live through the
+        // detour at positions 4-6
+        let mut scan = LinearScan::new();
+        let mut intervals = scan.build_intervals(&mf);
+        assert_eq!(intervals[&v(0)].start, 0);
+        assert_eq!(intervals[&v(0)].end, 6);
+
+        let allocated = scan.linear_scan(&mut intervals);
+        assert_ne!(allocated[&v(0)].phy_reg, allocated[&v(1)].phy_reg);
+    }
+
+    #[test]
+    fn values_live_across_calls_get_callee_saved_regs() {
+        let v = VReg::Virtual;
+        let call = MachineInstr::Jal {
+            rd: VReg::RA,
+            label: "f".to_string(),
+        };
+        let func = |instrs| MachineFunc {
+            name: "caller".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs,
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        // v0 is read after the call, v1 is only its argument and v2 its result
+        let mf = func(vec![
+            MachineInstr::Li { rd: v(0), imm: 1 },
+            MachineInstr::Li { rd: v(1), imm: 2 },
+            MachineInstr::Mv {
+                rd: VReg::A0,
+                rs1: v(1),
+            },
+            call.clone(),
+            MachineInstr::Mv {
+                rd: v(2),
+                rs1: VReg::A0,
+            },
+            MachineInstr::Add {
+                rd: v(3),
+                rs1: v(0),
+                rs2: v(2),
+            },
+            MachineInstr::Ret { rd: Some(v(3)) },
+        ]);
+
+        let mut scan = LinearScan::new();
+        let mut intervals = scan.build_intervals(&mf);
+        assert!(intervals[&v(0)].crosses_call);
+        assert!(!intervals[&v(1)].crosses_call);
+        assert!(!intervals[&v(2)].crosses_call);
+
+        let allocated = scan.linear_scan(&mut intervals);
+        let reg = |n| allocated[&v(n)].phy_reg.unwrap();
+        assert!(CALLEE_SAVED.contains(&reg(0)));
+        assert!(!CALLEE_SAVED.contains(&reg(1)));
+        assert!(!CALLEE_SAVED.contains(&reg(2)));
+
+        // one value more than there are callee-saved registers, the last one
+        // has to wait out the call on the stack
+        let live = CALLEE_SAVED.len() as i32 + 1;
+        let mut instrs: Vec<MachineInstr> = (0..live)
+            .map(|n| MachineInstr::Li {
+                rd: v(n),
+                imm: n as i64,
+            })
+            .collect();
+        instrs.push(call);
+        for n in 1..live {
+            instrs.push(MachineInstr::Add {
+                rd: v(0),
+                rs1: v(0),
+                rs2: v(n),
+            });
+        }
+        instrs.push(MachineInstr::Ret { rd: Some(v(0)) });
+
+        let mf = func(instrs);
+        let mut intervals = scan.build_intervals(&mf);
+        let allocated = scan.linear_scan(&mut intervals);
+        let spilled: Vec<&LiveIntervals> =
+            allocated.values().filter(|iv| iv.mark_spilled).collect();
+        assert_eq!(spilled.len(), 1);
+        assert!(spilled[0].crosses_call);
+        assert!(
+            allocated
+                .values()
+                .filter_map(|iv| iv.phy_reg)
+                .all(|r| CALLEE_SAVED.contains(&r))
+        );
+    }
+
+    #[test]
+    fn print_calls_the_runtime_then_writes_a_newline() {
+        let mut func = IrFunction::new("main");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Const {
+                dest: "x".into(),
+                value: ir::cfg::Literal::Int(42),
+                ty: None,
+            },
+            IrInstruction::Print {
+                values: vec!["x".into()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        let mf = select_instructions(&func);
+        assert!(matches!(
+            &mf.blocks[0].instrs[1..3],
+            [MachineInstr::Print { .. }, MachineInstr::PrintNewline]
+        ));
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.contains("  call __choco_print_int\n"));
+        assert!(asm.contains("  li a7, 64\n  ecall\n"));
+        // the call clobbers ra, so it has to be saved
+        assert!(asm.contains("  sd ra, "));
+    }
+
+    #[test]
+    fn stack_arguments_of_a_call_sit_below_the_saved_registers() {
+        let mut func = IrFunction::new("caller");
+        func.args = vec![("a".into(), IrType::I64), ("b".into(), IrType::I64)];
+        let entry = func.add_block("entry").unwrap();
+        let mut instrs: Vec<IrInstruction> = (0..10)
+            .map(|n| IrInstruction::Const {
+                dest: format!("k{}", n),
+                value: ir::cfg::Literal::Int(n),
+                ty: None,
+            })
+            .collect();
+        // `x` lives across the call in a callee-saved register
+        instrs.push(IrInstruction::Add {
+            dest: "x".into(),
+            lhs: "a".into(),
+            rhs: "b".into(),
+            ty: None,
+        });
+        instrs.push(IrInstruction::Call {
+            target_func: "callee".into(),
+            args: (0..10).map(|n| format!("k{}", n)).collect(),
+            dest: ir::CallDest::Named("r".into()),
+            ty: None,
+        });
+        instrs.push(IrInstruction::Add {
+            dest: "y".into(),
+            lhs: "x".into(),
+            rhs: "r".into(),
+            ty: None,
+        });
+        instrs.push(IrInstruction::Ret {
+            args: vec!["y".into()],
+        });
+        func.blocks[entry].instrs = instrs;
+
+        let mf = select_instructions(&func);
+        assert_eq!(mf.outgoing_arg_bytes, 16);
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.contains(", 0(sp)\n"));
+        assert!(asm.contains(", 8(sp)\n"));
+        // callee-saved registers (s0 aside) are stored above the arguments
+        let saved: Vec<i64> = asm
+            .lines()
+            .filter(|l| l.starts_with("  sd s") && !l.starts_with("  sd s0,"))
+            .map(|l| {
+                let offset = l.rsplit(", ").next().unwrap();
+                offset.trim_end_matches("(sp)").parse().unwrap()
+            })
+            .collect();
+        assert!(!saved.is_empty());
+        assert!(saved.iter().all(|&o| o >= 16));
+    }
+
+    #[test]
+    fn stack_frames_stay_16_byte_aligned() {
+        let mut func = IrFunction::new("main");
+        func.args = vec![("a".into(), IrType::I64), ("b".into(), IrType::I64)];
+        let entry = func.add_block("entry").unwrap();
+        // `x` needs a callee-saved register or a spill slot, 8 bytes on top of ra and s0
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Add {
+                dest: "x".into(),
+                lhs: "a".into(),
+                rhs: "b".into(),
+                ty: None,
+            },
+            IrInstruction::Print {
+                values: vec!["a".into()],
+            },
+            IrInstruction::Ret {
+                args: vec!["x".into()],
+            },
+        ];
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[select_instructions(&func)], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.contains("  addi sp, sp, -32\n"));
+        assert!(asm.contains("  addi sp, sp, 32\n"));
+        assert!(asm.contains("  sd ra, 24(sp)\n"));
+    }
+
+    #[test]
+    fn later_print_values_survive_the_earlier_calls() {
+        let mut func = IrFunction::new("main");
+        let entry = func.add_block("entry").unwrap();
+        let konst = |dest: &str, n| IrInstruction::Const {
+            dest: dest.into(),
+            value: ir::cfg::Literal::Int(n),
+            ty: None,
+        };
+        func.blocks[entry].instrs = vec![
+            konst("x", 1),
+            konst("y", 2),
+            IrInstruction::Print {
+                values: vec!["x".into(), "y".into()],
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        let mf = select_instructions(&func);
+        let prints: Vec<VReg> = mf.blocks[0]
+            .instrs
+            .iter()
+            .filter_map(|i| match i {
+                MachineInstr::Print { arg } => Some(*arg),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(prints.len(), 2);
+
+        // y is still needed after x has been printed
+        let mut scan = LinearScan::new();
+        let mut intervals = scan.build_intervals(&mf);
+        assert!(!intervals[&prints[0]].crosses_call);
+        assert!(intervals[&prints[1]].crosses_call);
+
+        let allocated = scan.linear_scan(&mut intervals);
+        let y = &allocated[&prints[1]];
+        assert!(y.mark_spilled || CALLEE_SAVED.contains(&y.phy_reg.unwrap()));
+    }
+
+    #[test]
+    fn select_branches_over_a_single_mv() {
+        let mut func = IrFunction::new("pick");
+        func.args = ["c", "a", "b"]
+            .iter()
+            .map(|a| (a.to_string(), IrType::I64))
+            .collect();
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Select {
+                dest: "x".into(),
+                cond: "c".into(),
+                true_val: "a".into(),
+                false_val: "b".into(),
+                ty: None,
+            },
+            IrInstruction::Ret {
+                args: vec!["x".into()],
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        let [c, a, b] = [mf.args[0], mf.args[1], mf.args[2]];
+        let instrs = &mf.blocks[0].instrs;
+        assert!(matches!(instrs[3], MachineInstr::Mv { rs1, .. } if rs1 == a));
+        assert!(matches!(
+            instrs[4],
+            MachineInstr::MvEqz { cond, rs1, .. } if cond == c && rs1 == b
+        ));
+        // the old value of rd survives when cond is set
+        assert!(instrs[4].uses().contains(&instrs[4].defs()[0]));
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        let lines: Vec<&str> = asm.lines().collect();
+        let at = lines.iter().position(|l| l.ends_with(", 1f")).unwrap();
+        assert!(lines[at].starts_with("  bnez "));
+        assert!(lines[at + 1].starts_with("  mv "));
+        assert_eq!(lines[at + 2], "1:");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "reached instruction selection")]
+    fn phis_left_over_for_instruction_selection_are_caught() {
+        let mut func = IrFunction::new("phi");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Phi {
+                dest: "x".into(),
+                sources: Vec::new(),
+                ty: None,
+            },
+            IrInstruction::Ret { args: Vec::new() },
+        ];
+
+        select_instructions(&func);
+    }
+
+    #[test]
+    fn ret_with_and_without_a_value() {
+        let mut func = IrFunction::new("ret");
+        let value = func.add_block("value").unwrap();
+        let void = func.add_block("void").unwrap();
+        func.blocks[value].instrs = vec![IrInstruction::Ret {
+            args: vec!["x".into()],
+        }];
+        func.blocks[void].instrs = vec![IrInstruction::Ret { args: Vec::new() }];
+
+        // used to index args[0] on the empty case and panic
+        let mf = select_instructions(&func);
+
+        assert!(matches!(
+            mf.blocks[value].instrs.last(),
+            Some(MachineInstr::Ret { rd: Some(_) })
+        ));
+        assert!(matches!(
+            mf.blocks[void].instrs[..],
+            [MachineInstr::Ret { rd: None }]
+        ));
+    }
+
+    #[test]
+    fn machine_blocks_can_be_found_by_label() {
+        let mut func = IrFunction::new("labels");
+        let entry = func.add_block("entry").unwrap();
+        let exit = func.add_block("exit").unwrap();
+        func.add_edge(entry, exit);
+        func.blocks[entry].instrs = vec![IrInstruction::Jmp {
+            label: "exit".into(),
+        }];
+        func.blocks[exit].instrs = vec![IrInstruction::Ret { args: Vec::new() }];
+
+        let mut mf = select_instructions(&func);
+        assert_eq!(mf.block_index(&"entry".to_string()), Some(entry));
+        assert_eq!(mf.block_index(&"exit".to_string()), Some(exit));
+        assert_eq!(mf.blocks[exit].preds, vec![entry]);
+
+        // blocks added after selection can be looked up too
+        let extra = mf.add_block("extra");
+        assert_eq!(extra, 2);
+        assert_eq!(mf.block_index(&"extra".to_string()), Some(extra));
+        assert_eq!(mf.blocks[extra].name, "extra");
+    }
+
+    #[test]
+    fn graph_coloring_separates_interfering_and_coalesces_moves() {
+        let v = VReg::Virtual;
+        let mf = MachineFunc {
+            name: "f".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li { rd: v(0), imm: 1 },
+                    MachineInstr::Li { rd: v(1), imm: 2 },
+                    MachineInstr::Add {
+                        rd: v(2),
+                        rs1: v(0),
+                        rs2: v(1),
+                    },
+                    MachineInstr::Mv {
+                        rd: v(3),
+                        rs1: v(2),
+                    },
+                    MachineInstr::Mv {
+                        rd: VReg::A0,
+                        rs1: v(3),
+                    },
+                    MachineInstr::Ret { rd: None },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        let mut alloc = GraphColoringAlloc::new();
+        let colors = alloc.allocate(&mf);
+
+        // both operands of the add are live at the same time
+        assert_ne!(colors[&v(0)], colors[&v(1)]);
+        // the copy chain collapses onto the pre-colored return register
+        assert_eq!(colors[&v(2)], VReg::A0);
+        assert_eq!(colors[&v(3)], VReg::A0);
+        assert!(alloc.spilled["f"].is_empty());
+    }
+
+    #[test]
+    fn peephole_drops_redundant_moves() {
+        let mut mf = MachineFunc {
+            name: "f".to_string(),
+            blocks: vec![MachineBlock {
+                name: "entry".to_string(),
+                instrs: vec![
+                    MachineInstr::Li {
+                        rd: VReg::T0,
+                        imm: 1,
+                    },
+                    MachineInstr::Mv {
+                        rd: VReg::T0,
+                        rs1: VReg::T0,
+                    },
+                    MachineInstr::Addi {
+                        rd: VReg::T1,
+                        rs1: VReg::T0,
+                        imm: 0,
+                    },
+                    MachineInstr::Mv {
+                        rd: VReg::T0,
+                        rs1: VReg::T1,
+                    },
+                    MachineInstr::Li {
+                        rd: VReg::T2,
+                        imm: 3,
+                    },
+                    MachineInstr::Li {
+                        rd: VReg::T2,
+                        imm: 3,
+                    },
+                    // reads what it writes, the second one isn't a no-op
+                    MachineInstr::Addi {
+                        rd: VReg::T2,
+                        rs1: VReg::T2,
+                        imm: 1,
+                    },
+                    MachineInstr::Addi {
+                        rd: VReg::T2,
+                        rs1: VReg::T2,
+                        imm: 1,
+                    },
+                    MachineInstr::Ret { rd: None },
+                ],
+                succs: Vec::new(),
+                preds: Vec::new(),
+            }],
+            ..Default::default()
+        };
+
+        assert!(PeepholePass::new().run(&mut mf));
+        assert_eq!(
+            mf.blocks[0].instrs,
+            vec![
+                MachineInstr::Li {
+                    rd: VReg::T0,
+                    imm: 1
+                },
+                MachineInstr::Mv {
+                    rd: VReg::T1,
+                    rs1: VReg::T0,
+                },
+                MachineInstr::Li {
+                    rd: VReg::T2,
+                    imm: 3
+                },
+                MachineInstr::Addi {
+                    rd: VReg::T2,
+                    rs1: VReg::T2,
+                    imm: 1,
+                },
+                MachineInstr::Addi {
+                    rd: VReg::T2,
+                    rs1: VReg::T2,
+                    imm: 1,
+                },
+                MachineInstr::Ret { rd: None },
+            ]
+        );
+        assert!(!PeepholePass::new().run(&mut mf));
+    }
+
+    #[test]
+    fn globals_go_into_rodata() {
+        let mut out = Vec::new();
+        emit_data_section(&ir::IrModule::default(), &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let module = ir::IrModule {
+            globals: vec![
+                ir::GlobalVar {
+                    name: "answer".to_string(),
+                    value: ir::cfg::Literal::Int(42),
+                },
+                ir::GlobalVar {
+                    name: "greeting".to_string(),
+                    value: ir::cfg::Literal::Str("hi \"you\"\n".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+        emit_data_section(&module, &mut out).unwrap();
+
+        let asm = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = asm.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                ".section .rodata",
+                ".p2align 3",
+                "answer: .dword 42",
+                ".p2align 3",
+                r#"greeting: .asciz "hi \"you\"\n""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn string_constants_load_the_address_of_a_rodata_entry() {
+        let mut func = IrFunction::new("greet");
+        let entry = func.add_block("entry").unwrap();
+        func.blocks[entry].instrs = vec![
+            IrInstruction::Const {
+                dest: "s".into(),
+                value: ir::cfg::Literal::Str("hi\n".to_string()),
+                ty: None,
+            },
+            IrInstruction::Ret {
+                args: vec!["s".into()],
+            },
+        ];
+
+        let mf = select_instructions(&func);
+        assert_eq!(
+            mf.strings,
+            vec![("greet.str0".to_string(), "hi\n".to_string())]
+        );
+        assert!(matches!(
+            &mf.blocks[0].instrs[0],
+            MachineInstr::La { label, .. } if label == "greet.str0"
+        ));
+
+        let mut out: Vec<u8> = Vec::new();
+        emit_riscv(&[mf], &mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+
+        assert!(asm.contains(", greet.str0\n"));
+        assert!(asm.ends_with(".section .rodata\ngreet.str0: .asciz \"hi\\n\"\n"));
+    }
 }
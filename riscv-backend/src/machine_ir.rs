@@ -7,18 +7,41 @@ pub struct MachineFunc {
     pub args: Vec<VReg>,
     pub blocks: Vec<MachineBlock>,
     pub label_to_idx: HashMap<String, usize>,
+    /// String literals as (label, contents), they go into `.rodata`
+    pub strings: Vec<(String, String)>,
+    /// Bytes at the bottom of the frame for the arguments of a call that
+    /// don't fit in registers, sized for the call with the most of them
+    pub outgoing_arg_bytes: usize,
 }
 
 impl MachineFunc {
+    /// Starts out without blocks, they get added through `add_block` while the
+    /// instructions are selected
     pub fn new(func: &IrFunction) -> Self {
         Self {
             name: func.name.to_string(),
             args: Vec::new(),
             blocks: Vec::new(),
-            label_to_idx: func.label_to_idx.clone(),
+            label_to_idx: HashMap::new(),
+            strings: Vec::new(),
+            outgoing_arg_bytes: 0,
         }
     }
 
+    /// Adds an empty block and returns its index, keeping `label_to_idx` in
+    /// sync with `blocks`
+    pub fn add_block(&mut self, name: &str) -> usize {
+        let idx = self.blocks.len();
+        self.blocks.push(MachineBlock {
+            name: name.to_string(),
+            instrs: Vec::new(),
+            succs: Vec::new(),
+            preds: Vec::new(),
+        });
+        self.label_to_idx.insert(name.to_string(), idx);
+        idx
+    }
+
     pub fn block_index(&self, label: &String) -> Option<usize> {
         self.label_to_idx.get(label).copied()
     }
@@ -29,11 +52,31 @@ pub struct MachineBlock {
     pub name: String,
     pub instrs: Vec<MachineInstr>,
     pub succs: Vec<BlockID>,
+    pub preds: Vec<BlockID>,
+}
+
+/// Fills in every block's `preds` from the `succs` lists, same as
+/// `IrFunction::add_edge` keeps them in sync on the IR side
+pub fn compute_preds(mf: &mut MachineFunc) {
+    for block in mf.blocks.iter_mut() {
+        block.preds.clear();
+    }
+
+    for b in 0..mf.blocks.len() {
+        for s in mf.blocks[b].succs.clone() {
+            if !mf.blocks[s].preds.contains(&b) {
+                mf.blocks[s].preds.push(b);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum VReg {
     Virtual(i32),
+    // Hardwired to 0, writes to it are dropped
+    Zero,
+
     // Temp registers
     T0,
     T1,
@@ -81,6 +124,8 @@ pub enum VReg {
 impl VReg {
     pub fn name(&self) -> String {
         match self {
+            VReg::Zero => "zero".to_string(),
+
             VReg::T0 => "t0".to_string(),
             VReg::T1 => "t1".to_string(),
             VReg::T2 => "t2".to_string(),
@@ -116,13 +161,15 @@ impl VReg {
             VReg::RA => "ra".to_string(),
             VReg::GP => "gp".to_string(),
             VReg::FP => "fp".to_string(),
-            _ => "rt".to_string(),
+
+            // not allocated yet, only shows up in debug output
+            VReg::Virtual(n) => format!("%{}", n),
         }
     }
 }
 
 /// Machine Instructions, 1:1 to RiscV
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MachineInstr {
     // R1 = R2 + Imm
     Addi { rd: VReg, rs1: VReg, imm: i64 },
@@ -135,13 +182,63 @@ pub enum MachineInstr {
 
     Div { rd: VReg, rs1: VReg, rs2: VReg },
 
+    // Bitwise
+    Xor { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Or { rd: VReg, rs1: VReg, rs2: VReg },
+
+    And { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Xori { rd: VReg, rs1: VReg, imm: i64 },
+
+    Ori { rd: VReg, rs1: VReg, imm: i64 },
+
+    Andi { rd: VReg, rs1: VReg, imm: i64 },
+
+    // Shifts
+    Sll { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Srl { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Sra { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Slli { rd: VReg, rs1: VReg, imm: i64 },
+
+    Srli { rd: VReg, rs1: VReg, imm: i64 },
+
+    Srai { rd: VReg, rs1: VReg, imm: i64 },
+
+    // Comparisons
+    // rd = rs1 < rs2
+    Slt { rd: VReg, rs1: VReg, rs2: VReg },
+
+    Slti { rd: VReg, rs1: VReg, imm: i64 },
+
+    // rd = rs1 == 0
+    Seqz { rd: VReg, rs1: VReg },
+
+    // rd = rs1 != 0
+    Snez { rd: VReg, rs1: VReg },
+
     // Load & Store
     Li { rd: VReg, imm: i64 },
 
+    // rd = address of label
+    La { rd: VReg, label: String },
+
     Mv { rd: VReg, rs1: VReg },
 
+    // if cond == 0 { rd = rs1 }, a branch over a single mv
+    MvEqz { rd: VReg, cond: VReg, rs1: VReg },
+
     Sw { rs1: VReg, offset: i32, base: VReg },
 
+    Sd { rs1: VReg, offset: i32, base: VReg },
+
+    Lw { rd: VReg, offset: i32, base: VReg },
+
+    Ld { rd: VReg, offset: i32, base: VReg },
+
     // Control flow Instructions
     // May not be needed? Seems we can use
     // Pseudoinstructions like Call or Ret
@@ -154,15 +251,52 @@ pub enum MachineInstr {
 
     Beq { rs1: VReg, rs2: VReg, label: String },
 
+    Bne { rs1: VReg, rs2: VReg, label: String },
+
+    Blt { rs1: VReg, rs2: VReg, label: String },
+
+    Bge { rs1: VReg, rs2: VReg, label: String },
+
+    Bltu { rs1: VReg, rs2: VReg, label: String },
+
+    Bgeu { rs1: VReg, rs2: VReg, label: String },
+
+    // Compare against zero
+    Bnez { rs1: VReg, label: String },
+
+    Blez { rs1: VReg, label: String },
+
+    Bgez { rs1: VReg, label: String },
+
+    Bltz { rs1: VReg, label: String },
+
+    Bgtz { rs1: VReg, label: String },
+
     Ret { rd: Option<VReg> },
 
     Call { func: String },
 
-    Print { args: Vec<VReg> },
+    // Prints one integer through the runtime
+    Print { arg: VReg },
+
+    // Ends the current `print` line
+    PrintNewline,
     // TODO: Add more instructions
 }
 
 impl MachineInstr {
+    /// Transfers control to another function, which is free to overwrite
+    /// every caller-saved register
+    pub fn is_call(&self) -> bool {
+        matches!(
+            self,
+            MachineInstr::Jal { .. }
+                | MachineInstr::Call { .. }
+                | MachineInstr::Print { .. }
+                | MachineInstr::PrintNewline
+        )
+    }
+
     pub fn defs(&self) -> Vec<VReg> {
         match self {
             MachineInstr::Add { rd, .. }
@@ -170,8 +304,28 @@ impl MachineInstr {
             | MachineInstr::Mul { rd, .. }
             | MachineInstr::Sub { rd, .. }
             | MachineInstr::Div { rd, .. }
+            | MachineInstr::Xor { rd, .. }
+            | MachineInstr::Or { rd, .. }
+            | MachineInstr::And { rd, .. }
+            | MachineInstr::Xori { rd, .. }
+            | MachineInstr::Ori { rd, .. }
+            | MachineInstr::Andi { rd, .. }
+            | MachineInstr::Sll { rd, .. }
+            | MachineInstr::Srl { rd, .. }
+            | MachineInstr::Sra { rd, .. }
+            | MachineInstr::Slli { rd, .. }
+            | MachineInstr::Srli { rd, .. }
+            | MachineInstr::Srai { rd, .. }
+            | MachineInstr::Slt { rd, .. }
+            | MachineInstr::Slti { rd, .. }
+            | MachineInstr::Seqz { rd, .. }
+            | MachineInstr::Snez { rd, .. }
             | MachineInstr::Mv { rd, .. }
-            | MachineInstr::Li { rd, .. } => {
+            | MachineInstr::MvEqz { rd, .. }
+            | MachineInstr::Lw { rd, .. }
+            | MachineInstr::Ld { rd, .. }
+            | MachineInstr::Li { rd, .. }
+            | MachineInstr::La { rd, .. } => {
                 vec![*rd]
             }
             _ => Vec::new(),
@@ -184,18 +338,112 @@ impl MachineInstr {
             | MachineInstr::Mul { rs1, rs2, .. }
             | MachineInstr::Sub { rs1, rs2, .. }
             | MachineInstr::Beq { rs1, rs2, .. }
+            | MachineInstr::Bne { rs1, rs2, .. }
+            | MachineInstr::Blt { rs1, rs2, .. }
+            | MachineInstr::Bge { rs1, rs2, .. }
+            | MachineInstr::Bltu { rs1, rs2, .. }
+            | MachineInstr::Bgeu { rs1, rs2, .. }
+            | MachineInstr::Xor { rs1, rs2, .. }
+            | MachineInstr::Or { rs1, rs2, .. }
+            | MachineInstr::And { rs1, rs2, .. }
+            | MachineInstr::Sll { rs1, rs2, .. }
+            | MachineInstr::Srl { rs1, rs2, .. }
+            | MachineInstr::Sra { rs1, rs2, .. }
+            | MachineInstr::Slt { rs1, rs2, .. }
             | MachineInstr::Div { rs1, rs2, .. } => {
                 vec![*rs1, *rs2]
             }
 
             MachineInstr::Addi { rs1, .. }
+            | MachineInstr::Xori { rs1, .. }
+            | MachineInstr::Ori { rs1, .. }
+            | MachineInstr::Andi { rs1, .. }
+            | MachineInstr::Slli { rs1, .. }
+            | MachineInstr::Srli { rs1, .. }
+            | MachineInstr::Srai { rs1, .. }
+            | MachineInstr::Slti { rs1, .. }
+            | MachineInstr::Seqz { rs1, .. }
+            | MachineInstr::Snez { rs1, .. }
             | MachineInstr::Sw { rs1, .. }
+            | MachineInstr::Sd { rs1, .. }
+            | MachineInstr::Lw { base: rs1, .. }
+            | MachineInstr::Ld { base: rs1, .. }
             | MachineInstr::Beqz { rs1, .. }
+            | MachineInstr::Bnez { rs1, .. }
+            | MachineInstr::Blez { rs1, .. }
+            | MachineInstr::Bgez { rs1, .. }
+            | MachineInstr::Bltz { rs1, .. }
+            | MachineInstr::Bgtz { rs1, .. }
             | MachineInstr::Mv { rs1, .. } => {
                 vec![*rs1]
             }
 
+            // rd keeps its old value when cond is non-zero
+            MachineInstr::MvEqz { rd, cond, rs1 } => vec![*rd, *cond, *rs1],
+
+            MachineInstr::Ret { rd: Some(rd) } => vec![*rd],
+            MachineInstr::Print { arg } => vec![*arg],
+
             _ => Vec::new(),
         }
     }
+
+    /// Every register operand, used for renaming registers in place
+    pub fn regs_mut(&mut self) -> Vec<&mut VReg> {
+        match self {
+            MachineInstr::Add { rd, rs1, rs2 }
+            | MachineInstr::Mul { rd, rs1, rs2 }
+            | MachineInstr::Sub { rd, rs1, rs2 }
+            | MachineInstr::Div { rd, rs1, rs2 }
+            | MachineInstr::Xor { rd, rs1, rs2 }
+            | MachineInstr::Or { rd, rs1, rs2 }
+            | MachineInstr::And { rd, rs1, rs2 }
+            | MachineInstr::Sll { rd, rs1, rs2 }
+            | MachineInstr::Srl { rd, rs1, rs2 }
+            | MachineInstr::Sra { rd, rs1, rs2 }
+            | MachineInstr::Slt { rd, rs1, rs2 } => vec![rd, rs1, rs2],
+            MachineInstr::MvEqz { rd, cond, rs1 } => vec![rd, cond, rs1],
+
+            MachineInstr::Addi { rd, rs1, .. }
+            | MachineInstr::Xori { rd, rs1, .. }
+            | MachineInstr::Ori { rd, rs1, .. }
+            | MachineInstr::Andi { rd, rs1, .. }
+            | MachineInstr::Slli { rd, rs1, .. }
+            | MachineInstr::Srli { rd, rs1, .. }
+            | MachineInstr::Srai { rd, rs1, .. }
+            | MachineInstr::Slti { rd, rs1, .. }
+            | MachineInstr::Seqz { rd, rs1 }
+            | MachineInstr::Snez { rd, rs1 }
+            | MachineInstr::Mv { rd, rs1 } => vec![rd, rs1],
+
+            MachineInstr::Sw { rs1, base, .. } | MachineInstr::Sd { rs1, base, .. } => {
+                vec![rs1, base]
+            }
+            MachineInstr::Lw { rd, base, .. } | MachineInstr::Ld { rd, base, .. } => {
+                vec![rd, base]
+            }
+            MachineInstr::Beq { rs1, rs2, .. }
+            | MachineInstr::Bne { rs1, rs2, .. }
+            | MachineInstr::Blt { rs1, rs2, .. }
+            | MachineInstr::Bge { rs1, rs2, .. }
+            | MachineInstr::Bltu { rs1, rs2, .. }
+            | MachineInstr::Bgeu { rs1, rs2, .. } => vec![rs1, rs2],
+
+            MachineInstr::Li { rd, .. }
+            | MachineInstr::La { rd, .. }
+            | MachineInstr::Jal { rd, .. } => vec![rd],
+            MachineInstr::Beqz { rs1, .. }
+            | MachineInstr::Bnez { rs1, .. }
+            | MachineInstr::Blez { rs1, .. }
+            | MachineInstr::Bgez { rs1, .. }
+            | MachineInstr::Bltz { rs1, .. }
+            | MachineInstr::Bgtz { rs1, .. } => vec![rs1],
+            MachineInstr::Ret { rd } => rd.iter_mut().collect(),
+            MachineInstr::Print { arg } => vec![arg],
+
+            MachineInstr::Jmp { .. } | MachineInstr::Call { .. } | MachineInstr::PrintNewline => {
+                Vec::new()
+            }
+        }
+    }
 }
@@ -0,0 +1,83 @@
+use crate::machine_ir::{MachineFunc, MachineInstr};
+
+/// Local clean-ups over windows of adjacent instructions, meant to run once
+/// registers are allocated and copies may have ended up redundant
+///
+/// - `mv x, x` is dropped
+/// - `mv x, y; mv y, x` drops the second move, `y` already holds `x`
+/// - the second of two identical instructions is dropped, as long as it has
+///   no side effects and doesn't read what it writes
+/// - `addi rd, rs1, 0` becomes `mv rd, rs1`
+#[derive(Debug, Default)]
+pub struct PeepholePass {}
+
+impl PeepholePass {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Returns true when anything got simplified
+    pub fn run(&mut self, mf: &mut MachineFunc) -> bool {
+        let mut changed = false;
+
+        for block in mf.blocks.iter_mut() {
+            // every rewrite can expose a new pattern, keep going until stable
+            while simplify(&mut block.instrs) {
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+/// One sweep over `instrs`, returns true if anything changed
+fn simplify(instrs: &mut Vec<MachineInstr>) -> bool {
+    let mut changed = false;
+    let mut out: Vec<MachineInstr> = Vec::with_capacity(instrs.len());
+
+    for instr in std::mem::take(instrs) {
+        let instr = match instr {
+            MachineInstr::Addi { rd, rs1, imm: 0 } => {
+                changed = true;
+                MachineInstr::Mv { rd, rs1 }
+            }
+            other => other,
+        };
+
+        if let MachineInstr::Mv { rd, rs1 } = &instr
+            && rd == rs1
+        {
+            changed = true;
+            continue;
+        }
+
+        if let Some(prev) = out.last()
+            && redundant_after(prev, &instr)
+        {
+            changed = true;
+            continue;
+        }
+
+        out.push(instr);
+    }
+
+    *instrs = out;
+    changed
+}
+
+/// `instr` recomputes what `prev` just left in place
+fn redundant_after(prev: &MachineInstr, instr: &MachineInstr) -> bool {
+    if let (MachineInstr::Mv { rd: a, rs1: b }, MachineInstr::Mv { rd: c, rs1: d }) = (prev, instr)
+        && a == d
+        && b == c
+    {
+        return true;
+    }
+
+    let has_side_effects = instr.is_call();
+    let uses = instr.uses();
+    let reads_own_def = instr.defs().iter().any(|d| uses.contains(d));
+
+    prev == instr && !has_side_effects && !reads_own_def
+}
@@ -1,8 +1,11 @@
-use crate::machine_ir::{MachineFunc, VReg};
-use std::{cmp, collections::HashMap};
+use crate::machine_ir::{MachineFunc, MachineInstr, VReg};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+};
 
 /// So far we're going to use Linear Scan for doing register allocation.
-/// TODO: Implementing Graph coloring...somewhere in the near future
+/// `GraphColoringAlloc` (graph_color.rs) is the graph coloring alternative
 
 #[derive(Debug, Default, Clone)]
 pub struct Interval {
@@ -10,6 +13,8 @@ pub struct Interval {
     pub end: usize,
     pub phy_reg: Option<VReg>,
     pub mark_spilled: bool,
+    /// Live across a call, so only a callee-saved register keeps it intact
+    pub crosses_call: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -19,9 +24,11 @@ pub struct LiveIntervals {
     pub end: usize,
     pub phy_reg: Option<VReg>,
     pub mark_spilled: bool,
+    /// Live across a call, so only a callee-saved register keeps it intact
+    pub crosses_call: bool,
 }
 
-const ALL_REGS: &[VReg] = &[
+pub(crate) const ALL_REGS: &[VReg] = &[
     // Temp registers
     VReg::T0,
     VReg::T1,
@@ -60,6 +67,21 @@ const ALL_REGS: &[VReg] = &[
     //VReg::GP,
 ];
 
+/// s1-s11, s0 is saved separately as the frame pointer
+pub(crate) const CALLEE_SAVED: &[VReg] = &[
+    VReg::S1,
+    VReg::S2,
+    VReg::S3,
+    VReg::S4,
+    VReg::S5,
+    VReg::S6,
+    VReg::S7,
+    VReg::S8,
+    VReg::S9,
+    VReg::S10,
+    VReg::S11,
+];
+
 #[derive(Debug, Default)]
 pub struct LinearScan {
     pub live_intervals: HashMap<VReg, LiveIntervals>,
@@ -86,14 +108,48 @@ impl LinearScan {
         let mut intervals: HashMap<VReg, Interval> = HashMap::new();
 
         let mut instrs_global_pos = HashMap::new();
+        let mut call_positions = Vec::new();
         let mut instr_pos = 0;
         for (b_idx, block) in mf.blocks.iter().enumerate() {
-            for i in 0..block.instrs.len() {
+            for (i, instr) in block.instrs.iter().enumerate() {
                 instrs_global_pos.insert((b_idx, i), instr_pos);
+                if instr.is_call() {
+                    call_positions.push(instr_pos);
+                }
                 instr_pos += 1;
             }
         }
 
+        // Anything live across a block boundary has to cover that boundary,
+        // otherwise values flowing around loops would get their register reused
+        let (live_out, live_in) = compute_machine_liveness(mf);
+        let mut block_start = 0;
+        for (b_idx, block) in mf.blocks.iter().enumerate() {
+            if block.instrs.is_empty() {
+                continue;
+            }
+            let block_end = block_start + block.instrs.len() - 1;
+
+            for (vregs, pos) in [
+                (&live_in[b_idx], block_start),
+                (&live_out[b_idx], block_end),
+            ] {
+                for &v in vregs {
+                    let interval = intervals.entry(v).or_insert(Interval {
+                        start: pos,
+                        end: pos,
+                        mark_spilled: false,
+                        phy_reg: None,
+                        crosses_call: false,
+                    });
+                    interval.start = cmp::min(interval.start, pos);
+                    interval.end = cmp::max(interval.end, pos);
+                }
+            }
+
+            block_start = block_end + 1;
+        }
+
         for (b_idx, block) in mf.blocks.iter().enumerate() {
             for (i, instr) in block.instrs.iter().enumerate() {
                 let pos = instrs_global_pos.get(&(b_idx, i)).unwrap();
@@ -104,6 +160,7 @@ impl LinearScan {
                         end: *pos,
                         mark_spilled: false,
                         phy_reg: None,
+                        crosses_call: false,
                     });
 
                     interval.start = cmp::min(interval.start, *pos);
@@ -115,12 +172,25 @@ impl LinearScan {
                         end: *pos,
                         mark_spilled: false,
                         phy_reg: None,
+                        crosses_call: false,
                     });
 
                     interval.end = cmp::max(interval.end, *pos);
                 }
             }
         }
+
+        // x0 is never allocated, reading it doesn't keep anything alive
+        intervals.remove(&VReg::Zero);
+
+        // A value read as a call argument or written from its result ends or
+        // starts at the call, only the ones alive on both sides get clobbered
+        for (vreg, interval) in intervals.iter_mut() {
+            interval.crosses_call = matches!(vreg, VReg::Virtual(_))
+                && call_positions
+                    .iter()
+                    .any(|&pos| interval.start < pos && pos < interval.end);
+        }
         intervals
     }
 
@@ -137,6 +207,7 @@ impl LinearScan {
                 end: interval.end,
                 phy_reg: None,
                 mark_spilled: false,
+                crosses_call: interval.crosses_call,
             })
             .collect();
 
@@ -159,13 +230,17 @@ impl LinearScan {
             });
 
             // allocate or spill
-            if let Some(reg) = free_regs.pop() {
+            if let Some(reg) = take_free_reg(&mut free_regs, curr_iv.crosses_call) {
                 curr_iv.phy_reg = Some(reg);
                 active_alloc_intervals.push(curr_iv.clone());
 
                 // sort by increasing order for end of a interval
                 active_alloc_intervals.sort_by_key(|x| x.end);
             }
+            // Nothing the call leaves alone is free, keep it on the stack
+            else if curr_iv.crosses_call {
+                curr_iv.mark_spilled = true;
+            }
             // Spill if we can't get any Free Register (free_regs)
             else {
                 let mut worst = active_alloc_intervals.pop().unwrap();
@@ -191,3 +266,200 @@ impl LinearScan {
         live_intervals.into_iter().map(|iv| (iv.vreg, iv)).collect()
     }
 }
+
+/// Values that cross a call only get a callee-saved register. Everything else
+/// prefers a caller-saved one, leaving the callee-saved ones for those values
+fn take_free_reg(free_regs: &mut Vec<VReg>, crosses_call: bool) -> Option<VReg> {
+    let preferred = free_regs
+        .iter()
+        .rposition(|r| CALLEE_SAVED.contains(r) == crosses_call);
+    match preferred {
+        Some(i) => Some(free_regs.remove(i)),
+        None if crosses_call => None,
+        None => free_regs.pop(),
+    }
+}
+
+/// Rewrites `mf` so every register `LinearScan` marked as spilled lives in a
+/// stack slot instead. Each use reloads the value into a fresh temp right
+/// before the instruction, each def stores it back right after.
+///
+/// `slots` maps spilled registers to their offset from `sp`, new slots are
+/// appended to it. They start above the outgoing argument area. Returns true if anything got rewritten, the function then
+/// has to go through allocation again so the temps get registers.
+pub fn insert_spill_code(
+    mf: &mut MachineFunc,
+    intervals: &HashMap<VReg, LiveIntervals>,
+    slots: &mut HashMap<VReg, i32>,
+) -> bool {
+    let mut spilled: Vec<VReg> = intervals
+        .values()
+        .filter(|iv| iv.mark_spilled && matches!(iv.vreg, VReg::Virtual(_)))
+        .map(|iv| iv.vreg)
+        .collect();
+    if spilled.is_empty() {
+        return false;
+    }
+
+    spilled.sort_by_key(|v| match v {
+        VReg::Virtual(n) => *n,
+        _ => 0,
+    });
+    for v in spilled.iter() {
+        let next = (mf.outgoing_arg_bytes + slots.len() * 8) as i32;
+        slots.entry(*v).or_insert(next);
+    }
+
+    let mut next_vreg = mf
+        .blocks
+        .iter()
+        .flat_map(|b| b.instrs.iter())
+        .flat_map(|i| i.defs().into_iter().chain(i.uses()))
+        .filter_map(|v| match v {
+            VReg::Virtual(n) => Some(n + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+
+    for block in mf.blocks.iter_mut() {
+        let mut rewritten = Vec::with_capacity(block.instrs.len());
+
+        for mut instr in std::mem::take(&mut block.instrs) {
+            let uses = instr.uses();
+            let defs = instr.defs();
+            let mut after = Vec::new();
+
+            for &v in spilled.iter() {
+                let is_used = uses.contains(&v);
+                let is_defined = defs.contains(&v);
+                if !is_used && !is_defined {
+                    continue;
+                }
+
+                let tmp = VReg::Virtual(next_vreg);
+                next_vreg += 1;
+                let offset = slots[&v];
+
+                for reg in instr.regs_mut() {
+                    if *reg == v {
+                        *reg = tmp;
+                    }
+                }
+
+                if is_used {
+                    rewritten.push(MachineInstr::Ld {
+                        rd: tmp,
+                        offset,
+                        base: VReg::SP,
+                    });
+                }
+                if is_defined {
+                    after.push(MachineInstr::Sd {
+                        rs1: tmp,
+                        offset,
+                        base: VReg::SP,
+                    });
+                }
+            }
+
+            rewritten.push(instr);
+            rewritten.extend(after);
+        }
+
+        block.instrs = rewritten;
+    }
+
+    true
+}
+
+/// Builds the interference graph of `mf`. Two registers interfere when one of
+/// them gets defined while the other is still live. Physical registers are
+/// nodes too, so calling convention constraints show up in the graph.
+///
+/// The source and dest of a `mv` don't interfere through the move itself,
+/// which leaves them free to be coalesced.
+pub fn build_interference_graph(mf: &MachineFunc) -> HashMap<VReg, HashSet<VReg>> {
+    let mut graph: HashMap<VReg, HashSet<VReg>> = HashMap::new();
+    let (live_out, _) = compute_machine_liveness(mf);
+
+    for (b, block) in mf.blocks.iter().enumerate() {
+        let mut live = live_out[b].clone();
+
+        for instr in block.instrs.iter().rev() {
+            let defs = instr.defs();
+            let uses = instr.uses();
+            for &v in defs.iter().chain(uses.iter()) {
+                graph.entry(v).or_default();
+            }
+
+            if let MachineInstr::Mv { rs1, .. } = instr {
+                live.remove(rs1);
+            }
+
+            live.extend(defs.iter().copied());
+            for &d in &defs {
+                for &l in &live {
+                    if l != d {
+                        graph.entry(d).or_default().insert(l);
+                        graph.entry(l).or_default().insert(d);
+                    }
+                }
+            }
+
+            for d in &defs {
+                live.remove(d);
+            }
+            live.extend(uses);
+        }
+    }
+
+    graph
+}
+
+/// Same fix-point as `passes::liveness::compute_liveness`, but over the
+/// machine CFG. Returns the (live-out, live-in) sets of every block.
+pub fn compute_machine_liveness(mf: &MachineFunc) -> (Vec<HashSet<VReg>>, Vec<HashSet<VReg>>) {
+    let n = mf.blocks.len();
+    let mut uses: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    let mut defs: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+
+    for (b, block) in mf.blocks.iter().enumerate() {
+        for instr in block.instrs.iter() {
+            for u in instr.uses() {
+                if !defs[b].contains(&u) {
+                    uses[b].insert(u);
+                }
+            }
+            defs[b].extend(instr.defs());
+        }
+    }
+
+    let mut live_in: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    loop {
+        let mut changed = false;
+
+        for b in (0..n).rev() {
+            let mut out = HashSet::new();
+            for &s in &mf.blocks[b].succs {
+                out.extend(live_in[s].iter().copied());
+            }
+
+            let mut inn = uses[b].clone();
+            inn.extend(out.difference(&defs[b]).copied());
+
+            if out != live_out[b] || inn != live_in[b] {
+                live_out[b] = out;
+                live_in[b] = inn;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    (live_out, live_in)
+}
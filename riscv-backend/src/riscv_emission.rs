@@ -1,7 +1,11 @@
-use crate::machine_ir::*;
-use crate::register_alloc::{LinearScan, LiveIntervals};
 use crate::VReg;
+use crate::machine_ir::*;
+use crate::peephole::PeepholePass;
+use crate::register_alloc::{CALLEE_SAVED, LinearScan, LiveIntervals, insert_spill_code};
+use ir::IrModule;
+use ir::cfg::Literal;
 use std::collections::HashMap;
+use std::io::{self, Write};
 
 // In case we manual added a register into our system, that hasn't been
 // processed through our live intervals, then we'll nudge the compiler to know
@@ -10,7 +14,8 @@ use std::collections::HashMap;
 // For things like calling conventions, this will be really useful
 fn to_phys(v: VReg, map: &HashMap<VReg, LiveIntervals>) -> VReg {
     match v {
-        VReg::A0
+        VReg::Zero
+        | VReg::A0
         | VReg::A1
         | VReg::A2
         | VReg::A3
@@ -28,48 +33,114 @@ fn to_phys(v: VReg, map: &HashMap<VReg, LiveIntervals>) -> VReg {
     }
 }
 
-pub fn emit_riscv(module: &[MachineFunc]) {
+pub fn emit_riscv<W: Write>(module: &[MachineFunc], out: &mut W) -> io::Result<()> {
+    let mut module = module.to_vec();
     let mut allocator = LinearScan::new();
-    let func_by_intervals = allocator.run(module);
+    let mut spill_slots: HashMap<String, HashMap<VReg, i32>> = HashMap::new();
 
-    println!(".section .text");
-    println!(".p2align 2"); // align to 4-byte boundary
+    // Spilled registers get rewritten into loads/stores around fresh temps,
+    // which need registers of their own, so allocate again until nothing spills
+    let func_by_intervals = loop {
+        let func_by_intervals = allocator.run(&module);
+
+        let mut spilled = false;
+        for func in module.iter_mut() {
+            let slots = spill_slots.entry(func.name.clone()).or_default();
+            spilled |= insert_spill_code(func, &func_by_intervals[&func.name], slots);
+        }
+
+        if !spilled {
+            break func_by_intervals;
+        }
+    };
+
+    // Registers are final now, write them into the instructions so the
+    // peephole pass gets to see the copies allocation made redundant
+    let mut peephole = PeepholePass::new();
+    for func in module.iter_mut() {
+        let live_intervals = &func_by_intervals[&func.name];
+        for instr in func.blocks.iter_mut().flat_map(|b| b.instrs.iter_mut()) {
+            for reg in instr.regs_mut() {
+                if let VReg::Virtual(_) = reg {
+                    *reg = to_phys(*reg, live_intervals);
+                }
+            }
+        }
+        peephole.run(func);
+    }
+
+    writeln!(out, ".section .text")?;
+    writeln!(out, ".p2align 2")?; // align to 4-byte boundary
 
     for func in module.iter() {
-        println!(".globl {}", func.name);
+        writeln!(out, ".globl {}", func.name)?;
     }
 
     for func in module.iter() {
-        let mut spill_slots = HashMap::<VReg, usize>::new();
-        let mut stack_frame: usize = 0;
         let live_intervals = &func_by_intervals.get(&func.name).unwrap();
-        for (&vreg, ivs) in live_intervals.iter() {
-            if ivs.mark_spilled {
-                spill_slots.insert(vreg, stack_frame);
-                stack_frame += 8;
-            }
-        }
+
+        // callee-saved registers the allocator handed out, the caller expects
+        // them untouched once we return
+        let saved_regs: Vec<VReg> = CALLEE_SAVED
+            .iter()
+            .copied()
+            .filter(|r| {
+                live_intervals
+                    .values()
+                    .any(|iv| matches!(iv.vreg, VReg::Virtual(_)) && iv.phy_reg == Some(*r))
+            })
+            .collect();
+
+        // Frame layout, from sp upwards:
+        //   outgoing stack args | spill slots | callee-saved registers | s0 | ra
+        let slot_bytes = spill_slots.get(&func.name).map_or(0, |s| s.len() * 8);
+        // where the callee-saved registers start
+        let saved_base = func.outgoing_arg_bytes + slot_bytes;
+        let saved_bytes = saved_regs.len() * 8;
+        // s0 is needed to reach arguments passed on the stack, and any call
+        // overwrites ra. The ABI wants sp 16-byte aligned, the padding ends up
+        // between the callee-saved registers and s0
+        let stack_args = func.args.len() > 8;
+        let makes_calls = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.instrs.iter())
+            .any(|i| i.is_call());
+        let stack_frame: usize = if saved_base + saved_bytes > 0 || stack_args || makes_calls {
+            (saved_base + saved_bytes + 16).next_multiple_of(16)
+        } else {
+            0
+        };
 
         // Prologue
-        println!("\n{}:", func.name); // function label
+        writeln!(out, "\n{}:", func.name)?; // function label
         if stack_frame > 0 {
-            println!("  addi sp, sp, -{}", stack_frame);
+            writeln!(out, "  addi sp, sp, -{}", stack_frame)?;
             // save ra = return address
-            println!("  sd ra, {}(sp)", stack_frame - 8);
+            writeln!(out, "  sd ra, {}(sp)", stack_frame - 8)?;
             // save frame pointer
-            println!("  sd s0, {}(sp)", stack_frame - 16);
-            println!("  mv s0, sp");
+            writeln!(out, "  sd s0, {}(sp)", stack_frame - 16)?;
+            for (i, reg) in saved_regs.iter().enumerate() {
+                writeln!(out, "  sd {}, {}(sp)", reg.name(), saved_base + i * 8)?;
+            }
+            // s0 points at the caller's sp, where stack arguments start
+            writeln!(out, "  addi s0, sp, {}", stack_frame)?;
         }
 
         for block in func.blocks.iter() {
-            println!("  .{}:", block.name);
+            writeln!(out, "  .{}:", block.name)?;
 
             for instr in block.instrs.iter() {
                 // TODO: Add more instructions
                 match instr {
                     MachineInstr::Li { rd, imm } => {
                         let phy_reg = to_phys(*rd, live_intervals);
-                        println!("  li {}, {}", phy_reg.name(), imm);
+                        writeln!(out, "  li {}, {}", phy_reg.name(), imm)?;
+                    }
+
+                    MachineInstr::La { rd, label } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        writeln!(out, "  la {}, {}", phy_reg.name(), label)?;
                     }
 
                     MachineInstr::Add { rd, rs1, rs2 } => {
@@ -77,7 +148,13 @@ pub fn emit_riscv(module: &[MachineFunc]) {
                         let prs1 = to_phys(*rs1, live_intervals);
                         let prs2 = to_phys(*rs2, live_intervals);
 
-                        println!("  add {}, {}, {}", phy_reg.name(), prs1.name(), prs2.name());
+                        writeln!(
+                            out,
+                            "  add {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
                     }
 
                     MachineInstr::Mul { rd, rs1, rs2 } => {
@@ -85,7 +162,13 @@ pub fn emit_riscv(module: &[MachineFunc]) {
                         let prs1 = to_phys(*rs1, live_intervals);
                         let prs2 = to_phys(*rs2, live_intervals);
 
-                        println!("  mul {}, {}, {}", phy_reg.name(), prs1.name(), prs2.name());
+                        writeln!(
+                            out,
+                            "  mul {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
                     }
 
                     MachineInstr::Sub { rd, rs1, rs2 } => {
@@ -93,7 +176,13 @@ pub fn emit_riscv(module: &[MachineFunc]) {
                         let prs1 = to_phys(*rs1, live_intervals);
                         let prs2 = to_phys(*rs2, live_intervals);
 
-                        println!("  sub {}, {}, {}", phy_reg.name(), prs1.name(), prs2.name());
+                        writeln!(
+                            out,
+                            "  sub {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
                     }
 
                     MachineInstr::Div { rd, rs1, rs2 } => {
@@ -101,61 +190,429 @@ pub fn emit_riscv(module: &[MachineFunc]) {
                         let prs1 = to_phys(*rs1, live_intervals);
                         let prs2 = to_phys(*rs2, live_intervals);
 
-                        println!("  div {}, {}, {}", phy_reg.name(), prs1.name(), prs2.name());
+                        writeln!(
+                            out,
+                            "  div {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Xor { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  xor {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Or { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  or {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::And { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  and {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Xori { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  xori {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Ori { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  ori {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Andi { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  andi {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Sll { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  sll {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Srl { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  srl {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Sra { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  sra {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Slli { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  slli {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Srli { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  srli {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Srai { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  srai {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Slt { rd, rs1, rs2 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  slt {}, {}, {}",
+                            phy_reg.name(),
+                            prs1.name(),
+                            prs2.name()
+                        )?;
+                    }
+
+                    MachineInstr::Slti { rd, rs1, imm } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  slti {}, {}, {}", phy_reg.name(), prs1.name(), imm)?;
+                    }
+
+                    MachineInstr::Seqz { rd, rs1 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  seqz {}, {}", phy_reg.name(), prs1.name())?;
+                    }
+
+                    MachineInstr::Snez { rd, rs1 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  snez {}, {}", phy_reg.name(), prs1.name())?;
                     }
 
                     MachineInstr::Mv { rd, rs1 } => {
                         let phy_reg = to_phys(*rd, live_intervals);
                         let prs1 = to_phys(*rs1, live_intervals);
 
-                        println!("  mv {}, {}", phy_reg.name(), prs1.name());
+                        writeln!(out, "  mv {}, {}", phy_reg.name(), prs1.name())?;
+                    }
+
+                    // numeric local label, `1f` is the next `1:` below
+                    MachineInstr::MvEqz { rd, cond, rs1 } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let pcond = to_phys(*cond, live_intervals);
+                        let prs1 = to_phys(*rs1, live_intervals);
+
+                        writeln!(out, "  bnez {}, 1f", pcond.name())?;
+                        writeln!(out, "  mv {}, {}", phy_reg.name(), prs1.name())?;
+                        writeln!(out, "1:")?;
                     }
 
                     MachineInstr::Sw { rs1, offset, base } => {
                         let rs = to_phys(*rs1, live_intervals);
                         let base_val = to_phys(*base, live_intervals);
 
-                        println!("  sw {}, {}({})", rs.name(), offset, base_val.name());
+                        writeln!(out, "  sw {}, {}({})", rs.name(), offset, base_val.name())?;
+                    }
+
+                    MachineInstr::Sd { rs1, offset, base } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        let base_val = to_phys(*base, live_intervals);
+
+                        writeln!(out, "  sd {}, {}({})", rs.name(), offset, base_val.name())?;
+                    }
+
+                    MachineInstr::Lw { rd, offset, base } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let base_val = to_phys(*base, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  lw {}, {}({})",
+                            phy_reg.name(),
+                            offset,
+                            base_val.name()
+                        )?;
+                    }
+
+                    MachineInstr::Ld { rd, offset, base } => {
+                        let phy_reg = to_phys(*rd, live_intervals);
+                        let base_val = to_phys(*base, live_intervals);
+
+                        writeln!(
+                            out,
+                            "  ld {}, {}({})",
+                            phy_reg.name(),
+                            offset,
+                            base_val.name()
+                        )?;
                     }
 
                     MachineInstr::Call { func } => {
-                        println!("  call {}", func);
+                        writeln!(out, "  call {}", func)?;
                     }
 
                     MachineInstr::Jmp { label } => {
-                        println!("  j {}", label);
+                        writeln!(out, "  j {}", label)?;
                     }
 
                     MachineInstr::Jal { rd, label } => {
-                        println!("  jal {}, {}", to_phys(*rd, live_intervals).name(), label);
+                        writeln!(
+                            out,
+                            "  jal {}, {}",
+                            to_phys(*rd, live_intervals).name(),
+                            label
+                        )?;
                     }
 
                     MachineInstr::Beqz { rs1, label } => {
-                        //println!("{:#?}", rs1);
+                        //writeln!(out, "{:#?}", rs1)?;
                         let rs = to_phys(*rs1, live_intervals);
-                        println!("  beqz {}, {}", rs.name(), label);
+                        writeln!(out, "  beqz {}, {}", rs.name(), label)?;
+                    }
+
+                    MachineInstr::Beq { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  beq {}, {}, {}", prs1.name(), prs2.name(), label)?;
+                    }
+
+                    MachineInstr::Bne { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  bne {}, {}, {}", prs1.name(), prs2.name(), label)?;
+                    }
+
+                    MachineInstr::Blt { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  blt {}, {}, {}", prs1.name(), prs2.name(), label)?;
+                    }
+
+                    MachineInstr::Bge { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  bge {}, {}, {}", prs1.name(), prs2.name(), label)?;
+                    }
+
+                    MachineInstr::Bltu { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  bltu {}, {}, {}", prs1.name(), prs2.name(), label)?;
                     }
 
+                    MachineInstr::Bgeu { rs1, rs2, label } => {
+                        let prs1 = to_phys(*rs1, live_intervals);
+                        let prs2 = to_phys(*rs2, live_intervals);
+                        writeln!(out, "  bgeu {}, {}, {}", prs1.name(), prs2.name(), label)?;
+                    }
+
+                    MachineInstr::Bnez { rs1, label } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        writeln!(out, "  bnez {}, {}", rs.name(), label)?;
+                    }
+
+                    MachineInstr::Blez { rs1, label } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        writeln!(out, "  blez {}, {}", rs.name(), label)?;
+                    }
+
+                    MachineInstr::Bgez { rs1, label } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        writeln!(out, "  bgez {}, {}", rs.name(), label)?;
+                    }
+
+                    MachineInstr::Bltz { rs1, label } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        writeln!(out, "  bltz {}, {}", rs.name(), label)?;
+                    }
+
+                    MachineInstr::Bgtz { rs1, label } => {
+                        let rs = to_phys(*rs1, live_intervals);
+                        writeln!(out, "  bgtz {}, {}", rs.name(), label)?;
+                    }
+
+                    // every value goes through the runtime
+                    MachineInstr::Print { arg } => {
+                        let reg = to_phys(*arg, live_intervals);
+                        writeln!(out, "  mv a0, {}", reg.name())?;
+                        writeln!(out, "  call __choco_print_int")?;
+                    }
+
+                    // the newline gets written straight to stdout
+                    MachineInstr::PrintNewline => emit_newline(out)?,
+
+                    // `ret` takes no operand, the value goes back through a0.
+                    // Move it before the epilogue, which may restore its register
                     MachineInstr::Ret { rd } => {
                         if let Some(r) = rd {
                             let phy_reg = to_phys(*r, live_intervals);
-                            println!("  ret {}", phy_reg.name());
-                        } else {
-                            println!("  ret");
+                            if phy_reg != VReg::A0 {
+                                writeln!(out, "  mv a0, {}", phy_reg.name())?;
+                            }
+                        }
+
+                        if stack_frame > 0 {
+                            emit_epilogue(out, stack_frame, saved_base, &saved_regs)?;
                         }
+
+                        writeln!(out, "  ret")?;
                     }
 
                     _ => {}
                 }
             }
         }
+    }
 
-        if stack_frame > 0 {
-            // save ra = return address
-            println!("  ld s0, {}(sp)", stack_frame - 16);
-            println!("  ld ra, {}(sp)", stack_frame - 8);
-            // save frame pointer
-            println!("  addi sp, sp, {}", stack_frame);
+    let strings: Vec<&(String, String)> = module.iter().flat_map(|f| f.strings.iter()).collect();
+    if !strings.is_empty() {
+        writeln!(out, "\n.section .rodata")?;
+        for (label, s) in strings {
+            writeln!(out, "{}: .asciz \"{}\"", label, escape_asciz(s))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the module's globals into `.rodata`, nothing at all if it has none
+pub fn emit_data_section<W: Write>(module: &IrModule, out: &mut W) -> io::Result<()> {
+    if module.globals.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out, "\n.section .rodata")?;
+    for global in module.globals.iter() {
+        writeln!(out, ".p2align 3")?; // align to 8-byte boundary
+        match &global.value {
+            Literal::Int(i) => writeln!(out, "{}: .dword {}", global.name, i)?,
+            Literal::Bool(b) => writeln!(out, "{}: .dword {}", global.name, *b as i64)?,
+            Literal::Str(s) => writeln!(out, "{}: .asciz \"{}\"", global.name, escape_asciz(s))?,
+            #[cfg(feature = "float")]
+            Literal::Float(x) => writeln!(out, "{}: .double {:?}", global.name, x)?,
         }
     }
+
+    Ok(())
+}
+
+/// Escapes `s` for a `.asciz` directive, bytes outside printable ascii are
+/// written as octal escapes
+fn escape_asciz(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+/// write(1, "\n", 1) through the Linux syscall, the byte lives on the stack
+fn emit_newline<W: Write>(out: &mut W) -> io::Result<()> {
+    writeln!(out, "  addi sp, sp, -16")?;
+    writeln!(out, "  li t0, 10")?;
+    writeln!(out, "  sb t0, 0(sp)")?;
+    writeln!(out, "  li a0, 1")?;
+    writeln!(out, "  mv a1, sp")?;
+    writeln!(out, "  li a2, 1")?;
+    writeln!(out, "  li a7, 64")?;
+    writeln!(out, "  ecall")?;
+    writeln!(out, "  addi sp, sp, 16")?;
+
+    Ok(())
+}
+
+/// Restores everything the prologue saved, has to run right before every `ret`
+fn emit_epilogue<W: Write>(
+    out: &mut W,
+    stack_frame: usize,
+    saved_base: usize,
+    saved_regs: &[VReg],
+) -> io::Result<()> {
+    for (i, reg) in saved_regs.iter().enumerate() {
+        writeln!(out, "  ld {}, {}(sp)", reg.name(), saved_base + i * 8)?;
+    }
+    // restore frame pointer & return address
+    writeln!(out, "  ld s0, {}(sp)", stack_frame - 16)?;
+    writeln!(out, "  ld ra, {}(sp)", stack_frame - 8)?;
+    writeln!(out, "  addi sp, sp, {}", stack_frame)?;
+
+    Ok(())
 }